@@ -1,4 +1,4 @@
-use std::io::{Read, Write};
+use std::io::{BufReader, Read, Write};
 
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub enum Instruction {
@@ -13,17 +13,25 @@ pub enum Instruction {
     Break,
 }
 
+/// What `,` does to the current cell once the input has run out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Eof {
+    Unchanged,
+    #[default]
+    Zero,
+    MinusOne,
+}
+
+#[derive(Default)]
 pub struct BF {
     code: Vec<Instruction>,
+    origins: Vec<Option<usize>>,
     pub input: Vec<u8>,
 }
 
 impl BF {
     pub fn new() -> Self {
-        Self {
-            code: Vec::new(),
-            input: Vec::new(),
-        }
+        Self::default()
     }
 
     pub fn parse(stream: impl Read) -> std::io::Result<Self> {
@@ -31,7 +39,7 @@ impl BF {
         let mut input = Vec::new();
         let mut is_input = false;
 
-        for char in stream.bytes() {
+        for char in BufReader::new(stream).bytes() {
             if is_input {
                 input.push(char?);
                 continue;
@@ -60,11 +68,34 @@ impl BF {
             }
         }
 
-        Ok(Self { code, input })
+        Ok(Self {
+            origins: vec![None; code.len()],
+            code,
+            input,
+        })
     }
 
     pub fn push(&mut self, instruction: Instruction) {
+        self.push_from(instruction, None);
+    }
+
+    /// Push an instruction, recording the source map origin it was generated from.
+    pub fn push_from(&mut self, instruction: Instruction, origin: Option<usize>) {
         self.code.push(instruction);
+        self.origins.push(origin);
+    }
+
+    pub fn origins(&self) -> &[Option<usize>] {
+        &self.origins
+    }
+
+    pub fn origin(&self, pc: usize) -> Option<usize> {
+        self.origins.get(pc).copied().flatten()
+    }
+
+    pub fn set_origins(&mut self, origins: Vec<Option<usize>>) {
+        assert_eq!(origins.len(), self.code.len());
+        self.origins = origins;
     }
 
     pub fn code(&self) -> &[Instruction] {
@@ -75,6 +106,10 @@ impl BF {
         self.code.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.code.is_empty()
+    }
+
     pub fn write(&self, mut output: impl Write) -> std::io::Result<()> {
         let mut column = 0;
         for instruction in &self.code {
@@ -92,12 +127,12 @@ impl BF {
 
             column += 1;
             if column >= 80 {
-                write!(output, "\n")?;
+                writeln!(output)?;
                 column = 0;
             }
         }
 
-        write!(output, "\n")?;
+        writeln!(output)?;
         Ok(())
     }
 }
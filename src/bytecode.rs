@@ -0,0 +1,438 @@
+use crate::bf::BF;
+use crate::interpreter::{read_byte, Config, RunStats, RuntimeError, Step};
+use crate::ir::{Ir, Node, NodeKind};
+use crate::simplify::standard_passes;
+use std::io::{Read, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    /// Add to the cell at `offset` from the pointer.
+    Add {
+        offset: isize,
+        amount: i64,
+    },
+
+    /// Set the cell at `offset` from the pointer, wrapping `value` to the cell width.
+    Set {
+        offset: isize,
+        value: i64,
+    },
+
+    Move(isize),
+    Input,
+    Output,
+
+    /// Jump past the matching `JumpIfNotZero` if the cell is zero.
+    JumpIfZero(usize),
+
+    /// Jump back past the matching `JumpIfZero` if the cell isn't zero.
+    JumpIfNotZero(usize),
+
+    /// Add the cell multiplied by `factor` to the cell at `offset`, as part of a loop like `[->++<]`.
+    Multiply {
+        offset: isize,
+        factor: i64,
+    },
+
+    /// Move by `step` until reaching a zero cell, as in `[>]`.
+    Scan(isize),
+
+    Break,
+}
+
+pub struct Bytecode {
+    ops: Vec<Op>,
+
+    /// The bf instruction each op starts at, for reporting errors.
+    pcs: Vec<usize>,
+}
+
+impl Bytecode {
+    pub fn compile(program: &BF, config: &Config) -> Result<Self, RuntimeError> {
+        let mut ir = Ir::from_bf(program)?;
+        standard_passes().run(&mut ir, config);
+        Ok(Self::lower(&ir))
+    }
+
+    /// Flatten an already optimised program into ops.
+    pub fn lower(ir: &Ir) -> Self {
+        let mut bytecode = Self {
+            ops: Vec::new(),
+            pcs: Vec::new(),
+        };
+        bytecode.nodes(&ir.nodes);
+        bytecode
+    }
+
+    fn nodes(&mut self, nodes: &[Node]) {
+        for node in nodes {
+            let pc = node.source.pc;
+            match &node.kind {
+                NodeKind::Add { offset, amount } => self.push(
+                    Op::Add {
+                        offset: *offset,
+                        amount: *amount,
+                    },
+                    pc,
+                ),
+                NodeKind::Set { offset, value } => self.push(
+                    Op::Set {
+                        offset: *offset,
+                        value: *value,
+                    },
+                    pc,
+                ),
+                NodeKind::Move(distance) => self.push(Op::Move(*distance), pc),
+                NodeKind::Input => self.push(Op::Input, pc),
+                NodeKind::Output => self.push(Op::Output, pc),
+                NodeKind::Break => self.push(Op::Break, pc),
+
+                NodeKind::Multiply(targets) => {
+                    for (offset, factor) in targets {
+                        self.push(
+                            Op::Multiply {
+                                offset: *offset,
+                                factor: *factor,
+                            },
+                            pc,
+                        );
+                    }
+                    self.push(
+                        Op::Set {
+                            offset: 0,
+                            value: 0,
+                        },
+                        pc,
+                    );
+                }
+                NodeKind::Scan(step) => self.push(Op::Scan(*step), pc),
+
+                NodeKind::Loop { body, end, .. } => {
+                    let open = self.ops.len();
+                    self.push(Op::JumpIfZero(0), pc);
+                    self.nodes(body);
+                    let close = self.ops.len();
+                    self.ops[open] = Op::JumpIfZero(close + 1);
+                    self.push(Op::JumpIfNotZero(open + 1), end.pc);
+                }
+            }
+        }
+    }
+
+    fn push(&mut self, op: Op, pc: usize) {
+        self.ops.push(op);
+        self.pcs.push(pc);
+    }
+
+    pub fn ops(&self) -> &[Op] {
+        &self.ops
+    }
+
+    /// The bf instruction that op `index` was compiled from.
+    pub fn pc(&self, index: usize) -> usize {
+        self.pcs[index]
+    }
+}
+
+/// Runs bytecode, counting each op as a single step.
+pub struct Vm<'a> {
+    bytecode: &'a Bytecode,
+    config: Config,
+    memory: Vec<u64>,
+    pointer: usize,
+    index: usize,
+    steps: u64,
+    input: Box<dyn Read + 'a>,
+    output: Box<dyn Write + 'a>,
+}
+
+impl<'a> Vm<'a> {
+    /// A vm with no input, that discards its output.
+    pub fn new(bytecode: &'a Bytecode, config: Config) -> Self {
+        Self {
+            bytecode,
+            config,
+            memory: vec![0; 1],
+            pointer: 0,
+            index: 0,
+            steps: 0,
+            input: Box::new(std::io::empty()),
+            output: Box::new(std::io::sink()),
+        }
+    }
+
+    pub fn with_input(mut self, input: impl Read + 'a) -> Self {
+        self.input = Box::new(input);
+        self
+    }
+
+    pub fn with_output(mut self, output: impl Write + 'a) -> Self {
+        self.output = Box::new(output);
+        self
+    }
+
+    pub fn pointer(&self) -> usize {
+        self.pointer
+    }
+
+    pub fn memory(&self) -> &[u64] {
+        &self.memory
+    }
+
+    pub fn stats(&self) -> RunStats {
+        RunStats {
+            steps: self.steps,
+            cells: self.memory.len(),
+        }
+    }
+
+    fn pc(&self) -> usize {
+        self.bytecode.pc(self.index)
+    }
+
+    fn io_error(&self, err: std::io::Error) -> RuntimeError {
+        RuntimeError::Io {
+            pc: self.pc(),
+            kind: err.kind(),
+        }
+    }
+
+    fn offset(&mut self, offset: isize) -> Result<usize, RuntimeError> {
+        let pointer = self
+            .pointer
+            .checked_add_signed(offset)
+            .ok_or(RuntimeError::PointerUnderflow { pc: self.pc() })?;
+        if self.config.memory_size.is_some_and(|size| pointer >= size) {
+            return Err(RuntimeError::PointerOverflow { pc: self.pc() });
+        }
+        if pointer >= self.memory.len() {
+            self.memory.resize(pointer + 1, 0);
+        }
+        Ok(pointer)
+    }
+
+    /// Run until the program halts or reaches a `*` breakpoint.
+    pub fn run(&mut self) -> Result<Step, RuntimeError> {
+        let ops = self.bytecode.ops();
+        let max = self.config.cell_width.max();
+        while self.index < ops.len() {
+            if let Some(limit) = self.config.step_limit {
+                if self.steps >= limit {
+                    return Err(RuntimeError::StepLimitExceeded { limit });
+                }
+            }
+            self.steps += 1;
+
+            match ops[self.index] {
+                Op::Add { offset, amount } => {
+                    let pointer = self.offset(offset)?;
+                    let cell = self.memory[pointer];
+                    self.memory[pointer] =
+                        self.config
+                            .add(cell, amount)
+                            .ok_or(RuntimeError::CellOverflow {
+                                pc: self.pc(),
+                                pointer,
+                            })?;
+                }
+                Op::Set { offset, value } => {
+                    let pointer = self.offset(offset)?;
+                    self.memory[pointer] = value as u64 & max;
+                }
+                Op::Move(distance) => self.pointer = self.offset(distance)?,
+
+                Op::Input => {
+                    self.output.flush().map_err(|err| self.io_error(err))?;
+                    let cell = self.memory[self.pointer];
+                    self.memory[self.pointer] = match read_byte(&mut self.input) {
+                        Ok(Some(byte)) => byte as u64,
+                        Ok(None) => self.config.end_of_input(cell),
+                        Err(err) => return Err(self.io_error(err)),
+                    };
+                }
+                Op::Output => {
+                    let byte = self.memory[self.pointer] as u8;
+                    self.output
+                        .write_all(&[byte])
+                        .map_err(|err| self.io_error(err))?;
+                }
+
+                Op::JumpIfZero(target) => {
+                    if self.memory[self.pointer] == 0 {
+                        self.index = target;
+                        continue;
+                    }
+                }
+                Op::JumpIfNotZero(target) => {
+                    if self.memory[self.pointer] != 0 {
+                        self.index = target;
+                        continue;
+                    }
+                }
+
+                Op::Multiply { offset, factor } => {
+                    let value = self.memory[self.pointer];
+                    if value != 0 {
+                        let target = self.offset(offset)?;
+                        let product = value.wrapping_mul(factor as u64);
+                        self.memory[target] = self.memory[target].wrapping_add(product) & max;
+                    }
+                }
+                Op::Scan(step) => {
+                    while self.memory[self.pointer] != 0 {
+                        self.pointer = self.offset(step)?;
+                    }
+                }
+
+                Op::Break => {
+                    self.index += 1;
+                    return Ok(Step::Break);
+                }
+            }
+
+            self.index += 1;
+        }
+
+        self.output.flush().map_err(|err| self.io_error(err))?;
+        Ok(Step::Halted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::{run_program, Overflow};
+
+    fn compile(source: &str, config: &Config) -> Bytecode {
+        let program = BF::parse(source.as_bytes()).unwrap();
+        Bytecode::compile(&program, config).unwrap()
+    }
+
+    fn run(
+        source: &str,
+        config: Config,
+        input: &[u8],
+    ) -> Result<(RunStats, Vec<u8>), RuntimeError> {
+        let program = BF::parse(source.as_bytes()).unwrap();
+        let mut output = Vec::new();
+        let stats = run_program(&program, config, input, &mut output)?;
+        Ok((stats, output))
+    }
+
+    #[test]
+    fn runs_are_folded_into_single_ops() {
+        let bytecode = compile(",+++>>--<.", &Config::default());
+        assert_eq!(
+            bytecode.ops(),
+            [
+                Op::Input,
+                Op::Add {
+                    offset: 0,
+                    amount: 3
+                },
+                Op::Add {
+                    offset: 2,
+                    amount: -2
+                },
+                Op::Move(1),
+                Op::Output,
+            ]
+        );
+    }
+
+    #[test]
+    fn loops_jump_past_each_other() {
+        let bytecode = compile(",[>,].", &Config::default());
+        assert_eq!(
+            bytecode.ops(),
+            [
+                Op::Input,
+                Op::JumpIfZero(5),
+                Op::Move(1),
+                Op::Input,
+                Op::JumpIfNotZero(2),
+                Op::Output,
+            ]
+        );
+
+        // Each op points back at the bf instruction it starts at.
+        let pcs = (0..bytecode.ops().len())
+            .map(|index| bytecode.pc(index))
+            .collect::<Vec<_>>();
+        assert_eq!(pcs, [0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn loop_idioms_become_single_ops() {
+        let bytecode = compile(",[->++<],[<]", &Config::default());
+        assert_eq!(
+            bytecode.ops(),
+            [
+                Op::Input,
+                Op::Multiply {
+                    offset: 1,
+                    factor: 2
+                },
+                Op::Set {
+                    offset: 0,
+                    value: 0
+                },
+                Op::Input,
+                Op::Scan(-1),
+            ]
+        );
+    }
+
+    #[test]
+    fn steps_count_ops() {
+        let (stats, output) = run(",[->++<]>.", Config::default(), &[21]).unwrap();
+        assert_eq!(output, [42]);
+        assert_eq!(stats.steps, 5);
+        assert_eq!(stats.cells, 2);
+    }
+
+    #[test]
+    fn step_limit_stops_the_program() {
+        let config = Config {
+            step_limit: Some(10),
+            ..Config::default()
+        };
+        assert_eq!(
+            run("+[]", config, b""),
+            Err(RuntimeError::StepLimitExceeded { limit: 10 })
+        );
+    }
+
+    #[test]
+    fn pointer_leaving_the_tape_is_reported_when_a_cell_is_used() {
+        assert_eq!(
+            run("<+", Config::default(), b""),
+            Err(RuntimeError::PointerUnderflow { pc: 1 })
+        );
+
+        // The moves cancel out, so no cell left of 0 is ever used.
+        let (_, output) = run("<>+.", Config::default(), b"").unwrap();
+        assert_eq!(output, [1]);
+
+        let config = Config {
+            memory_size: Some(2),
+            ..Config::default()
+        };
+        assert_eq!(
+            run(">>+", config, b""),
+            Err(RuntimeError::PointerOverflow { pc: 2 })
+        );
+    }
+
+    #[test]
+    fn trapping_cells_overflow() {
+        let config = Config {
+            overflow: Overflow::Trapping,
+            ..Config::default()
+        };
+        assert_eq!(
+            run(">-", config, b""),
+            Err(RuntimeError::CellOverflow { pc: 1, pointer: 1 })
+        );
+    }
+}
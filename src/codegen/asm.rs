@@ -0,0 +1,332 @@
+use crate::bf::Eof;
+use crate::bytecode::{Bytecode, Op};
+use crate::interpreter::{CellWidth, Config};
+use std::io::Write;
+
+const OUTPUT_BUFFER_SIZE: usize = 4096;
+
+/// Names for operating on cells of one width, in AT&T syntax.
+struct Cell {
+    suffix: char,
+    scale: usize,
+    rax: &'static str,
+
+    /// Zero extends a cell into `%rax`.
+    load_rax: &'static str,
+}
+
+impl Cell {
+    fn new(width: CellWidth) -> Self {
+        match width {
+            CellWidth::U8 => Self {
+                suffix: 'b',
+                scale: 1,
+                rax: "%al",
+                load_rax: "movzbl {cell}, %eax",
+            },
+            CellWidth::U16 => Self {
+                suffix: 'w',
+                scale: 2,
+                rax: "%ax",
+                load_rax: "movzwl {cell}, %eax",
+            },
+            CellWidth::U32 => Self {
+                suffix: 'l',
+                scale: 4,
+                rax: "%eax",
+                load_rax: "movl {cell}, %eax",
+            },
+            CellWidth::U64 => Self {
+                suffix: 'q',
+                scale: 8,
+                rax: "%rax",
+                load_rax: "movq {cell}, %rax",
+            },
+        }
+    }
+
+    /// The cell at the index in `register`.
+    fn at(&self, register: &str) -> String {
+        format!("(%r12,{register},{})", self.scale)
+    }
+
+    /// `value` as an immediate for this width, or `None` if it has to go through a register.
+    fn immediate(&self, value: i64) -> Option<i64> {
+        match self.suffix {
+            'b' => Some(value as i8 as i64),
+            'w' => Some(value as i16 as i64),
+            'l' => Some(value as i32 as i64),
+            _ => i32::try_from(value).is_ok().then_some(value),
+        }
+    }
+}
+
+/// Write the program as GNU assembler source for x86-64 Linux, that only depends on syscalls.
+pub fn write(
+    bytecode: &Bytecode,
+    config: &Config,
+    memory_size: usize,
+    mut output: impl Write,
+) -> std::io::Result<()> {
+    let cell = Cell::new(config.cell_width);
+    if memory_size > i32::MAX as usize / cell.scale {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "The tape is too large to address in assembly",
+        ));
+    }
+
+    let suffix = cell.suffix;
+    let current = cell.at("%rbx");
+
+    writeln!(output, "# Generated by bfmacro.")?;
+    writeln!(
+        output,
+        "# Build with: as program.s -o program.o && ld program.o -o program"
+    )?;
+    writeln!(
+        output,
+        "# %rbx is the pointer, %r12 the tape and %r13 the number of buffered output bytes."
+    )?;
+    writeln!(output)?;
+    writeln!(output, "    .set TAPE_LENGTH, {memory_size}")?;
+    writeln!(output, "    .set OUTPUT_BUFFER_SIZE, {OUTPUT_BUFFER_SIZE}")?;
+    writeln!(output)?;
+    writeln!(output, "    .bss")?;
+    writeln!(output, "    .balign 8")?;
+    writeln!(output, "tape:")?;
+    writeln!(output, "    .zero TAPE_LENGTH * {}", cell.scale)?;
+    writeln!(output, "output_buffer:")?;
+    writeln!(output, "    .zero OUTPUT_BUFFER_SIZE")?;
+    writeln!(output, "input_byte:")?;
+    writeln!(output, "    .zero 1")?;
+    writeln!(output)?;
+    writeln!(output, "    .section .rodata")?;
+    writeln!(output, "pointer_message:")?;
+    writeln!(
+        output,
+        "    .ascii \"error: pointer moved outside the tape\\n\""
+    )?;
+    writeln!(
+        output,
+        "    .set POINTER_MESSAGE_LENGTH, . - pointer_message"
+    )?;
+    writeln!(output, "io_message:")?;
+    writeln!(output, "    .ascii \"error: failed to read or write\\n\"")?;
+    writeln!(output, "    .set IO_MESSAGE_LENGTH, . - io_message")?;
+    writeln!(output)?;
+    writeln!(output, "    .text")?;
+    writeln!(output, "    .globl _start")?;
+    writeln!(output, "_start:")?;
+    writeln!(output, "    xorl %ebx, %ebx")?;
+    writeln!(output, "    leaq tape(%rip), %r12")?;
+    writeln!(output, "    xorl %r13d, %r13d")?;
+    writeln!(output)?;
+
+    let move_to = |output: &mut dyn Write, register: &str| {
+        writeln!(output, "    cmpq $TAPE_LENGTH, {register}")?;
+        writeln!(output, "    jae pointer_error")
+    };
+
+    // Puts the index of the cell at `offset` in `%rcx` when it isn't the current cell.
+    let cell_at = |output: &mut dyn Write, offset: isize| {
+        if offset == 0 {
+            return Ok(current.clone());
+        }
+
+        writeln!(output, "    leaq {offset}(%rbx), %rcx")?;
+        move_to(output, "%rcx")?;
+        Ok::<_, std::io::Error>(cell.at("%rcx"))
+    };
+
+    for (index, op) in bytecode.ops().iter().enumerate() {
+        match *op {
+            Op::Add { offset, amount } => {
+                let target = cell_at(&mut output, offset)?;
+                match cell.immediate(amount) {
+                    Some(amount) => writeln!(output, "    add{suffix} ${amount}, {target}")?,
+                    None => {
+                        writeln!(output, "    movabsq ${amount}, %rax")?;
+                        writeln!(output, "    addq %rax, {target}")?;
+                    }
+                }
+            }
+            Op::Set { offset, value } => {
+                let target = cell_at(&mut output, offset)?;
+                match cell.immediate(value) {
+                    Some(value) => writeln!(output, "    mov{suffix} ${value}, {target}")?,
+                    None => {
+                        writeln!(output, "    movabsq ${value}, %rax")?;
+                        writeln!(output, "    movq %rax, {target}")?;
+                    }
+                }
+            }
+            Op::Move(distance) => {
+                writeln!(output, "    addq ${distance}, %rbx")?;
+                move_to(&mut output, "%rbx")?;
+            }
+
+            Op::Input => {
+                writeln!(output, "    call input")?;
+                match config.eof {
+                    Eof::Unchanged => {
+                        writeln!(output, "    testl %eax, %eax")?;
+                        writeln!(output, "    js .Linput_end_{index}")?;
+                    }
+                    Eof::Zero => {
+                        writeln!(output, "    testl %eax, %eax")?;
+                        writeln!(output, "    jns .Linput_store_{index}")?;
+                        writeln!(output, "    xorl %eax, %eax")?;
+                        writeln!(output, ".Linput_store_{index}:")?;
+                    }
+                    Eof::MinusOne => {
+                        writeln!(output, "    cltq")?;
+                    }
+                }
+                writeln!(output, "    mov{suffix} {}, {current}", cell.rax)?;
+                if config.eof == Eof::Unchanged {
+                    writeln!(output, ".Linput_end_{index}:")?;
+                }
+            }
+            Op::Output => {
+                writeln!(output, "    movb {current}, %dil")?;
+                writeln!(output, "    call output")?;
+            }
+
+            Op::JumpIfZero(_) => {
+                writeln!(output, "    cmp{suffix} $0, {current}")?;
+                writeln!(output, "    je .Lloop_end_{index}")?;
+                writeln!(output, ".Lloop_{index}:")?;
+            }
+            Op::JumpIfNotZero(target) => {
+                let open = target - 1;
+                writeln!(output, "    cmp{suffix} $0, {current}")?;
+                writeln!(output, "    jne .Lloop_{open}")?;
+                writeln!(output, ".Lloop_end_{open}:")?;
+            }
+
+            Op::Multiply { offset, factor } => {
+                writeln!(output, "    {}", cell.load_rax.replace("{cell}", &current))?;
+                if suffix == 'q' {
+                    writeln!(output, "    testq %rax, %rax")?;
+                } else {
+                    writeln!(output, "    testl %eax, %eax")?;
+                }
+                writeln!(output, "    jz .Lmultiply_end_{index}")?;
+                writeln!(output, "    leaq {offset}(%rbx), %rcx")?;
+                move_to(&mut output, "%rcx")?;
+                match i32::try_from(factor) {
+                    Ok(factor) => writeln!(output, "    imulq ${factor}, %rax")?,
+                    Err(_) => {
+                        writeln!(output, "    movabsq ${factor}, %rdx")?;
+                        writeln!(output, "    imulq %rdx, %rax")?;
+                    }
+                }
+                writeln!(output, "    add{suffix} {}, {}", cell.rax, cell.at("%rcx"))?;
+                writeln!(output, ".Lmultiply_end_{index}:")?;
+            }
+            Op::Scan(step) => {
+                writeln!(output, ".Lscan_{index}:")?;
+                writeln!(output, "    cmp{suffix} $0, {current}")?;
+                writeln!(output, "    je .Lscan_end_{index}")?;
+                writeln!(output, "    addq ${step}, %rbx")?;
+                move_to(&mut output, "%rbx")?;
+                writeln!(output, "    jmp .Lscan_{index}")?;
+                writeln!(output, ".Lscan_end_{index}:")?;
+            }
+
+            Op::Break => writeln!(output, "    # breakpoint")?,
+        }
+    }
+
+    writeln!(output)?;
+    writeln!(output, "    call flush")?;
+    writeln!(output, "    xorl %edi, %edi")?;
+    writeln!(output, "    jmp exit")?;
+    writeln!(output)?;
+
+    writeln!(
+        output,
+        "# Reads a byte into %eax, which is -1 at the end of input."
+    )?;
+    writeln!(output, "input:")?;
+    writeln!(output, "    call flush")?;
+    writeln!(output, "1:")?;
+    writeln!(output, "    xorl %eax, %eax")?;
+    writeln!(output, "    xorl %edi, %edi")?;
+    writeln!(output, "    leaq input_byte(%rip), %rsi")?;
+    writeln!(output, "    movl $1, %edx")?;
+    writeln!(output, "    syscall")?;
+    writeln!(output, "    cmpq $-4, %rax")?;
+    writeln!(output, "    je 1b")?;
+    writeln!(output, "    testq %rax, %rax")?;
+    writeln!(output, "    js io_error")?;
+    writeln!(output, "    jz 2f")?;
+    writeln!(output, "    movzbl input_byte(%rip), %eax")?;
+    writeln!(output, "    ret")?;
+    writeln!(output, "2:")?;
+    writeln!(output, "    movl $-1, %eax")?;
+    writeln!(output, "    ret")?;
+    writeln!(output)?;
+
+    writeln!(output, "# Buffers the byte in %dil.")?;
+    writeln!(output, "output:")?;
+    writeln!(output, "    leaq output_buffer(%rip), %rax")?;
+    writeln!(output, "    movb %dil, (%rax,%r13)")?;
+    writeln!(output, "    incq %r13")?;
+    writeln!(output, "    cmpq $OUTPUT_BUFFER_SIZE, %r13")?;
+    writeln!(output, "    je flush")?;
+    writeln!(output, "    ret")?;
+    writeln!(output)?;
+
+    writeln!(output, "flush:")?;
+    writeln!(output, "    leaq output_buffer(%rip), %rsi")?;
+    writeln!(output, "1:")?;
+    writeln!(output, "    testq %r13, %r13")?;
+    writeln!(output, "    jz 2f")?;
+    writeln!(output, "    movl $1, %eax")?;
+    writeln!(output, "    movl $1, %edi")?;
+    writeln!(output, "    movq %r13, %rdx")?;
+    writeln!(output, "    syscall")?;
+    writeln!(output, "    cmpq $-4, %rax")?;
+    writeln!(output, "    je 1b")?;
+    writeln!(output, "    testq %rax, %rax")?;
+    writeln!(output, "    js io_error")?;
+    writeln!(output, "    addq %rax, %rsi")?;
+    writeln!(output, "    subq %rax, %r13")?;
+    writeln!(output, "    jmp 1b")?;
+    writeln!(output, "2:")?;
+    writeln!(output, "    ret")?;
+    writeln!(output)?;
+
+    writeln!(output, "pointer_error:")?;
+    writeln!(output, "    call flush")?;
+    writeln!(output, "    leaq pointer_message(%rip), %rsi")?;
+    writeln!(output, "    movl $POINTER_MESSAGE_LENGTH, %edx")?;
+    writeln!(output, "    jmp fail")?;
+    writeln!(output)?;
+
+    writeln!(output, "io_error:")?;
+    writeln!(output, "    xorl %r13d, %r13d")?;
+    writeln!(output, "    leaq io_message(%rip), %rsi")?;
+    writeln!(output, "    movl $IO_MESSAGE_LENGTH, %edx")?;
+    writeln!(output)?;
+
+    writeln!(
+        output,
+        "# Writes %rdx bytes at %rsi to stderr, and exits with 1."
+    )?;
+    writeln!(output, "fail:")?;
+    writeln!(output, "    movl $1, %eax")?;
+    writeln!(output, "    movl $2, %edi")?;
+    writeln!(output, "    syscall")?;
+    writeln!(output, "    movl $1, %edi")?;
+    writeln!(output)?;
+
+    writeln!(output, "exit:")?;
+    writeln!(output, "    movl $60, %eax")?;
+    writeln!(output, "    syscall")?;
+    writeln!(output)?;
+    writeln!(output, "    .section .note.GNU-stack, \"\", @progbits")?;
+    Ok(())
+}
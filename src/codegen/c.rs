@@ -0,0 +1,144 @@
+use crate::bf::Eof;
+use crate::bytecode::{Bytecode, Op};
+use crate::interpreter::Config;
+use std::io::Write;
+
+fn offset(distance: isize) -> String {
+    if distance < 0 {
+        format!("p - {}", distance.unsigned_abs())
+    } else {
+        format!("p + {distance}")
+    }
+}
+
+fn cell_at(distance: isize) -> String {
+    if distance == 0 {
+        "tape[p]".to_owned()
+    } else {
+        format!("tape[check({})]", offset(distance))
+    }
+}
+
+pub fn write(
+    bytecode: &Bytecode,
+    config: &Config,
+    memory_size: usize,
+    mut output: impl Write,
+) -> std::io::Result<()> {
+    let max = config.cell_width.max();
+    let end_of_input = match config.eof {
+        Eof::Unchanged => "current",
+        Eof::Zero => "0",
+        Eof::MinusOne => "(cell)-1",
+    };
+
+    writeln!(output, "/* Generated by bfmacro. */")?;
+    writeln!(output, "#include <stdint.h>")?;
+    writeln!(output, "#include <stdio.h>")?;
+    writeln!(output, "#include <stdlib.h>")?;
+    writeln!(output)?;
+    writeln!(output, "#define TAPE_LENGTH {memory_size}")?;
+    writeln!(output)?;
+    writeln!(output, "typedef uint{}_t cell;", config.cell_width.bits())?;
+    writeln!(output)?;
+    writeln!(output, "static cell tape[TAPE_LENGTH];")?;
+    writeln!(output)?;
+    let ops = bytecode.ops();
+    let moves = ops.iter().any(|op| match op {
+        Op::Add { offset, .. } | Op::Set { offset, .. } => *offset != 0,
+        Op::Move(_) | Op::Multiply { .. } | Op::Scan(_) => true,
+        _ => false,
+    });
+    if moves {
+        writeln!(output, "static size_t check(size_t pointer)")?;
+        writeln!(output, "{{")?;
+        writeln!(output, "    if (pointer >= TAPE_LENGTH) {{")?;
+        writeln!(
+            output,
+            "        fputs(\"error: pointer moved outside the tape\\n\", stderr);"
+        )?;
+        writeln!(output, "        exit(1);")?;
+        writeln!(output, "    }}")?;
+        writeln!(output, "    return pointer;")?;
+        writeln!(output, "}}")?;
+        writeln!(output)?;
+    }
+
+    if ops.contains(&Op::Input) {
+        writeln!(output, "static cell input(cell current)")?;
+        writeln!(output, "{{")?;
+        writeln!(output, "    int c;")?;
+        if config.eof != Eof::Unchanged {
+            writeln!(output, "    (void)current;")?;
+        }
+        writeln!(output, "    fflush(stdout);")?;
+        writeln!(output, "    c = getchar();")?;
+        writeln!(output, "    return c == EOF ? {end_of_input} : (cell)c;")?;
+        writeln!(output, "}}")?;
+        writeln!(output)?;
+    }
+
+    writeln!(output, "int main(void)")?;
+    writeln!(output, "{{")?;
+    writeln!(output, "    size_t p = 0;")?;
+    writeln!(output)?;
+
+    let mut depth = 1;
+    for op in ops {
+        if let Op::JumpIfNotZero(_) = op {
+            depth -= 1;
+        }
+
+        let indent = "    ".repeat(depth);
+        match *op {
+            Op::Add { offset, amount } if amount < 0 => writeln!(
+                output,
+                "{indent}{} -= {};",
+                cell_at(offset),
+                amount.unsigned_abs()
+            )?,
+            Op::Add { offset, amount } => {
+                writeln!(output, "{indent}{} += {amount};", cell_at(offset))?
+            }
+            Op::Set { offset, value } => {
+                let value = value as u64 & max;
+                if value <= u32::MAX as u64 {
+                    writeln!(output, "{indent}{} = {value};", cell_at(offset))?
+                } else {
+                    writeln!(output, "{indent}{} = UINT64_C({value});", cell_at(offset))?
+                }
+            }
+            Op::Move(distance) => writeln!(output, "{indent}p = check({});", offset(distance))?,
+            Op::Input => writeln!(output, "{indent}tape[p] = input(tape[p]);")?,
+            Op::Output => writeln!(output, "{indent}putchar(tape[p]);")?,
+
+            Op::JumpIfZero(_) => {
+                writeln!(output, "{indent}while (tape[p]) {{")?;
+                depth += 1;
+            }
+            Op::JumpIfNotZero(_) => writeln!(output, "{indent}}}")?,
+
+            Op::Multiply {
+                offset: distance,
+                factor,
+            } => writeln!(
+                output,
+                "{indent}if (tape[p]) tape[check({})] += (cell)(tape[p] * UINT64_C({}));",
+                offset(distance),
+                factor as u64,
+            )?,
+            Op::Scan(step) => writeln!(
+                output,
+                "{indent}while (tape[p]) p = check({});",
+                offset(step)
+            )?,
+
+            Op::Break => writeln!(output, "{indent}/* breakpoint */")?,
+        }
+    }
+
+    writeln!(output)?;
+    writeln!(output, "    return 0;")?;
+    writeln!(output, "}}")?;
+    Ok(())
+}
@@ -0,0 +1,69 @@
+use crate::bf::BF;
+use crate::bytecode::Bytecode;
+use crate::interpreter::{Config, Overflow, DEFAULT_MEMORY_SIZE};
+use std::io::Write;
+
+mod asm;
+mod c;
+mod rust;
+mod wasm;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// GNU assembler source for x86-64 Linux.
+    Asm,
+    C,
+    Rust,
+
+    /// Rust without a `main`, to be pulled into another crate.
+    RustLibrary,
+
+    /// A WebAssembly module in the binary format.
+    Wasm,
+
+    /// A WebAssembly module in the text format.
+    Wat,
+}
+
+impl Target {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "asm" => Some(Target::Asm),
+            "c" => Some(Target::C),
+            "rust" => Some(Target::Rust),
+            "rust-lib" => Some(Target::RustLibrary),
+            "wasm" => Some(Target::Wasm),
+            "wat" => Some(Target::Wat),
+            _ => None,
+        }
+    }
+}
+
+fn invalid_input(message: impl Into<String>) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, message.into())
+}
+
+/// Translate a program into source code for `target`, with the cell width, tape length and end
+/// of input behaviour from `config`.
+pub fn generate(
+    target: Target,
+    program: &BF,
+    config: &Config,
+    output: impl Write,
+) -> std::io::Result<()> {
+    if config.overflow != Overflow::Wrapping {
+        return Err(invalid_input("Generated code only supports wrapping cells"));
+    }
+
+    let bytecode =
+        Bytecode::compile(program, config).map_err(|err| invalid_input(err.to_string()))?;
+    let memory_size = config.memory_size.unwrap_or(DEFAULT_MEMORY_SIZE);
+    match target {
+        Target::Asm => asm::write(&bytecode, config, memory_size, output),
+        Target::C => c::write(&bytecode, config, memory_size, output),
+        Target::Rust => rust::write(&bytecode, config, memory_size, true, output),
+        Target::RustLibrary => rust::write(&bytecode, config, memory_size, false, output),
+        Target::Wasm => wasm::write_binary(&bytecode, config, memory_size, output),
+        Target::Wat => wasm::write_text(&bytecode, config, memory_size, output),
+    }
+}
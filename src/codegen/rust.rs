@@ -0,0 +1,180 @@
+use crate::bf::Eof;
+use crate::bytecode::{Bytecode, Op};
+use crate::interpreter::Config;
+use std::io::Write;
+
+fn offset(distance: isize) -> String {
+    if distance < 0 {
+        format!("check(p.checked_sub({}))?", distance.unsigned_abs())
+    } else {
+        format!("check(p.checked_add({distance}))?")
+    }
+}
+
+fn cell_at(distance: isize) -> String {
+    if distance == 0 {
+        "tape[p]".to_owned()
+    } else {
+        format!("tape[{}]", offset(distance))
+    }
+}
+
+pub fn write(
+    bytecode: &Bytecode,
+    config: &Config,
+    memory_size: usize,
+    main: bool,
+    mut output: impl Write,
+) -> std::io::Result<()> {
+    let max = config.cell_width.max();
+    let end_of_input = match config.eof {
+        Eof::Unchanged => "current".to_owned(),
+        Eof::Zero => "0".to_owned(),
+        Eof::MinusOne => max.to_string(),
+    };
+
+    writeln!(output, "// Generated by bfmacro.")?;
+    writeln!(output, "use std::io::{{self, Read, Write}};")?;
+    writeln!(output)?;
+    writeln!(output, "const TAPE_LENGTH: usize = {memory_size};")?;
+    writeln!(output)?;
+    writeln!(output, "type Cell = u{};", config.cell_width.bits())?;
+    writeln!(output)?;
+
+    let ops = bytecode.ops();
+    let moves = ops.iter().any(|op| match op {
+        Op::Add { offset, .. } | Op::Set { offset, .. } => *offset != 0,
+        Op::Move(_) | Op::Multiply { .. } | Op::Scan(_) => true,
+        _ => false,
+    });
+    if moves {
+        writeln!(
+            output,
+            "fn check(pointer: Option<usize>) -> io::Result<usize> {{"
+        )?;
+        writeln!(output, "    pointer")?;
+        writeln!(output, "        .filter(|pointer| *pointer < TAPE_LENGTH)")?;
+        writeln!(output, "        .ok_or_else(|| io::Error::new(io::ErrorKind::Other, \"pointer moved outside the tape\"))")?;
+        writeln!(output, "}}")?;
+        writeln!(output)?;
+    }
+
+    if ops.contains(&Op::Input) {
+        writeln!(output, "#[allow(unused_variables)]")?;
+        writeln!(output, "fn read_cell(input: &mut impl Read, output: &mut impl Write, current: Cell) -> io::Result<Cell> {{")?;
+        writeln!(output, "    output.flush()?;")?;
+        writeln!(output, "    let mut byte = [0];")?;
+        writeln!(output, "    loop {{")?;
+        writeln!(output, "        match input.read(&mut byte) {{")?;
+        writeln!(output, "            Ok(0) => return Ok({end_of_input}),")?;
+        writeln!(
+            output,
+            "            Ok(_) => return Ok(Cell::from(byte[0])),"
+        )?;
+        writeln!(
+            output,
+            "            Err(err) if err.kind() == io::ErrorKind::Interrupted => {{}}"
+        )?;
+        writeln!(output, "            Err(err) => return Err(err),")?;
+        writeln!(output, "        }}")?;
+        writeln!(output, "    }}")?;
+        writeln!(output, "}}")?;
+        writeln!(output)?;
+    }
+
+    writeln!(
+        output,
+        "/// Run the program, reading `,` from `input` and writing `.` to `output`."
+    )?;
+    writeln!(output, "#[allow(unused_mut, unused_variables)]")?;
+    writeln!(
+        output,
+        "pub fn run(mut input: impl Read, mut output: impl Write) -> io::Result<()> {{"
+    )?;
+    writeln!(
+        output,
+        "    let mut tape: Vec<Cell> = vec![0; TAPE_LENGTH];"
+    )?;
+    writeln!(output, "    let mut p: usize = 0;")?;
+    writeln!(output)?;
+
+    let mut depth = 1;
+    for op in ops {
+        if let Op::JumpIfNotZero(_) = op {
+            depth -= 1;
+        }
+
+        let indent = "    ".repeat(depth);
+        match *op {
+            Op::Add {
+                offset: distance,
+                amount,
+            } => {
+                let (operation, amount) = if amount < 0 {
+                    ("wrapping_sub", amount.unsigned_abs() & max)
+                } else {
+                    ("wrapping_add", amount as u64 & max)
+                };
+                if distance == 0 {
+                    writeln!(output, "{indent}tape[p] = tape[p].{operation}({amount});")?
+                } else {
+                    writeln!(output, "{indent}let target = {};", offset(distance))?;
+                    writeln!(
+                        output,
+                        "{indent}tape[target] = tape[target].{operation}({amount});"
+                    )?
+                }
+            }
+            Op::Set { offset, value } => writeln!(
+                output,
+                "{indent}{} = {};",
+                cell_at(offset),
+                value as u64 & max
+            )?,
+            Op::Move(distance) => writeln!(output, "{indent}p = {};", offset(distance))?,
+            Op::Input => writeln!(
+                output,
+                "{indent}tape[p] = read_cell(&mut input, &mut output, tape[p])?;"
+            )?,
+            Op::Output => writeln!(output, "{indent}output.write_all(&[tape[p] as u8])?;")?,
+
+            Op::JumpIfZero(_) => {
+                writeln!(output, "{indent}while tape[p] != 0 {{")?;
+                depth += 1;
+            }
+            Op::JumpIfNotZero(_) => writeln!(output, "{indent}}}")?,
+
+            Op::Multiply {
+                offset: distance,
+                factor,
+            } => {
+                writeln!(output, "{indent}if tape[p] != 0 {{")?;
+                writeln!(output, "{indent}    let target = {};", offset(distance))?;
+                writeln!(
+                    output,
+                    "{indent}    tape[target] = tape[target].wrapping_add(tape[p].wrapping_mul({}));",
+                    factor as u64 & max
+                )?;
+                writeln!(output, "{indent}}}")?;
+            }
+            Op::Scan(step) => {
+                writeln!(output, "{indent}while tape[p] != 0 {{")?;
+                writeln!(output, "{indent}    p = {};", offset(step))?;
+                writeln!(output, "{indent}}}")?;
+            }
+
+            Op::Break => writeln!(output, "{indent}// breakpoint")?,
+        }
+    }
+
+    writeln!(output)?;
+    writeln!(output, "    output.flush()")?;
+    writeln!(output, "}}")?;
+    if main {
+        writeln!(output)?;
+        writeln!(output, "fn main() -> io::Result<()> {{")?;
+        writeln!(output, "    run(io::stdin().lock(), io::stdout().lock())")?;
+        writeln!(output, "}}")?;
+    }
+    Ok(())
+}
@@ -0,0 +1,636 @@
+use crate::bf::Eof;
+use crate::bytecode::{Bytecode, Op};
+use crate::interpreter::{CellWidth, Config};
+use std::io::Write;
+
+const PAGE_SIZE: usize = 65536;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ValueType {
+    I32,
+    I64,
+}
+
+impl ValueType {
+    fn name(&self) -> &'static str {
+        match self {
+            ValueType::I32 => "i32",
+            ValueType::I64 => "i64",
+        }
+    }
+
+    fn code(&self) -> u8 {
+        match self {
+            ValueType::I32 => 0x7F,
+            ValueType::I64 => 0x7E,
+        }
+    }
+}
+
+/// The subset of WebAssembly instructions the generated code uses.
+#[derive(Debug, Clone, Copy)]
+enum Instruction {
+    Unreachable,
+    Block,
+    Loop,
+    If(Option<ValueType>),
+    Else,
+    End,
+    Br(u32),
+    BrIf(u32),
+    Call(u32),
+    LocalGet(u32),
+    LocalSet(u32),
+    LocalTee(u32),
+    Load(CellWidth),
+    Store(CellWidth),
+    I32Const(i32),
+    I64Const(i64),
+    Eqz(ValueType),
+    Add(ValueType),
+    Mul(ValueType),
+    I32LtS,
+    I32GeU,
+    I32WrapI64,
+    I64ExtendI32U,
+}
+
+impl Instruction {
+    fn write_text(&self, locals: &[&str], functions: &[&str]) -> String {
+        let memory = |width: &CellWidth, operation: &str| match width {
+            CellWidth::U8 => format!(
+                "i32.{operation}8{}",
+                if operation == "load" { "_u" } else { "" }
+            ),
+            CellWidth::U16 => format!(
+                "i32.{operation}16{}",
+                if operation == "load" { "_u" } else { "" }
+            ),
+            CellWidth::U32 => format!("i32.{operation}"),
+            CellWidth::U64 => format!("i64.{operation}"),
+        };
+
+        match self {
+            Instruction::Unreachable => "unreachable".to_owned(),
+            Instruction::Block => "block".to_owned(),
+            Instruction::Loop => "loop".to_owned(),
+            Instruction::If(None) => "if".to_owned(),
+            Instruction::If(Some(result)) => format!("if (result {})", result.name()),
+            Instruction::Else => "else".to_owned(),
+            Instruction::End => "end".to_owned(),
+            Instruction::Br(depth) => format!("br {depth}"),
+            Instruction::BrIf(depth) => format!("br_if {depth}"),
+            Instruction::Call(function) => format!("call ${}", functions[*function as usize]),
+            Instruction::LocalGet(local) => format!("local.get ${}", locals[*local as usize]),
+            Instruction::LocalSet(local) => format!("local.set ${}", locals[*local as usize]),
+            Instruction::LocalTee(local) => format!("local.tee ${}", locals[*local as usize]),
+            Instruction::Load(width) => memory(width, "load"),
+            Instruction::Store(width) => memory(width, "store"),
+            Instruction::I32Const(value) => format!("i32.const {value}"),
+            Instruction::I64Const(value) => format!("i64.const {value}"),
+            Instruction::Eqz(value_type) => format!("{}.eqz", value_type.name()),
+            Instruction::Add(value_type) => format!("{}.add", value_type.name()),
+            Instruction::Mul(value_type) => format!("{}.mul", value_type.name()),
+            Instruction::I32LtS => "i32.lt_s".to_owned(),
+            Instruction::I32GeU => "i32.ge_u".to_owned(),
+            Instruction::I32WrapI64 => "i32.wrap_i64".to_owned(),
+            Instruction::I64ExtendI32U => "i64.extend_i32_u".to_owned(),
+        }
+    }
+
+    fn write_binary(&self, code: &mut Vec<u8>) {
+        let memory_argument = |code: &mut Vec<u8>, opcode: u8, width: &CellWidth| {
+            code.push(opcode);
+            code.push(width.bits().trailing_zeros() as u8 - 3);
+            code.push(0);
+        };
+
+        match self {
+            Instruction::Unreachable => code.push(0x00),
+            Instruction::Block => code.extend([0x02, 0x40]),
+            Instruction::Loop => code.extend([0x03, 0x40]),
+            Instruction::If(None) => code.extend([0x04, 0x40]),
+            Instruction::If(Some(result)) => code.extend([0x04, result.code()]),
+            Instruction::Else => code.push(0x05),
+            Instruction::End => code.push(0x0B),
+            Instruction::Br(depth) => {
+                code.push(0x0C);
+                unsigned(code, *depth as u64);
+            }
+            Instruction::BrIf(depth) => {
+                code.push(0x0D);
+                unsigned(code, *depth as u64);
+            }
+            Instruction::Call(function) => {
+                code.push(0x10);
+                unsigned(code, *function as u64);
+            }
+            Instruction::LocalGet(local) => {
+                code.push(0x20);
+                unsigned(code, *local as u64);
+            }
+            Instruction::LocalSet(local) => {
+                code.push(0x21);
+                unsigned(code, *local as u64);
+            }
+            Instruction::LocalTee(local) => {
+                code.push(0x22);
+                unsigned(code, *local as u64);
+            }
+            Instruction::Load(width) => {
+                let opcode = match width {
+                    CellWidth::U8 => 0x2D,
+                    CellWidth::U16 => 0x2F,
+                    CellWidth::U32 => 0x28,
+                    CellWidth::U64 => 0x29,
+                };
+                memory_argument(code, opcode, width);
+            }
+            Instruction::Store(width) => {
+                let opcode = match width {
+                    CellWidth::U8 => 0x3A,
+                    CellWidth::U16 => 0x3B,
+                    CellWidth::U32 => 0x36,
+                    CellWidth::U64 => 0x37,
+                };
+                memory_argument(code, opcode, width);
+            }
+            Instruction::I32Const(value) => {
+                code.push(0x41);
+                signed(code, *value as i64);
+            }
+            Instruction::I64Const(value) => {
+                code.push(0x42);
+                signed(code, *value);
+            }
+            Instruction::Eqz(ValueType::I32) => code.push(0x45),
+            Instruction::Eqz(ValueType::I64) => code.push(0x50),
+            Instruction::Add(ValueType::I32) => code.push(0x6A),
+            Instruction::Add(ValueType::I64) => code.push(0x7C),
+            Instruction::Mul(ValueType::I32) => code.push(0x6C),
+            Instruction::Mul(ValueType::I64) => code.push(0x7E),
+            Instruction::I32LtS => code.push(0x48),
+            Instruction::I32GeU => code.push(0x4F),
+            Instruction::I32WrapI64 => code.push(0xA7),
+            Instruction::I64ExtendI32U => code.push(0xAD),
+        }
+    }
+}
+
+fn unsigned(code: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            code.push(byte);
+            return;
+        }
+        code.push(byte | 0x80);
+    }
+}
+
+fn signed(code: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+            code.push(byte);
+            return;
+        }
+        code.push(byte | 0x80);
+    }
+}
+
+fn name(code: &mut Vec<u8>, name: &str) {
+    unsigned(code, name.len() as u64);
+    code.extend(name.as_bytes());
+}
+
+fn section(module: &mut Vec<u8>, id: u8, contents: Vec<u8>) {
+    module.push(id);
+    unsigned(module, contents.len() as u64);
+    module.extend(contents);
+}
+
+const GETCHAR: u32 = 0;
+const PUTCHAR: u32 = 1;
+const CHECK: u32 = 2;
+const INPUT: u32 = 3;
+const RUN: u32 = 4;
+const FUNCTION_NAMES: [&str; 5] = ["getchar", "putchar", "check", "input", "run"];
+
+const POINTER: u32 = 0;
+const TARGET: u32 = 1;
+const VALUE: u32 = 2;
+const RUN_LOCALS: [&str; 3] = ["p", "target", "value"];
+
+struct Function {
+    name: &'static str,
+    parameters: Vec<ValueType>,
+    result: Option<ValueType>,
+    locals: Vec<ValueType>,
+    local_names: &'static [&'static str],
+    body: Vec<Instruction>,
+}
+
+/// Builds the module for a program, with cells of `width` in a tape `limit` bytes long.
+struct Module {
+    width: CellWidth,
+    cell: ValueType,
+    limit: usize,
+    pages: usize,
+    functions: Vec<Function>,
+}
+
+impl Module {
+    fn new(bytecode: &Bytecode, config: &Config, memory_size: usize) -> std::io::Result<Self> {
+        let width = config.cell_width;
+        let bytes = width.bits() as usize / 8;
+        let limit = memory_size * bytes;
+        if limit > u32::MAX as usize {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "The tape is too large for WebAssembly memory",
+            ));
+        }
+
+        let cell = match width {
+            CellWidth::U64 => ValueType::I64,
+            _ => ValueType::I32,
+        };
+        let mut module = Self {
+            width,
+            cell,
+            limit,
+            pages: limit.div_ceil(PAGE_SIZE).max(1),
+            functions: Vec::new(),
+        };
+
+        module.functions.push(module.check());
+        module.functions.push(module.input(config.eof));
+        let run = module.run(bytecode);
+        module.functions.push(run);
+        Ok(module)
+    }
+
+    fn constant(&self, value: i64) -> Instruction {
+        match self.cell {
+            ValueType::I32 => Instruction::I32Const(value as i32),
+            ValueType::I64 => Instruction::I64Const(value),
+        }
+    }
+
+    /// `check(address)` traps unless `address` is within the tape, and returns it.
+    fn check(&self) -> Function {
+        Function {
+            name: "check",
+            parameters: vec![ValueType::I32],
+            result: Some(ValueType::I32),
+            locals: Vec::new(),
+            local_names: &["address"],
+            body: vec![
+                Instruction::LocalGet(0),
+                Instruction::I32Const(self.limit as u32 as i32),
+                Instruction::I32GeU,
+                Instruction::If(None),
+                Instruction::Unreachable,
+                Instruction::End,
+                Instruction::LocalGet(0),
+            ],
+        }
+    }
+
+    /// `input(current)` reads a byte, or returns the end of input value for the cell.
+    fn input(&self, eof: Eof) -> Function {
+        let end_of_input = match eof {
+            Eof::Unchanged => Instruction::LocalGet(0),
+            Eof::Zero => self.constant(0),
+            Eof::MinusOne => self.constant(-1),
+        };
+
+        let mut body = vec![
+            Instruction::Call(GETCHAR),
+            Instruction::LocalTee(1),
+            Instruction::I32Const(0),
+            Instruction::I32LtS,
+            Instruction::If(Some(self.cell)),
+            end_of_input,
+            Instruction::Else,
+            Instruction::LocalGet(1),
+        ];
+        if self.cell == ValueType::I64 {
+            body.push(Instruction::I64ExtendI32U);
+        }
+        body.push(Instruction::End);
+
+        Function {
+            name: "input",
+            parameters: vec![self.cell],
+            result: Some(self.cell),
+            locals: vec![ValueType::I32],
+            local_names: &["current", "byte"],
+            body,
+        }
+    }
+
+    fn load(&self, body: &mut Vec<Instruction>) {
+        body.extend([
+            Instruction::LocalGet(POINTER),
+            Instruction::Load(self.width),
+        ]);
+    }
+
+    /// Leaves 1 on the stack if the current cell is zero.
+    fn is_zero(&self, body: &mut Vec<Instruction>) {
+        self.load(body);
+        body.push(Instruction::Eqz(self.cell));
+    }
+
+    /// Leaves a non-zero i32 on the stack if the current cell isn't zero.
+    fn is_nonzero(&self, body: &mut Vec<Instruction>) {
+        match self.cell {
+            ValueType::I32 => self.load(body),
+            ValueType::I64 => {
+                self.is_zero(body);
+                body.push(Instruction::Eqz(ValueType::I32));
+            }
+        }
+    }
+
+    fn move_pointer(&self, distance: isize, body: &mut Vec<Instruction>) {
+        let bytes = self.width.bits() as i64 / 8;
+        body.extend([
+            Instruction::LocalGet(POINTER),
+            Instruction::I32Const((distance as i64 * bytes) as i32),
+            Instruction::Add(ValueType::I32),
+            Instruction::Call(CHECK),
+            Instruction::LocalSet(POINTER),
+        ]);
+    }
+
+    /// Put the checked address of the cell at `offset` from the pointer in a local, and return
+    /// the local.
+    fn address(&self, offset: isize, body: &mut Vec<Instruction>) -> u32 {
+        if offset == 0 {
+            return POINTER;
+        }
+
+        let bytes = self.width.bits() as i64 / 8;
+        body.extend([
+            Instruction::LocalGet(POINTER),
+            Instruction::I32Const((offset as i64 * bytes) as i32),
+            Instruction::Add(ValueType::I32),
+            Instruction::Call(CHECK),
+            Instruction::LocalSet(TARGET),
+        ]);
+        TARGET
+    }
+
+    fn run(&self, bytecode: &Bytecode) -> Function {
+        let mut body = Vec::new();
+        for op in bytecode.ops() {
+            match *op {
+                Op::Add { offset, amount } => {
+                    let address = self.address(offset, &mut body);
+                    body.extend([
+                        Instruction::LocalGet(address),
+                        Instruction::LocalGet(address),
+                        Instruction::Load(self.width),
+                        self.constant(amount),
+                        Instruction::Add(self.cell),
+                        Instruction::Store(self.width),
+                    ]);
+                }
+                Op::Set { offset, value } => {
+                    let address = self.address(offset, &mut body);
+                    body.extend([
+                        Instruction::LocalGet(address),
+                        self.constant(value),
+                        Instruction::Store(self.width),
+                    ]);
+                }
+                Op::Move(distance) => self.move_pointer(distance, &mut body),
+
+                Op::Input => {
+                    body.push(Instruction::LocalGet(POINTER));
+                    self.load(&mut body);
+                    body.extend([Instruction::Call(INPUT), Instruction::Store(self.width)]);
+                }
+                Op::Output => {
+                    self.load(&mut body);
+                    if self.cell == ValueType::I64 {
+                        body.push(Instruction::I32WrapI64);
+                    }
+                    body.push(Instruction::Call(PUTCHAR));
+                }
+
+                Op::JumpIfZero(_) => {
+                    body.push(Instruction::Block);
+                    self.is_zero(&mut body);
+                    body.extend([Instruction::BrIf(0), Instruction::Loop]);
+                }
+                Op::JumpIfNotZero(_) => {
+                    self.is_nonzero(&mut body);
+                    body.extend([Instruction::BrIf(0), Instruction::End, Instruction::End]);
+                }
+
+                Op::Multiply { offset, factor } => {
+                    self.load(&mut body);
+                    body.push(Instruction::LocalTee(VALUE));
+                    if self.cell == ValueType::I64 {
+                        body.extend([
+                            Instruction::Eqz(ValueType::I64),
+                            Instruction::Eqz(ValueType::I32),
+                        ]);
+                    }
+                    body.push(Instruction::If(None));
+
+                    self.address(offset, &mut body);
+                    body.extend([
+                        Instruction::LocalGet(TARGET),
+                        Instruction::LocalGet(TARGET),
+                        Instruction::Load(self.width),
+                        Instruction::LocalGet(VALUE),
+                        self.constant(factor),
+                        Instruction::Mul(self.cell),
+                        Instruction::Add(self.cell),
+                        Instruction::Store(self.width),
+                        Instruction::End,
+                    ]);
+                }
+                Op::Scan(step) => {
+                    body.extend([Instruction::Block, Instruction::Loop]);
+                    self.is_zero(&mut body);
+                    body.push(Instruction::BrIf(1));
+                    self.move_pointer(step, &mut body);
+                    body.extend([Instruction::Br(0), Instruction::End, Instruction::End]);
+                }
+
+                Op::Break => {}
+            }
+        }
+
+        Function {
+            name: "run",
+            parameters: Vec::new(),
+            result: None,
+            locals: vec![ValueType::I32, ValueType::I32, self.cell],
+            local_names: &RUN_LOCALS,
+            body,
+        }
+    }
+
+    fn write_text(&self, mut output: impl Write) -> std::io::Result<()> {
+        writeln!(output, ";; Generated by bfmacro.")?;
+        writeln!(output, "(module")?;
+        writeln!(
+            output,
+            "  (import \"env\" \"getchar\" (func $getchar (result i32)))"
+        )?;
+        writeln!(
+            output,
+            "  (import \"env\" \"putchar\" (func $putchar (param i32)))"
+        )?;
+        writeln!(output, "  (memory (export \"memory\") {})", self.pages)?;
+
+        for function in &self.functions {
+            write!(output, "\n  (func ${}", function.name)?;
+            if function.name == "run" {
+                write!(output, " (export \"run\")")?;
+            }
+
+            let mut names = function.local_names.iter();
+            for parameter in &function.parameters {
+                write!(
+                    output,
+                    " (param ${} {})",
+                    names.next().unwrap(),
+                    parameter.name()
+                )?;
+            }
+            if let Some(result) = function.result {
+                write!(output, " (result {})", result.name())?;
+            }
+            writeln!(output)?;
+            for local in &function.locals {
+                writeln!(
+                    output,
+                    "    (local ${} {})",
+                    names.next().unwrap(),
+                    local.name()
+                )?;
+            }
+
+            let mut depth = 2;
+            for instruction in &function.body {
+                if matches!(instruction, Instruction::End | Instruction::Else) {
+                    depth -= 1;
+                }
+
+                let text = instruction.write_text(function.local_names, &FUNCTION_NAMES);
+                writeln!(output, "{}{text}", "  ".repeat(depth))?;
+
+                if matches!(
+                    instruction,
+                    Instruction::Block | Instruction::Loop | Instruction::If(_) | Instruction::Else
+                ) {
+                    depth += 1;
+                }
+            }
+            writeln!(output, "  )")?;
+        }
+
+        writeln!(output, ")")?;
+        Ok(())
+    }
+
+    fn write_binary(&self, mut output: impl Write) -> std::io::Result<()> {
+        let mut module = b"\0asm".to_vec();
+        module.extend(1u32.to_le_bytes());
+
+        // Imports take the first two types, then each function has its own.
+        let mut types = Vec::new();
+        unsigned(&mut types, 2 + self.functions.len() as u64);
+        types.extend([0x60, 0x00, 0x01, ValueType::I32.code()]);
+        types.extend([0x60, 0x01, ValueType::I32.code(), 0x00]);
+        for function in &self.functions {
+            types.push(0x60);
+            unsigned(&mut types, function.parameters.len() as u64);
+            types.extend(function.parameters.iter().map(ValueType::code));
+            match function.result {
+                Some(result) => types.extend([0x01, result.code()]),
+                None => types.push(0x00),
+            }
+        }
+        section(&mut module, 1, types);
+
+        let mut imports = Vec::new();
+        unsigned(&mut imports, 2);
+        for (index, function) in [GETCHAR, PUTCHAR].into_iter().enumerate() {
+            name(&mut imports, "env");
+            name(&mut imports, FUNCTION_NAMES[function as usize]);
+            imports.extend([0x00, index as u8]);
+        }
+        section(&mut module, 2, imports);
+
+        let mut functions = Vec::new();
+        unsigned(&mut functions, self.functions.len() as u64);
+        for index in 0..self.functions.len() {
+            unsigned(&mut functions, 2 + index as u64);
+        }
+        section(&mut module, 3, functions);
+
+        let mut memory = vec![0x01, 0x00];
+        unsigned(&mut memory, self.pages as u64);
+        section(&mut module, 5, memory);
+
+        let mut exports = Vec::new();
+        unsigned(&mut exports, 2);
+        name(&mut exports, "memory");
+        exports.extend([0x02, 0x00]);
+        name(&mut exports, "run");
+        exports.push(0x00);
+        unsigned(&mut exports, RUN as u64);
+        section(&mut module, 7, exports);
+
+        let mut code = Vec::new();
+        unsigned(&mut code, self.functions.len() as u64);
+        for function in &self.functions {
+            let mut body = Vec::new();
+            unsigned(&mut body, function.locals.len() as u64);
+            for local in &function.locals {
+                body.extend([0x01, local.code()]);
+            }
+            for instruction in &function.body {
+                instruction.write_binary(&mut body);
+            }
+            body.push(0x0B);
+
+            unsigned(&mut code, body.len() as u64);
+            code.extend(body);
+        }
+        section(&mut module, 10, code);
+
+        output.write_all(&module)
+    }
+}
+
+/// Write the program as a WebAssembly module in the text format.
+pub fn write_text(
+    bytecode: &Bytecode,
+    config: &Config,
+    memory_size: usize,
+    output: impl Write,
+) -> std::io::Result<()> {
+    Module::new(bytecode, config, memory_size)?.write_text(output)
+}
+
+/// Write the program as a WebAssembly module in the binary format.
+pub fn write_binary(
+    bytecode: &Bytecode,
+    config: &Config,
+    memory_size: usize,
+    output: impl Write,
+) -> std::io::Result<()> {
+    Module::new(bytecode, config, memory_size)?.write_binary(output)
+}
@@ -1,20 +1,25 @@
-use std::path::{Path, PathBuf};
+use super::diagnostic::FileId;
 
 pub type Program = Vec<Definition>;
 pub type Variable = Vec<Identifier>;
 
 #[derive(Debug, Clone)]
 pub enum Definition {
-    Include(String),
+    Include(String, Span),
     Frame(FrameDefinition),
     Macro(Macro),
     Using(Using),
+    Constant(Constant),
 }
 
 #[derive(Debug, Clone)]
 pub struct FrameDefinition {
     pub name: Identifier,
+
+    /// Let the compiler choose the order of the slots.
+    pub auto: bool,
     pub slots: Vec<SlotDefinition>,
+    pub file: FileId,
 }
 
 #[derive(Debug, Clone)]
@@ -35,6 +40,14 @@ pub enum Parameter {
     Slot(Identifier),
     SubFrame(Identifier, Identifier),
     Block(Identifier),
+    Integer(Identifier),
+}
+
+#[derive(Debug, Clone)]
+pub struct Constant {
+    pub name: Identifier,
+    pub value: Expression,
+    pub file: FileId,
 }
 
 #[derive(Debug, Clone)]
@@ -45,31 +58,70 @@ pub struct Using {
 
 #[derive(Debug, Clone)]
 pub enum Instruction {
-    Add,
-    Subtract,
+    Add(Span),
+    Subtract(Span),
+
+    /// `+` or `-` with a count, like `+48` or `-'A'`.
+    AddMany(Span, Expression),
+    SubtractMany(Span, Expression),
+
     Left(Span),
     Right(Span),
-    Input,
-    Output,
-    OpenLoop,
+    Input(Span),
+    Output(Span),
+    OpenLoop(Span),
     CloseLoop(Span),
     MovingBlock(Block),
     Using(Using),
+
+    /// Evaluate the block a number of times known at compile time.
+    Repeat(Expression, Block, Span),
+
+    /// Evaluate the first block if the condition is non-zero at compile time, otherwise the
+    /// second if there is one.
+    If(Expression, Block, Option<Block>, Span),
+
     Variable(Variable),
     MacroInvoke(Identifier, Vec<Argument>),
 }
 
+/// An integer known at compile time.
+#[derive(Debug, Clone)]
+pub enum Expression {
+    Number(i64, Span),
+    Constant(Identifier),
+    Negate(Box<Expression>, Span),
+    Binary(Box<Expression>, Operator, Box<Expression>, Span),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
 #[derive(Debug, Clone)]
 pub enum Argument {
     Variable(Variable),
     Block(Block),
+    Integer(Expression),
+    String(String, Span),
 }
 
 #[derive(Debug, Clone)]
 pub struct Block {
     pub instructions: Vec<Instruction>,
     pub span: Span,
-    pub file_path: Option<PathBuf>,
+    pub file: FileId,
 }
 
 #[derive(Debug, Clone)]
@@ -79,39 +131,3 @@ pub struct Identifier {
 }
 
 pub type Span = (usize, usize);
-
-fn set_block_file_path(block: &mut Block, file_path: &Path) {
-    block.file_path = Some(file_path.to_owned());
-    for instruction in &mut block.instructions {
-        match instruction {
-            Instruction::MovingBlock(block) => {
-                set_block_file_path(block, file_path);
-            }
-            Instruction::Using(using) => {
-                set_block_file_path(&mut using.block, file_path);
-            }
-            Instruction::MacroInvoke(_, arguments) => {
-                for argument in arguments {
-                    if let Argument::Block(block) = argument {
-                        set_block_file_path(block, file_path);
-                    }
-                }
-            }
-            _ => {}
-        }
-    }
-}
-
-pub fn set_program_file_path(program: &mut Program, file_path: &Path) {
-    for definition in program {
-        match definition {
-            Definition::Macro(macro_) => {
-                set_block_file_path(&mut macro_.block, file_path);
-            }
-            Definition::Using(using) => {
-                set_block_file_path(&mut using.block, file_path);
-            }
-            _ => {}
-        }
-    }
-}
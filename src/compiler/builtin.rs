@@ -0,0 +1,209 @@
+use super::ast::{Argument, Block, Identifier, Instruction, Span, Variable};
+use super::diagnostic::{ErrorCode, FileId};
+use super::error::{argument_span, arguments_span, Error, Result};
+use crate::bf::Eof;
+
+/// Settings of the target that built-in macros adapt to.
+#[derive(Clone, Copy)]
+pub struct Target {
+    pub eof: Eof,
+}
+
+fn slot_arguments<'a>(
+    name: &Identifier,
+    arguments: &'a [Argument],
+    required: usize,
+    optional: usize,
+) -> Result<Vec<&'a Variable>> {
+    if arguments.len() < required || arguments.len() > required + optional {
+        let expected = if optional == 0 {
+            required.to_string()
+        } else {
+            format!("{required} or {}", required + optional)
+        };
+
+        return Err(Error::new(
+            ErrorCode::ArgumentCount,
+            arguments_span(arguments).unwrap_or(name.span),
+            format!(
+                "Built-in macro '{}' expected {expected} arguments, got {}",
+                name.value,
+                arguments.len()
+            ),
+        ));
+    }
+
+    arguments
+        .iter()
+        .map(|argument| match argument {
+            Argument::Variable(variable) => Ok(variable),
+            Argument::Block(_) => Err(Error::new(
+                ErrorCode::ArgumentType,
+                argument_span(argument),
+                format!("Can not pass a block to built-in macro '{}'", name.value),
+            )),
+            Argument::Integer(_) => Err(Error::new(
+                ErrorCode::ArgumentType,
+                argument_span(argument),
+                format!("Can not pass an integer to built-in macro '{}'", name.value),
+            )),
+            Argument::String(..) => Err(Error::new(
+                ErrorCode::ArgumentType,
+                argument_span(argument),
+                format!("Can not pass a string to built-in macro '{}'", name.value),
+            )),
+        })
+        .collect()
+}
+
+/// Builds the instructions of a built-in macro, all attributed to the invocation.
+struct Expansion {
+    instructions: Vec<Instruction>,
+    span: Span,
+}
+
+impl Expansion {
+    fn new(span: Span) -> Self {
+        Self {
+            instructions: Vec::new(),
+            span,
+        }
+    }
+
+    fn code(mut self, code: &str) -> Self {
+        for c in code.chars() {
+            let span = self.span;
+            self.instructions.push(match c {
+                '+' => Instruction::Add(span),
+                '-' => Instruction::Subtract(span),
+                ',' => Instruction::Input(span),
+                '.' => Instruction::Output(span),
+                '[' => Instruction::OpenLoop(span),
+                ']' => Instruction::CloseLoop(span),
+                _ => continue,
+            });
+        }
+        self
+    }
+
+    fn add(mut self, amount: i64) -> Self {
+        let span = self.span;
+        let instruction = if amount < 0 {
+            Instruction::Subtract(span)
+        } else {
+            Instruction::Add(span)
+        };
+
+        for _ in 0..amount.unsigned_abs() {
+            self.instructions.push(instruction.clone());
+        }
+        self
+    }
+
+    fn at(mut self, variable: &Variable) -> Self {
+        self.instructions
+            .push(Instruction::Variable(variable.clone()));
+        self
+    }
+
+    fn into_block(self, file: FileId) -> Block {
+        Block {
+            instructions: self.instructions,
+            span: self.span,
+            file,
+        }
+    }
+}
+
+/// `read(cell)` or `read(cell, temp)`: read a byte into `cell`, leaving 0 at the end of input.
+fn read(name: &Identifier, arguments: &[Argument], target: &Target) -> Result<Expansion> {
+    let arguments = slot_arguments(name, arguments, 1, 1)?;
+    let cell = arguments[0];
+    let expansion = Expansion::new(name.span).at(cell);
+
+    match (target.eof, arguments.get(1)) {
+        (Eof::Zero, _) => Ok(expansion.code(",")),
+        (Eof::Unchanged, _) => Ok(expansion.code("[-],")),
+
+        // Add one so the end of input becomes 0, then take it off again for every other value.
+        (Eof::MinusOne, Some(temp)) => Ok(expansion
+            .code(",+[-")
+            .at(temp)
+            .code("+")
+            .at(cell)
+            .code("]")
+            .at(temp)
+            .code("[-[-")
+            .at(cell)
+            .code("+")
+            .at(temp)
+            .code("]]")),
+
+        (Eof::MinusOne, None) => Err(Error::new(
+            ErrorCode::ArgumentCount,
+            name.span,
+            "Built-in macro 'read' needs a temporary slot when end of input is -1",
+        )),
+    }
+}
+
+/// `print(temp, "text")`: write `text` using `temp`, changing it from each byte to the next, and
+/// clear it again.
+fn print(name: &Identifier, arguments: &[Argument]) -> Result<Expansion> {
+    let [Argument::Variable(temp), Argument::String(text, _)] = arguments else {
+        if arguments.len() != 2 {
+            return Err(Error::new(
+                ErrorCode::ArgumentCount,
+                arguments_span(arguments).unwrap_or(name.span),
+                format!(
+                    "Built-in macro 'print' expected 2 arguments, got {}",
+                    arguments.len()
+                ),
+            ));
+        }
+
+        return Err(Error::new(
+            ErrorCode::ArgumentType,
+            arguments_span(arguments).unwrap_or(name.span),
+            "Built-in macro 'print' expects a slot and a string",
+        ));
+    };
+
+    let mut expansion = Expansion::new(name.span);
+    if text.is_empty() {
+        return Ok(expansion);
+    }
+
+    expansion = expansion.at(temp).code("[-]");
+    let mut current = 0i64;
+    for byte in text.bytes() {
+        let byte = byte as i64;
+
+        // Clearing the cell takes three instructions, so start again from 0 when that's shorter.
+        if 3 + byte < (byte - current).abs() {
+            expansion = expansion.code("[-]");
+            current = 0;
+        }
+
+        expansion = expansion.add(byte - current).code(".");
+        current = byte;
+    }
+
+    Ok(expansion.code("[-]"))
+}
+
+/// Expand the built-in macro `name`, or `None` if there isn't one by that name.
+pub fn expand_builtin(
+    name: &Identifier,
+    arguments: &[Argument],
+    file: FileId,
+    target: &Target,
+) -> Option<Result<Block>> {
+    let expansion = match name.value.as_str() {
+        "read" => read(name, arguments, target),
+        "print" => print(name, arguments),
+        _ => return None,
+    };
+
+    Some(expansion.map(|expansion| expansion.into_block(file)))
+}
@@ -0,0 +1,431 @@
+use super::ast::Span;
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct FileId(usize);
+
+impl FileId {
+    pub fn index(&self) -> usize {
+        self.0
+    }
+}
+
+pub struct SourceFile {
+    pub path: PathBuf,
+    pub source: String,
+    line_starts: Vec<usize>,
+}
+
+impl SourceFile {
+    fn new(path: PathBuf, source: String) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+
+        Self {
+            path,
+            source,
+            line_starts,
+        }
+    }
+
+    /// Zero based line index containing the byte `offset`.
+    pub fn line_index(&self, offset: usize) -> usize {
+        match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(line) => line - 1,
+        }
+    }
+
+    /// One based line and column of the byte `offset`.
+    pub fn location(&self, offset: usize) -> (usize, usize) {
+        let offset = offset.min(self.source.len());
+        let line = self.line_index(offset);
+        let column = self.source[self.line_starts[line]..offset].chars().count();
+        (line + 1, column + 1)
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    pub fn line(&self, line_index: usize) -> &str {
+        let start = self.line_starts[line_index];
+        let end = self
+            .line_starts
+            .get(line_index + 1)
+            .copied()
+            .unwrap_or(self.source.len());
+        self.source[start..end].trim_end_matches(['\n', '\r'])
+    }
+
+    pub fn line_start(&self, line_index: usize) -> usize {
+        self.line_starts[line_index]
+    }
+}
+
+#[derive(Default)]
+pub struct Files {
+    files: Vec<SourceFile>,
+}
+
+impl Files {
+    pub fn add(&mut self, path: &Path, source: String) -> FileId {
+        self.files.push(SourceFile::new(path.to_owned(), source));
+        FileId(self.files.len() - 1)
+    }
+
+    pub fn get(&self, file: FileId) -> &SourceFile {
+        &self.files[file.0]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &SourceFile> {
+        self.files.iter()
+    }
+
+    pub fn find(&self, path: &Path) -> Option<FileId> {
+        self.files
+            .iter()
+            .position(|file| file.path == path)
+            .map(FileId)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    Io,
+    Syntax,
+    UnknownSymbol,
+    NotASubFrame,
+    ExpectedSlot,
+    UnknownFrame,
+    UnknownMacro,
+    DuplicateDefinition,
+    ArgumentCount,
+    ArgumentType,
+    RecursiveFrame,
+    UnknownConstant,
+    RecursiveConstant,
+    ExpectedInteger,
+    RecursionLimit,
+    UnbalancedLoop,
+    LoopPointerMismatch,
+    ManualMovement,
+    NamedAccessInMovingBlock,
+    CountOutOfRange,
+    Arithmetic,
+    NotConstant,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::Io => "E0001",
+            ErrorCode::Syntax => "E0002",
+            ErrorCode::UnknownSymbol => "E0100",
+            ErrorCode::NotASubFrame => "E0101",
+            ErrorCode::ExpectedSlot => "E0102",
+            ErrorCode::UnknownFrame => "E0103",
+            ErrorCode::UnknownMacro => "E0104",
+            ErrorCode::DuplicateDefinition => "E0105",
+            ErrorCode::ArgumentCount => "E0106",
+            ErrorCode::ArgumentType => "E0107",
+            ErrorCode::RecursiveFrame => "E0108",
+            ErrorCode::UnknownConstant => "E0109",
+            ErrorCode::RecursiveConstant => "E0110",
+            ErrorCode::ExpectedInteger => "E0111",
+            ErrorCode::RecursionLimit => "E0112",
+            ErrorCode::UnbalancedLoop => "E0200",
+            ErrorCode::LoopPointerMismatch => "E0201",
+            ErrorCode::ManualMovement => "E0202",
+            ErrorCode::NamedAccessInMovingBlock => "E0203",
+            ErrorCode::CountOutOfRange => "E0204",
+            ErrorCode::Arithmetic => "E0205",
+            ErrorCode::NotConstant => "E0206",
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub file: FileId,
+    pub span: Span,
+    pub message: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Option<ErrorCode>,
+    pub message: String,
+    pub primary: Option<Label>,
+    pub secondary: Vec<Label>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            code: Some(code),
+            message: message.into(),
+            primary: None,
+            secondary: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn io(file_path: &Path, error: std::io::Error) -> Self {
+        Self::error(
+            ErrorCode::Io,
+            format!("Could not read '{}': {error}", file_path.display()),
+        )
+    }
+
+    pub fn with_primary(mut self, file: FileId, span: Span, message: impl Into<String>) -> Self {
+        self.primary = Some(Label {
+            file,
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_label(mut self, file: FileId, span: Span, message: impl Into<String>) -> Self {
+        self.secondary.push(Label {
+            file,
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    fn labels(&self) -> impl Iterator<Item = (&Label, bool)> {
+        self.primary
+            .iter()
+            .map(|label| (label, true))
+            .chain(self.secondary.iter().map(|label| (label, false)))
+    }
+
+    pub fn to_json(&self, files: &Files) -> String {
+        let label_json = |label: &Label| {
+            let file = files.get(label.file);
+            let (start_line, start_column) = file.location(label.span.0);
+            let (end_line, end_column) = file.location(label.span.1);
+            format!(
+                "{{\"file\":{},\"byte_start\":{},\"byte_end\":{},\
+                \"line_start\":{start_line},\"column_start\":{start_column},\
+                \"line_end\":{end_line},\"column_end\":{end_column},\"label\":{}}}",
+                json_string(&file.path.to_string_lossy()),
+                label.span.0,
+                label.span.1,
+                json_string(&label.message),
+            )
+        };
+
+        let code = match self.code {
+            Some(code) => json_string(code.as_str()),
+            None => "null".to_owned(),
+        };
+        let primary = match &self.primary {
+            Some(label) => label_json(label),
+            None => "null".to_owned(),
+        };
+        let secondary = self.secondary.iter().map(label_json).collect::<Vec<_>>();
+        let notes = self
+            .notes
+            .iter()
+            .map(|note| json_string(note))
+            .collect::<Vec<_>>();
+
+        format!(
+            "{{\"severity\":{},\"code\":{code},\"message\":{},\
+            \"primary\":{primary},\"secondary\":[{}],\"notes\":[{}]}}",
+            json_string(&self.severity.to_string()),
+            json_string(&self.message),
+            secondary.join(","),
+            notes.join(","),
+        )
+    }
+
+    pub fn render(&self, files: &Files, mut output: impl Write) -> std::io::Result<()> {
+        match self.code {
+            Some(code) => writeln!(output, "{}[{code}]: {}", self.severity, self.message)?,
+            None => writeln!(output, "{}: {}", self.severity, self.message)?,
+        }
+
+        let max_line = self
+            .labels()
+            .map(|(label, _)| files.get(label.file).location(label.span.1).0)
+            .max()
+            .unwrap_or(0);
+        let gutter = " ".repeat(max_line.to_string().len());
+
+        // Group labels by file, keeping the file of the primary label first.
+        let mut groups = Vec::<(FileId, Vec<(&Label, bool)>)>::new();
+        for (label, is_primary) in self.labels() {
+            match groups.iter_mut().find(|(file, _)| *file == label.file) {
+                Some((_, labels)) => labels.push((label, is_primary)),
+                None => groups.push((label.file, vec![(label, is_primary)])),
+            }
+        }
+
+        for (file, labels) in &groups {
+            let source_file = files.get(*file);
+            let (first, is_primary) = labels[0];
+            let (line, column) = source_file.location(first.span.0);
+            let arrow = if is_primary { "-->" } else { ":::" };
+            writeln!(
+                output,
+                "{gutter}{arrow} {}:{line}:{column}",
+                source_file.path.display()
+            )?;
+            writeln!(output, "{gutter} |")?;
+            render_snippet(source_file, labels, &gutter, &mut output)?;
+        }
+
+        if !groups.is_empty() && !self.notes.is_empty() {
+            writeln!(output, "{gutter} |")?;
+        }
+        for note in &self.notes {
+            writeln!(output, "{gutter} = note: {note}")?;
+        }
+
+        writeln!(output)
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut json = String::with_capacity(value.len() + 2);
+    json.push('"');
+    for char in value.chars() {
+        match char {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            char if (char as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", char as u32)),
+            char => json.push(char),
+        }
+    }
+    json.push('"');
+    json
+}
+
+fn render_snippet(
+    file: &SourceFile,
+    labels: &[(&Label, bool)],
+    gutter: &str,
+    output: &mut impl Write,
+) -> std::io::Result<()> {
+    let mut lines = labels
+        .iter()
+        .flat_map(|(label, _)| {
+            let start = file.line_index(label.span.0);
+            let end = file.line_index(label.span.1.max(label.span.0 + 1) - 1);
+            start..=end.min(file.line_count() - 1)
+        })
+        .collect::<Vec<_>>();
+    lines.sort();
+    lines.dedup();
+
+    let mut previous_line = None;
+    for line in lines {
+        if previous_line.is_some_and(|previous| line > previous + 1) {
+            writeln!(output, "...")?;
+        }
+        previous_line = Some(line);
+
+        let text = file.line(line);
+        writeln!(
+            output,
+            "{:>width$} | {text}",
+            line + 1,
+            width = gutter.len()
+        )?;
+
+        for (label, is_primary) in labels {
+            let start_line = file.line_index(label.span.0);
+            let end_line = file.line_index(label.span.1.max(label.span.0 + 1) - 1);
+            if line < start_line || line > end_line {
+                continue;
+            }
+
+            let line_start = file.line_start(line);
+            let column = |offset: usize| {
+                let offset = offset.clamp(line_start, line_start + text.len());
+                text[..offset - line_start].chars().count()
+            };
+
+            let start = if line == start_line {
+                column(label.span.0)
+            } else {
+                text.len() - text.trim_start().len()
+            };
+            let end = if line == end_line {
+                column(label.span.1)
+            } else {
+                text.chars().count()
+            };
+
+            let marker = if *is_primary { "^" } else { "-" };
+            let mut underline =
+                " ".repeat(start) + &marker.repeat(end.saturating_sub(start).max(1));
+            if line == end_line && !label.message.is_empty() {
+                underline += " ";
+                underline += &label.message;
+            }
+            writeln!(output, "{gutter} | {underline}")?;
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Default)]
+pub struct DiagnosticSink {
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl DiagnosticSink {
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic);
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics
+            .iter()
+            .any(|diagnostic| diagnostic.severity == Severity::Error)
+    }
+
+    pub fn into_diagnostics(self) -> Vec<Diagnostic> {
+        self.diagnostics
+    }
+}
@@ -1,176 +1,526 @@
-use super::ast::{Block, Definition, Instruction, Program, Using};
-use super::error::{display_error_message, variable_span, Error};
+use super::ast::{Block, Definition, Expression, Instruction, Program, Span, Using};
+use super::builtin::{expand_builtin, Target};
+use super::diagnostic::{Diagnostic, DiagnosticSink, ErrorCode, FileId};
+use super::error::{expression_span, variable_span, Error, Result};
 use super::frame::{Frame, Lookup};
+use super::layout::Traces;
 use super::scope::Scope;
+use super::source_map::OriginTable;
 use crate::bf::{self, BF};
 
-fn evaluate_moving_block(output: &mut BF, block: &Block, scope: &Scope) -> bool {
-    let mut did_error = false;
+/// Largest count a `+` or `-` can repeat by.
+const MAX_COUNT: u64 = u16::MAX as u64;
+
+/// Deepest macro invocations can nest before they're assumed to recurse forever.
+const MAX_EXPANSION_DEPTH: usize = 128;
+
+/// Most macro invocations and `repeat` iterations a program can expand, and most instructions it
+/// can compile to, before it's assumed to never finish expanding.
+const MAX_EXPANSIONS: usize = 1 << 18;
+const MAX_INSTRUCTIONS: usize = 1 << 22;
+
+fn repetition_count(count: &Expression, value: Result<Option<i64>>) -> Result<i64> {
+    let Some(value) = value? else {
+        return Ok(0);
+    };
+
+    if value.unsigned_abs() > MAX_COUNT {
+        return Err(Error::new(
+            ErrorCode::CountOutOfRange,
+            expression_span(count),
+            format!("Repetition count must be at most {MAX_COUNT}, got {value}"),
+        ));
+    }
+
+    Ok(value)
+}
+
+/// Evaluate the count of a `repeat` or condition of an `if`, which must be known at compile
+/// time, or `None` if it has an error.
+fn evaluate_compile_time(
+    frame: &Frame,
+    expression: &Expression,
+    construct: Span,
+    scope: &Scope,
+    file: FileId,
+    diagnostics: &mut DiagnosticSink,
+) -> Option<i64> {
+    match frame.evaluate(expression, scope) {
+        Ok(value) => value,
+        Err(err)
+            if matches!(
+                err.code,
+                ErrorCode::UnknownConstant | ErrorCode::ExpectedInteger
+            ) =>
+        {
+            diagnostics.push(
+                Diagnostic::error(
+                    ErrorCode::NotConstant,
+                    "Expected a value known at compile time",
+                )
+                .with_primary(file, construct, "not known at compile time")
+                .with_label(file, err.span, "")
+                .with_note(err.message),
+            );
+            None
+        }
+        Err(err) => {
+            diagnostics.push(err.into_diagnostic(file));
+            None
+        }
+    }
+}
+
+/// The blocks a `repeat` or `if` expands to, in order.
+fn expand_compile_time<'a>(
+    instruction: &'a Instruction,
+    frame: &Frame,
+    scope: &Scope,
+    file: FileId,
+    diagnostics: &mut DiagnosticSink,
+) -> Vec<&'a Block> {
+    match instruction {
+        Instruction::Repeat(count, body, span) => {
+            let Some(value) = evaluate_compile_time(frame, count, *span, scope, file, diagnostics)
+            else {
+                return Vec::new();
+            };
+
+            if value < 0 || value.unsigned_abs() > MAX_COUNT {
+                diagnostics.push(
+                    Error::new(
+                        ErrorCode::CountOutOfRange,
+                        expression_span(count),
+                        format!("Repeat count must be between 0 and {MAX_COUNT}, got {value}"),
+                    )
+                    .into_diagnostic(file),
+                );
+                return Vec::new();
+            }
+
+            vec![body; value as usize]
+        }
+
+        Instruction::If(condition, then, otherwise, span) => {
+            match evaluate_compile_time(frame, condition, *span, scope, file, diagnostics) {
+                Some(0) => otherwise.iter().collect(),
+                Some(_) => vec![then],
+                None => Vec::new(),
+            }
+        }
+
+        _ => unreachable!("Only `repeat` and `if` are expanded at compile time"),
+    }
+}
+
+struct Output<'a> {
+    bf: BF,
+    origins: &'a mut OriginTable,
+    target: Target,
+    traces: Option<&'a mut Traces>,
+
+    /// Names of the macros currently being expanded, outermost first.
+    expansions: Vec<String>,
+
+    /// Number of macro invocations and `repeat` iterations expanded so far.
+    expansion_count: usize,
+
+    /// The error for the first expansion over a limit, after which nothing more is expanded.
+    /// It's kept until the end, as it may be hit while errors are being ignored.
+    expansion_limit: Option<Diagnostic>,
+}
+
+impl Output<'_> {
+    /// Count an expansion at `span`, or return false if the program has already expanded too
+    /// far to expand anything else.
+    fn expand(&mut self, file: FileId, span: Span) -> bool {
+        if self.expansion_limit.is_some() {
+            return false;
+        }
+
+        self.expansion_count += 1;
+        let message = if self.expansions.len() >= MAX_EXPANSION_DEPTH {
+            format!("Macro expansion is nested deeper than {MAX_EXPANSION_DEPTH} levels")
+        } else if self.expansion_count > MAX_EXPANSIONS {
+            format!("Macros and repeats are expanded more than {MAX_EXPANSIONS} times")
+        } else if self.bf.len() > MAX_INSTRUCTIONS {
+            format!("Program expands to more than {MAX_INSTRUCTIONS} instructions")
+        } else {
+            return true;
+        };
+
+        let mut diagnostic = Diagnostic::error(ErrorCode::RecursionLimit, message).with_primary(
+            file,
+            span,
+            "while expanding this",
+        );
+        if !self.expansions.is_empty() {
+            diagnostic = diagnostic.with_note(expansion_chain(&self.expansions));
+        }
+        self.expansion_limit = Some(diagnostic);
+        false
+    }
+
+    fn emit(
+        &mut self,
+        instruction: bf::Instruction,
+        file: FileId,
+        span: Span,
+        caller: Option<usize>,
+    ) {
+        let origin = self.origins.intern(file, span, caller, None);
+        self.bf.push_from(instruction, Some(origin));
+    }
+
+    /// Emit `up` `count` times, or `down` if the count is negative.
+    fn emit_many(
+        &mut self,
+        up: bf::Instruction,
+        down: bf::Instruction,
+        count: i64,
+        file: FileId,
+        span: Span,
+        caller: Option<usize>,
+    ) {
+        let instruction = if count < 0 { down } else { up };
+        for _ in 0..count.unsigned_abs() {
+            self.emit(instruction, file, span, caller);
+        }
+    }
+
+    fn start_trace(&mut self, frame: &Frame) {
+        if let Some(traces) = &mut self.traces {
+            traces
+                .entry(frame.name.clone())
+                .or_default()
+                .push(Vec::new());
+        }
+    }
+
+    fn trace(&mut self, frame: &Frame, offset: usize) {
+        if let Some(trace) = self
+            .traces
+            .as_mut()
+            .and_then(|traces| traces.get_mut(&frame.name))
+            .and_then(|traces| traces.last_mut())
+        {
+            trace.push(offset);
+        }
+    }
+}
+
+fn construct_span(instruction: &Instruction) -> Span {
+    match instruction {
+        Instruction::Repeat(_, _, span) | Instruction::If(_, _, _, span) => *span,
+        _ => unreachable!("Only `repeat` and `if` are expanded at compile time"),
+    }
+}
+
+/// Describe the chain of macros being expanded, collapsing runs of the same macro.
+fn expansion_chain(expansions: &[String]) -> String {
+    let mut runs = Vec::<(&str, usize)>::new();
+    for name in expansions {
+        match runs.last_mut() {
+            Some((last, count)) if *last == name => *count += 1,
+            _ => runs.push((name, 1)),
+        }
+    }
+
+    let runs = runs
+        .iter()
+        .map(|(name, count)| match count {
+            1 => format!("'{name}'"),
+            count => format!("'{name}' ({count} times)"),
+        })
+        .collect::<Vec<_>>();
+    format!("Expanded through {}", runs.join(" -> "))
+}
+
+fn evaluate_moving_block(
+    output: &mut Output,
+    frame: &Frame,
+    block: &Block,
+    scope: &Scope,
+    caller: Option<usize>,
+    diagnostics: &mut DiagnosticSink,
+) {
+    let file = block.file;
     let mut loop_depth = 0;
 
     for instruction in &block.instructions {
         match instruction {
-            Instruction::Add => output.push(bf::Instruction::Add),
-            Instruction::Subtract => output.push(bf::Instruction::Subtract),
-            Instruction::Left(_) => output.push(bf::Instruction::Left),
-            Instruction::Right(_) => output.push(bf::Instruction::Right),
-            Instruction::Input => output.push(bf::Instruction::Input),
-            Instruction::Output => output.push(bf::Instruction::Output),
-
-            Instruction::OpenLoop => {
+            Instruction::Add(span) => output.emit(bf::Instruction::Add, file, *span, caller),
+            Instruction::Subtract(span) => {
+                output.emit(bf::Instruction::Subtract, file, *span, caller)
+            }
+            Instruction::AddMany(span, count) => {
+                match repetition_count(count, frame.evaluate(count, scope)) {
+                    Ok(count) => output.emit_many(
+                        bf::Instruction::Add,
+                        bf::Instruction::Subtract,
+                        count,
+                        file,
+                        *span,
+                        caller,
+                    ),
+                    Err(err) => diagnostics.push(err.into_diagnostic(file)),
+                }
+            }
+            Instruction::SubtractMany(span, count) => {
+                match repetition_count(count, frame.evaluate(count, scope)) {
+                    Ok(count) => output.emit_many(
+                        bf::Instruction::Subtract,
+                        bf::Instruction::Add,
+                        count,
+                        file,
+                        *span,
+                        caller,
+                    ),
+                    Err(err) => diagnostics.push(err.into_diagnostic(file)),
+                }
+            }
+            Instruction::Left(span) => output.emit(bf::Instruction::Left, file, *span, caller),
+            Instruction::Right(span) => output.emit(bf::Instruction::Right, file, *span, caller),
+            Instruction::Input(span) => output.emit(bf::Instruction::Input, file, *span, caller),
+            Instruction::Output(span) => output.emit(bf::Instruction::Output, file, *span, caller),
+
+            Instruction::OpenLoop(span) => {
                 loop_depth += 1;
-                output.push(bf::Instruction::OpenLoop);
+                output.emit(bf::Instruction::OpenLoop, file, *span, caller);
             }
 
             Instruction::CloseLoop(span) => {
                 if loop_depth == 0 {
-                    display_error_message(
-                        &block.file_path,
-                        Error {
-                            span: *span,
-                            message: "Too many closing brackets".to_owned(),
-                        },
+                    diagnostics.push(
+                        Diagnostic::error(ErrorCode::UnbalancedLoop, "Too many closing brackets")
+                            .with_primary(block.file, *span, "no matching '['"),
                     );
                 } else {
                     loop_depth -= 1;
                 }
 
-                output.push(bf::Instruction::CloseLoop);
+                output.emit(bf::Instruction::CloseLoop, file, *span, caller);
             }
 
             Instruction::MovingBlock(block) => {
-                did_error |= evaluate_moving_block(output, block, scope);
+                evaluate_moving_block(output, frame, block, scope, caller, diagnostics);
             }
 
             Instruction::Using(using) => {
-                let (using_did_error, _) = evaluate_using(output, using, scope);
-                did_error |= using_did_error;
+                evaluate_using(output, using, scope, caller, diagnostics);
+            }
+
+            Instruction::Repeat(..) | Instruction::If(..) => {
+                let bodies = expand_compile_time(instruction, frame, scope, file, diagnostics);
+                let mut repeated = DiagnosticSink::default();
+                for (iteration, body) in bodies.into_iter().enumerate() {
+                    if !output.expand(file, construct_span(instruction)) {
+                        break;
+                    }
+
+                    // Each iteration has the same errors, so only report the first.
+                    let diagnostics = if iteration == 0 {
+                        &mut *diagnostics
+                    } else {
+                        &mut repeated
+                    };
+                    evaluate_moving_block(output, frame, body, scope, caller, diagnostics);
+                }
             }
 
             Instruction::Variable(variable) => {
-                did_error = true;
-                display_error_message(
-                    &block.file_path,
-                    Error {
-                        span: variable_span(variable),
-                        message: "Cannot access variables from inside a moving block".to_owned(),
-                    },
+                diagnostics.push(
+                    Diagnostic::error(
+                        ErrorCode::NamedAccessInMovingBlock,
+                        "Cannot access variables from inside a moving block",
+                    )
+                    .with_primary(block.file, variable_span(variable), "")
+                    .with_label(
+                        block.file,
+                        block.span,
+                        "inside this moving block",
+                    ),
                 );
             }
 
             Instruction::MacroInvoke(name, _) => {
-                did_error = true;
-                display_error_message(
-                    &block.file_path,
-                    Error {
-                        span: name.span,
-                        message: "Cannot access macros from inside a moving block".to_owned(),
-                    },
+                diagnostics.push(
+                    Diagnostic::error(
+                        ErrorCode::NamedAccessInMovingBlock,
+                        "Cannot access macros from inside a moving block",
+                    )
+                    .with_primary(block.file, name.span, "")
+                    .with_label(
+                        block.file,
+                        block.span,
+                        "inside this moving block",
+                    ),
                 );
             }
         }
     }
 
     if loop_depth > 0 {
-        display_error_message(
-            &block.file_path,
-            Error {
-                span: block.span,
-                message: "Too many open brackets".to_owned(),
-            },
+        diagnostics.push(
+            Diagnostic::error(ErrorCode::UnbalancedLoop, "Too many open brackets").with_primary(
+                block.file,
+                block.span,
+                "unclosed loop in this block",
+            ),
         );
     }
-
-    did_error
 }
 
 fn evaluate(
-    output: &mut BF,
+    output: &mut Output,
     frame: &Frame,
     frame_offset: usize,
     block: &Block,
     scope: &Scope,
-) -> (bool, usize) {
+    caller: Option<usize>,
+    diagnostics: &mut DiagnosticSink,
+) -> usize {
+    let file = block.file;
     let mut frame_offset = frame_offset;
-    let mut did_error = false;
-    let mut loop_stack = Vec::<usize>::new();
+    let mut loop_stack = Vec::<(usize, Span)>::new();
 
     for instruction in &block.instructions {
         match instruction {
-            Instruction::Add => output.push(bf::Instruction::Add),
-            Instruction::Subtract => output.push(bf::Instruction::Subtract),
-            Instruction::Input => output.push(bf::Instruction::Input),
-            Instruction::Output => output.push(bf::Instruction::Output),
-
-            Instruction::OpenLoop => {
-                loop_stack.push(frame_offset);
-                output.push(bf::Instruction::OpenLoop);
+            Instruction::Add(span) => output.emit(bf::Instruction::Add, file, *span, caller),
+            Instruction::Subtract(span) => {
+                output.emit(bf::Instruction::Subtract, file, *span, caller)
+            }
+            Instruction::AddMany(span, count) => {
+                match repetition_count(count, frame.evaluate(count, scope)) {
+                    Ok(count) => output.emit_many(
+                        bf::Instruction::Add,
+                        bf::Instruction::Subtract,
+                        count,
+                        file,
+                        *span,
+                        caller,
+                    ),
+                    Err(err) => diagnostics.push(err.into_diagnostic(file)),
+                }
+            }
+            Instruction::SubtractMany(span, count) => {
+                match repetition_count(count, frame.evaluate(count, scope)) {
+                    Ok(count) => output.emit_many(
+                        bf::Instruction::Subtract,
+                        bf::Instruction::Add,
+                        count,
+                        file,
+                        *span,
+                        caller,
+                    ),
+                    Err(err) => diagnostics.push(err.into_diagnostic(file)),
+                }
+            }
+            Instruction::Input(span) => output.emit(bf::Instruction::Input, file, *span, caller),
+            Instruction::Output(span) => output.emit(bf::Instruction::Output, file, *span, caller),
+
+            Instruction::OpenLoop(span) => {
+                loop_stack.push((frame_offset, *span));
+                output.emit(bf::Instruction::OpenLoop, file, *span, caller);
             }
 
             Instruction::CloseLoop(span) => {
                 match loop_stack.pop() {
-                    Some(loop_start_offset) => {
+                    Some((loop_start_offset, loop_start_span)) => {
                         if loop_start_offset != frame_offset {
-                            did_error = true;
-                            display_error_message(
-                                &block.file_path,
-                                Error {
-                                    span: *span,
-                                    message:
-                                        "Must exit a loop at the same pointer that you entered"
-                                            .to_owned(),
-                                },
+                            diagnostics.push(
+                                Diagnostic::error(
+                                    ErrorCode::LoopPointerMismatch,
+                                    "Must exit a loop at the same pointer that you entered",
+                                )
+                                .with_primary(
+                                    block.file,
+                                    *span,
+                                    format!("exited at offset {frame_offset}"),
+                                )
+                                .with_label(
+                                    block.file,
+                                    loop_start_span,
+                                    format!("entered at offset {loop_start_offset}"),
+                                ),
                             );
                         }
                     }
 
                     None => {
-                        did_error = true;
-                        display_error_message(
-                            &block.file_path,
-                            Error {
-                                span: *span,
-                                message: "Too many closing brackets".to_owned(),
-                            },
+                        diagnostics.push(
+                            Diagnostic::error(
+                                ErrorCode::UnbalancedLoop,
+                                "Too many closing brackets",
+                            )
+                            .with_primary(
+                                block.file,
+                                *span,
+                                "no matching '['",
+                            ),
                         );
                     }
                 }
 
-                output.push(bf::Instruction::CloseLoop);
+                output.emit(bf::Instruction::CloseLoop, file, *span, caller);
             }
 
             Instruction::Left(span) | Instruction::Right(span) => {
-                did_error = true;
-                display_error_message(
-                    &block.file_path,
-                    Error {
-                        span: span.clone(),
-                        message: "Can only use manual pointer movement inside a moving block"
-                            .to_owned(),
-                    },
+                diagnostics.push(
+                    Diagnostic::error(
+                        ErrorCode::ManualMovement,
+                        "Can only use manual pointer movement inside a moving block",
+                    )
+                    .with_primary(block.file, *span, ""),
                 );
             }
 
             Instruction::MovingBlock(block) => {
-                evaluate_moving_block(output, block, scope);
+                evaluate_moving_block(output, frame, block, scope, caller, diagnostics);
             }
 
             Instruction::Using(using) => {
-                let (using_did_error, using_frame_offset) = evaluate_using(output, using, scope);
-                frame_offset += using_frame_offset;
-                did_error |= using_did_error;
+                frame_offset += evaluate_using(output, using, scope, caller, diagnostics);
+            }
+
+            Instruction::Repeat(..) | Instruction::If(..) => {
+                let bodies = expand_compile_time(instruction, frame, scope, file, diagnostics);
+                let mut repeated = DiagnosticSink::default();
+                for (iteration, body) in bodies.into_iter().enumerate() {
+                    if !output.expand(file, construct_span(instruction)) {
+                        break;
+                    }
+
+                    // Each iteration has the same errors, so only report the first.
+                    let diagnostics = if iteration == 0 {
+                        &mut *diagnostics
+                    } else {
+                        &mut repeated
+                    };
+                    frame_offset = evaluate(
+                        output,
+                        frame,
+                        frame_offset,
+                        body,
+                        scope,
+                        caller,
+                        diagnostics,
+                    );
+                }
             }
 
             Instruction::Variable(variable) => {
-                match frame.lookup(&variable) {
+                let span = variable_span(variable);
+                match frame.lookup(variable) {
                     Ok(Lookup::Slot(offset)) => {
+                        output.trace(frame, offset);
                         if offset > frame_offset {
                             for _ in frame_offset..offset {
-                                output.push(bf::Instruction::Left);
+                                output.emit(bf::Instruction::Right, file, span, caller);
                             }
                         } else if offset < frame_offset {
                             for _ in offset..frame_offset {
-                                output.push(bf::Instruction::Right);
+                                output.emit(bf::Instruction::Left, file, span, caller);
                             }
                         }
 
@@ -178,92 +528,162 @@ fn evaluate(
                     }
 
                     Ok(Lookup::Block(block, frame)) => {
-                        let (block_did_error, block_frame_offset) =
-                            evaluate(output, &frame, frame_offset, &block, scope);
-                        frame_offset = block_frame_offset;
-                        did_error |= block_did_error;
+                        let call = output.origins.intern(file, span, caller, None);
+                        frame_offset = evaluate(
+                            output,
+                            &frame,
+                            frame_offset,
+                            &block,
+                            scope,
+                            Some(call),
+                            diagnostics,
+                        );
                     }
 
-                    Err(err) => {
-                        did_error = true;
-                        display_error_message(&block.file_path, err);
-                    }
+                    Err(err) => diagnostics.push(err.into_diagnostic(block.file)),
                 };
             }
 
             Instruction::MacroInvoke(name, arguments) => {
                 let macro_ = scope.macro_(&name.value);
                 if macro_.is_none() {
-                    did_error = true;
-                    display_error_message(
-                        &block.file_path,
-                        Error {
-                            span: name.span,
-                            message: format!("Error: No macro '{}' found", name.value),
-                        },
-                    );
+                    match expand_builtin(name, arguments, file, &output.target) {
+                        Some(Ok(builtin)) => {
+                            let call =
+                                output
+                                    .origins
+                                    .intern(file, name.span, caller, Some(&name.value));
+                            frame_offset = evaluate(
+                                output,
+                                frame,
+                                frame_offset,
+                                &builtin,
+                                scope,
+                                Some(call),
+                                diagnostics,
+                            );
+                        }
+
+                        Some(Err(err)) => diagnostics.push(err.into_diagnostic(block.file)),
+                        None => diagnostics.push(
+                            Diagnostic::error(
+                                ErrorCode::UnknownMacro,
+                                format!("No macro '{}' found", name.value),
+                            )
+                            .with_primary(
+                                block.file,
+                                name.span,
+                                "not found",
+                            ),
+                        ),
+                    }
                     continue;
                 }
 
                 let macro_ = macro_.unwrap();
-                match frame.macro_frame(&macro_.name, &macro_.parameters, &arguments) {
+                if !output.expand(file, name.span) {
+                    continue;
+                }
+
+                match frame.macro_frame(&macro_.name, &macro_.parameters, arguments, scope) {
                     Ok(frame) => {
-                        let (macro_did_error, macro_frame_offset) =
-                            evaluate(output, &frame, frame_offset, &macro_.block, scope);
-                        frame_offset = macro_frame_offset;
-                        did_error |= macro_did_error;
+                        let call =
+                            output
+                                .origins
+                                .intern(file, name.span, caller, Some(&name.value));
+                        output.expansions.push(name.value.clone());
+                        frame_offset = evaluate(
+                            output,
+                            &frame,
+                            frame_offset,
+                            &macro_.block,
+                            scope,
+                            Some(call),
+                            diagnostics,
+                        );
+                        output.expansions.pop();
                     }
 
                     Err(err) => {
-                        did_error = true;
-                        display_error_message(&block.file_path, err);
+                        let diagnostic = err.into_diagnostic(block.file);
+                        diagnostics.push(diagnostic.with_label(
+                            macro_.block.file,
+                            macro_.name.span,
+                            "macro defined here",
+                        ));
                     }
                 }
             }
         }
     }
 
-    if !loop_stack.is_empty() {
-        display_error_message(
-            &block.file_path,
-            Error {
-                span: block.span,
-                message: "Too many open brackets".to_owned(),
-            },
+    for (_, span) in loop_stack {
+        diagnostics.push(
+            Diagnostic::error(ErrorCode::UnbalancedLoop, "Too many open brackets").with_primary(
+                block.file,
+                span,
+                "this loop is never closed",
+            ),
         );
     }
 
-    (did_error, frame_offset)
+    frame_offset
 }
 
-fn evaluate_using(output: &mut BF, using: &Using, scope: &Scope) -> (bool, usize) {
+fn evaluate_using(
+    output: &mut Output,
+    using: &Using,
+    scope: &Scope,
+    caller: Option<usize>,
+    diagnostics: &mut DiagnosticSink,
+) -> usize {
     let frame_definition = scope.frame_definition(&using.frame.value);
     if frame_definition.is_none() {
-        display_error_message(
-            &using.block.file_path,
-            Error {
-                span: using.frame.span,
-                message: format!("Error: No frame '{}' found", using.frame.value),
-            },
+        diagnostics.push(
+            Diagnostic::error(
+                ErrorCode::UnknownFrame,
+                format!("No frame '{}' found", using.frame.value),
+            )
+            .with_primary(using.block.file, using.frame.span, "not found"),
         );
-        return (true, 0);
+        return 0;
     }
 
-    let frame = Frame::from_definition(frame_definition.unwrap(), scope);
-    evaluate(output, &frame, 0, &using.block, scope)
+    let Some(frame) = Frame::from_definition(frame_definition.unwrap(), scope, diagnostics) else {
+        return 0;
+    };
+
+    output.start_trace(&frame);
+    evaluate(output, &frame, 0, &using.block, scope, caller, diagnostics)
 }
 
-pub fn evaluate_program(program: &Program) -> std::io::Result<(BF, bool)> {
-    let mut output = BF::new();
-    let mut did_error = false;
+pub fn evaluate_program(
+    program: &Program,
+    scope: &Scope,
+    target: Target,
+    origins: &mut OriginTable,
+    traces: Option<&mut Traces>,
+    diagnostics: &mut DiagnosticSink,
+) -> BF {
+    let mut output = Output {
+        bf: BF::new(),
+        origins,
+        target,
+        traces,
+        expansions: Vec::new(),
+        expansion_count: 0,
+        expansion_limit: None,
+    };
 
-    let scope = Scope::new(program, &std::env::current_dir()?)?;
     for definition in program {
         if let Definition::Using(using) = definition {
-            let (using_did_error, _) = evaluate_using(&mut output, using, &scope);
-            did_error |= using_did_error;
+            evaluate_using(&mut output, using, scope, None, diagnostics);
         }
     }
 
-    Ok((output, did_error))
+    if let Some(diagnostic) = output.expansion_limit {
+        diagnostics.push(diagnostic);
+    }
+
+    output.bf
 }
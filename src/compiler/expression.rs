@@ -0,0 +1,66 @@
+use super::ast::{Expression, Identifier, Operator};
+use super::diagnostic::ErrorCode;
+use super::error::{expression_span, Error, Result};
+
+/// Looks up the values of the constants an expression uses.
+pub trait Constants {
+    /// The value of a constant, or `None` if it has an error that's already been reported.
+    fn constant(&mut self, name: &Identifier) -> Result<Option<i64>>;
+}
+
+/// Evaluate an expression, or `None` if it uses a constant with an error.
+pub fn evaluate_expression(
+    expression: &Expression,
+    constants: &mut impl Constants,
+) -> Result<Option<i64>> {
+    match expression {
+        Expression::Number(value, _) => Ok(Some(*value)),
+        Expression::Constant(name) => constants.constant(name),
+
+        Expression::Negate(value, span) => {
+            let Some(value) = evaluate_expression(value, constants)? else {
+                return Ok(None);
+            };
+
+            value
+                .checked_neg()
+                .map(Some)
+                .ok_or_else(|| Error::new(ErrorCode::Arithmetic, *span, "Arithmetic overflow"))
+        }
+
+        Expression::Binary(left, operator, right, span) => {
+            let Some(left) = evaluate_expression(left, constants)? else {
+                return Ok(None);
+            };
+            let divisor = expression_span(right);
+            let Some(right) = evaluate_expression(right, constants)? else {
+                return Ok(None);
+            };
+
+            let value = match operator {
+                Operator::Add => left.checked_add(right),
+                Operator::Subtract => left.checked_sub(right),
+                Operator::Multiply => left.checked_mul(right),
+                Operator::Divide | Operator::Remainder if right == 0 => {
+                    return Err(Error::new(
+                        ErrorCode::Arithmetic,
+                        divisor,
+                        "Division by zero",
+                    ));
+                }
+                Operator::Divide => left.checked_div(right),
+                Operator::Remainder => left.checked_rem(right),
+                Operator::Equal => Some((left == right) as i64),
+                Operator::NotEqual => Some((left != right) as i64),
+                Operator::Less => Some((left < right) as i64),
+                Operator::LessOrEqual => Some((left <= right) as i64),
+                Operator::Greater => Some((left > right) as i64),
+                Operator::GreaterOrEqual => Some((left >= right) as i64),
+            };
+
+            value
+                .map(Some)
+                .ok_or_else(|| Error::new(ErrorCode::Arithmetic, *span, "Arithmetic overflow"))
+        }
+    }
+}
@@ -1,5 +1,9 @@
-use super::ast::{Argument, Block, FrameDefinition, Identifier, Parameter, SlotDefinition};
+use super::ast::{
+    Argument, Block, Expression, FrameDefinition, Identifier, Parameter, SlotDefinition,
+};
+use super::diagnostic::{Diagnostic, DiagnosticSink, ErrorCode};
 use super::error::{argument_span, arguments_span, Error, Result};
+use super::expression::{evaluate_expression, Constants};
 use super::scope::Scope;
 use std::collections::HashMap;
 
@@ -13,6 +17,9 @@ pub struct Frame {
 enum Symbol {
     Slot(Slot),
     Block(Block, Frame),
+
+    /// An integer parameter, or `None` if its argument had an error.
+    Integer(Option<i64>),
 }
 
 #[derive(Debug, Clone)]
@@ -21,16 +28,80 @@ struct Slot {
     sub_frame: Option<Frame>,
 }
 
+#[derive(Debug, Clone)]
+pub struct SlotLayout {
+    pub name: String,
+    pub offset: usize,
+}
+
+/// The cells of a frame, with sub-frames flattened into `name.sub_name` slots.
+#[derive(Debug, Clone)]
+pub struct FrameLayout {
+    pub name: String,
+    pub size: usize,
+    pub slots: Vec<SlotLayout>,
+}
+
+impl FrameLayout {
+    pub fn slot(&self, name: &str) -> Option<&SlotLayout> {
+        self.slots.iter().find(|slot| slot.name == name)
+    }
+
+    pub fn slot_at(&self, offset: usize) -> Option<&SlotLayout> {
+        self.slots.iter().find(|slot| slot.offset == offset)
+    }
+}
+
+/// Integer parameters of a frame, falling back to the constants in scope.
+struct FrameConstants<'a> {
+    frame: &'a Frame,
+    scope: &'a Scope,
+}
+
+impl Constants for FrameConstants<'_> {
+    fn constant(&mut self, name: &Identifier) -> Result<Option<i64>> {
+        match self.frame.symbols.get(&name.value) {
+            Some(Symbol::Integer(value)) => Ok(*value),
+            Some(_) => Err(Error::new(
+                ErrorCode::ExpectedInteger,
+                name.span,
+                format!("Expected symbol '{}' to be an integer", name.value),
+            )),
+            None => self.scope.evaluate(&Expression::Constant(name.clone())),
+        }
+    }
+}
+
 pub enum Lookup {
     Slot(usize),
     Block(Block, Frame),
 }
 
 impl Frame {
-    pub fn from_definition(definition: &FrameDefinition, scope: &Scope) -> Self {
+    pub fn from_definition(
+        definition: &FrameDefinition,
+        scope: &Scope,
+        diagnostics: &mut DiagnosticSink,
+    ) -> Option<Self> {
+        Self::from_definition_within(definition, scope, &mut Vec::new(), diagnostics)
+    }
+
+    fn from_definition_within(
+        definition: &FrameDefinition,
+        scope: &Scope,
+        parents: &mut Vec<String>,
+        diagnostics: &mut DiagnosticSink,
+    ) -> Option<Self> {
+        parents.push(definition.name.value.clone());
+
         let mut symbols = HashMap::new();
+        let slots: Vec<&SlotDefinition> = match scope.slot_order(&definition.name.value) {
+            Some(order) => order.iter().map(|&slot| &definition.slots[slot]).collect(),
+            None => definition.slots.iter().collect(),
+        };
+
         let mut index = 0;
-        for slot in &definition.slots {
+        for slot in slots {
             match slot {
                 SlotDefinition::Variable(name) => {
                     symbols.insert(
@@ -45,13 +116,43 @@ impl Frame {
                 }
 
                 SlotDefinition::SubFrame(name, frame) => {
-                    let sub_frame_definition =
-                        scope.frame_definition(&frame.value).unwrap_or_else(|| {
-                            panic!("Error: No frame '{}' found", frame.value);
-                        });
+                    let Some(sub_frame_definition) = scope.frame_definition(&frame.value) else {
+                        diagnostics.push(
+                            Diagnostic::error(
+                                ErrorCode::UnknownFrame,
+                                format!("No frame '{}' found", frame.value),
+                            )
+                            .with_primary(
+                                definition.file,
+                                frame.span,
+                                "not found",
+                            ),
+                        );
+                        return None;
+                    };
+
+                    if parents.contains(&frame.value) {
+                        diagnostics.push(
+                            Diagnostic::error(
+                                ErrorCode::RecursiveFrame,
+                                format!("Frame '{}' contains itself", frame.value),
+                            )
+                            .with_primary(definition.file, frame.span, "recursive sub-frame")
+                            .with_note(format!(
+                                "frame path: {} -> {}",
+                                parents.join(" -> "),
+                                frame.value
+                            )),
+                        );
+                        return None;
+                    }
 
-                    // TODO: Detect cycles.
-                    let sub_frame = Frame::from_definition(sub_frame_definition, scope);
+                    let sub_frame = Frame::from_definition_within(
+                        sub_frame_definition,
+                        scope,
+                        parents,
+                        diagnostics,
+                    )?;
                     let sub_frame_size = sub_frame.size();
                     symbols.insert(
                         name.value.clone(),
@@ -66,9 +167,51 @@ impl Frame {
             }
         }
 
-        Self {
+        parents.pop();
+        Some(Self {
             name: definition.name.value.clone(),
             symbols,
+        })
+    }
+
+    /// Evaluate an expression using the integer parameters of the frame and the constants in
+    /// scope, or `None` if it uses a constant with an error.
+    pub fn evaluate(&self, expression: &Expression, scope: &Scope) -> Result<Option<i64>> {
+        let mut constants = FrameConstants { frame: self, scope };
+        evaluate_expression(expression, &mut constants)
+    }
+
+    fn evaluate_integer_argument(
+        &self,
+        name: &Identifier,
+        argument: &Argument,
+        scope: &Scope,
+    ) -> Result<Option<i64>> {
+        let not_an_integer = || {
+            Error::new(
+                ErrorCode::ArgumentType,
+                argument_span(argument),
+                format!(
+                    "Argument to integer parameter '{}' must be an integer",
+                    name.value
+                ),
+            )
+        };
+
+        match argument {
+            Argument::Integer(expression) => self.evaluate(expression, scope),
+            Argument::Variable(variable) if variable.len() == 1 => {
+                if let Some(Symbol::Slot(_) | Symbol::Block(..)) =
+                    self.symbols.get(&variable[0].value)
+                {
+                    return Err(not_an_integer());
+                }
+
+                self.evaluate(&Expression::Constant(variable[0].clone()), scope)
+            }
+            Argument::Variable(_) | Argument::Block(_) | Argument::String(..) => {
+                Err(not_an_integer())
+            }
         }
     }
 
@@ -76,7 +219,13 @@ impl Frame {
         &self,
         parameter: &Parameter,
         argument: &Argument,
+        scope: &Scope,
     ) -> Result<(String, Symbol)> {
+        if let Parameter::Integer(name) = parameter {
+            let value = self.evaluate_integer_argument(name, argument, scope)?;
+            return Ok((name.value.clone(), Symbol::Integer(value)));
+        }
+
         match argument {
             Argument::Variable(variable) => {
                 let (slot, index) = self.slot(variable)?;
@@ -91,24 +240,23 @@ impl Frame {
 
                     Parameter::SubFrame(name, sub_frame) => {
                         if slot.sub_frame.is_none() {
-                            return Err(Error {
-                                span: argument_span(argument),
-                                message: format!(
-                                    "Argument must have a sub-frame of '{}'",
-                                    sub_frame.value
-                                ),
-                            });
+                            return Err(Error::new(
+                                ErrorCode::ArgumentType,
+                                argument_span(argument),
+                                format!("Argument must have a sub-frame of '{}'", sub_frame.value),
+                            ));
                         }
 
                         let argument_sub_frame = slot.sub_frame.as_ref().unwrap();
                         if argument_sub_frame.name != sub_frame.value {
-                            return Err(Error {
-                                span: argument_span(argument),
-                                message: format!(
+                            return Err(Error::new(
+                                ErrorCode::ArgumentType,
+                                argument_span(argument),
+                                format!(
                                     "Argument must have a sub-frame of '{}', got '{}'",
                                     sub_frame.value, argument_sub_frame.name,
                                 ),
-                            });
+                            ));
                         }
 
                         Ok((
@@ -120,10 +268,13 @@ impl Frame {
                         ))
                     }
 
-                    Parameter::Block(name) => Err(Error {
-                        span: argument_span(argument),
-                        message: format!("Can only pass a block to parameter '{}'", name.value),
-                    }),
+                    Parameter::Block(name) => Err(Error::new(
+                        ErrorCode::ArgumentType,
+                        argument_span(argument),
+                        format!("Can only pass a block to parameter '{}'", name.value),
+                    )),
+
+                    Parameter::Integer(_) => unreachable!("Integer parameters are handled above"),
                 }
             }
 
@@ -133,10 +284,41 @@ impl Frame {
                     Symbol::Block(block.clone(), self.clone()),
                 )),
 
-                Parameter::Slot(name) | Parameter::SubFrame(name, _) => Err(Error {
-                    span: argument_span(argument),
-                    message: format!("Can not pass a block to slot parameter '{}'", name.value),
-                }),
+                Parameter::Slot(name) | Parameter::SubFrame(name, _) => Err(Error::new(
+                    ErrorCode::ArgumentType,
+                    argument_span(argument),
+                    format!("Can not pass a block to slot parameter '{}'", name.value),
+                )),
+
+                Parameter::Integer(_) => unreachable!("Integer parameters are handled above"),
+            },
+
+            Argument::String(..) => {
+                let (Parameter::Slot(name)
+                | Parameter::SubFrame(name, _)
+                | Parameter::Block(name)
+                | Parameter::Integer(name)) = parameter;
+                Err(Error::new(
+                    ErrorCode::ArgumentType,
+                    argument_span(argument),
+                    format!("Can not pass a string to parameter '{}'", name.value),
+                ))
+            }
+
+            Argument::Integer(_) => match parameter {
+                Parameter::Block(name) => Err(Error::new(
+                    ErrorCode::ArgumentType,
+                    argument_span(argument),
+                    format!("Can only pass a block to parameter '{}'", name.value),
+                )),
+
+                Parameter::Slot(name) | Parameter::SubFrame(name, _) => Err(Error::new(
+                    ErrorCode::ArgumentType,
+                    argument_span(argument),
+                    format!("Can not pass an integer to slot parameter '{}'", name.value),
+                )),
+
+                Parameter::Integer(_) => unreachable!("Integer parameters are handled above"),
             },
         }
     }
@@ -146,21 +328,23 @@ impl Frame {
         name: &Identifier,
         parameters: &[Parameter],
         arguments: &[Argument],
+        scope: &Scope,
     ) -> Result<Self> {
         if parameters.len() != arguments.len() {
-            return Err(Error {
-                span: arguments_span(arguments).unwrap_or(name.span),
-                message: format!(
+            return Err(Error::new(
+                ErrorCode::ArgumentCount,
+                arguments_span(arguments).unwrap_or(name.span),
+                format!(
                     "Macro expected {} arguments, got {}",
                     parameters.len(),
                     arguments.len()
                 ),
-            });
+            ));
         }
 
         let mut symbols = HashMap::new();
         for (parameter, argument) in parameters.iter().zip(arguments) {
-            let (name, symbol) = self.evaluate_macro_parameter(parameter, argument)?;
+            let (name, symbol) = self.evaluate_macro_parameter(parameter, argument, scope)?;
             symbols.insert(name, symbol);
         }
 
@@ -171,24 +355,30 @@ impl Frame {
     }
 
     fn slot(&self, path: &[Identifier]) -> Result<(&Slot, usize)> {
-        if path.len() == 0 {
+        if path.is_empty() {
             panic!("Path must have at least one element");
         }
 
         let name = &path[0];
-        let symbol = self.symbols.get(&name.value).ok_or(Error {
-            span: name.span,
-            message: format!(
-                "No symbol with the name '{}' found in frame '{}'",
-                name.value, self.name
-            ),
+        let symbol = self.symbols.get(&name.value).ok_or_else(|| {
+            Error::new(
+                ErrorCode::UnknownSymbol,
+                name.span,
+                format!(
+                    "No symbol with the name '{}' found in frame '{}'",
+                    name.value, self.name
+                ),
+            )
         })?;
 
         if let Symbol::Slot(slot) = symbol {
             if path.len() > 1 {
-                let sub_frame = slot.sub_frame.as_ref().ok_or(Error {
-                    span: name.span,
-                    message: "Must be a sub frame to use `.`".to_owned(),
+                let sub_frame = slot.sub_frame.as_ref().ok_or_else(|| {
+                    Error::new(
+                        ErrorCode::NotASubFrame,
+                        name.span,
+                        "Must be a sub frame to use `.`",
+                    )
                 })?;
 
                 let (sub_slot, sub_index) = sub_frame.slot(&path[1..])?;
@@ -197,40 +387,75 @@ impl Frame {
                 Ok((slot, slot.index))
             }
         } else {
-            Err(Error {
-                span: name.span,
-                message: format!("Expected symbol '{}' to be a slot", name.value),
-            })
+            Err(Error::new(
+                ErrorCode::ExpectedSlot,
+                name.span,
+                format!("Expected symbol '{}' to be a slot", name.value),
+            ))
         }
     }
 
     pub fn lookup(&self, path: &[Identifier]) -> Result<Lookup> {
-        if path.len() == 0 {
+        if path.is_empty() {
             panic!("Path must have at least one element");
         }
 
         let name = &path[0];
-        let symbol = self.symbols.get(&name.value).ok_or(Error {
-            span: name.span,
-            message: format!(
-                "No symbol with the name '{}' found in frame '{}'",
-                name.value, self.name
-            ),
+        let symbol = self.symbols.get(&name.value).ok_or_else(|| {
+            Error::new(
+                ErrorCode::UnknownSymbol,
+                name.span,
+                format!(
+                    "No symbol with the name '{}' found in frame '{}'",
+                    name.value, self.name
+                ),
+            )
         })?;
 
         Ok(match symbol {
             Symbol::Block(block, frame) => Lookup::Block(block.clone(), frame.clone()),
-            Symbol::Slot(_) => {
+            Symbol::Slot(_) | Symbol::Integer(_) => {
                 let (_, index) = self.slot(path)?;
                 Lookup::Slot(index)
             }
         })
     }
 
+    pub fn layout(&self) -> FrameLayout {
+        let mut slots = Vec::new();
+        self.collect_slots("", 0, &mut slots);
+        slots.sort_by_key(|slot| slot.offset);
+
+        FrameLayout {
+            name: self.name.clone(),
+            size: self.size(),
+            slots,
+        }
+    }
+
+    fn collect_slots(&self, prefix: &str, base: usize, slots: &mut Vec<SlotLayout>) {
+        for (name, symbol) in &self.symbols {
+            let Symbol::Slot(slot) = symbol else {
+                continue;
+            };
+
+            let name = format!("{prefix}{name}");
+            match &slot.sub_frame {
+                Some(sub_frame) => {
+                    sub_frame.collect_slots(&format!("{name}."), base + slot.index, slots)
+                }
+                None => slots.push(SlotLayout {
+                    name,
+                    offset: base + slot.index,
+                }),
+            }
+        }
+    }
+
     pub fn size(&self) -> usize {
         self.symbols
-            .iter()
-            .map(|(_, symbol)| {
+            .values()
+            .map(|symbol| {
                 if let Symbol::Slot(slot) = symbol {
                     if let Some(frame) = &slot.sub_frame {
                         slot.index + frame.size()
@@ -0,0 +1,185 @@
+use super::ast::{FrameDefinition, SlotDefinition};
+use super::diagnostic::DiagnosticSink;
+use super::frame::Frame;
+use super::scope::Scope;
+use std::collections::HashMap;
+
+/// The offsets each `using` block of a frame moved to, including inside the macros it invoked,
+/// by frame name.
+pub type Traces = HashMap<String, Vec<Vec<usize>>>;
+
+/// A cell of the frame, as a slot definition and offset inside it, or `None` for the cell a
+/// `using` block starts at.
+type Point = Option<(usize, usize)>;
+
+/// How far the pointer moves following every trace, with the slots laid out in `order`.
+fn distance(order: &[usize], sizes: &[usize], moves: &[(Point, Point, usize)]) -> usize {
+    let mut bases = vec![0; sizes.len()];
+    let mut base = 0;
+    for &slot in order {
+        bases[slot] = base;
+        base += sizes[slot];
+    }
+
+    let position = |point: Point| point.map_or(0, |(slot, inner)| bases[slot] + inner);
+    moves
+        .iter()
+        .map(|(from, to, count)| position(*from).abs_diff(position(*to)) * count)
+        .sum()
+}
+
+/// Move single slots while that shortens the distance.
+fn improve(order: &mut Vec<usize>, sizes: &[usize], moves: &[(Point, Point, usize)]) -> usize {
+    let mut best = distance(order, sizes, moves);
+    let mut improved = true;
+    while improved {
+        improved = false;
+        for from in 0..order.len() {
+            for to in 0..order.len() {
+                if from == to {
+                    continue;
+                }
+
+                let mut candidate = order.clone();
+                let slot = candidate.remove(from);
+                candidate.insert(to, slot);
+
+                let cost = distance(&candidate, sizes, moves);
+                if cost < best {
+                    *order = candidate;
+                    best = cost;
+                    improved = true;
+                }
+            }
+        }
+    }
+
+    best
+}
+
+/// Order the slot definitions of a frame to minimise how far the pointer moves following
+/// `traces`, which were recorded with the slots in declaration order. Sub-frames are moved as a
+/// whole, so stay contiguous.
+fn slot_order(
+    definition: &FrameDefinition,
+    scope: &Scope,
+    traces: &[Vec<usize>],
+) -> Option<Vec<usize>> {
+    let mut sizes = Vec::new();
+    for slot in &definition.slots {
+        match slot {
+            SlotDefinition::Variable(_) => sizes.push(1),
+            SlotDefinition::SubFrame(_, frame) => {
+                let definition = scope.frame_definition(&frame.value)?;
+                let mut ignored = DiagnosticSink::default();
+                sizes.push(Frame::from_definition(definition, scope, &mut ignored)?.size());
+            }
+        }
+    }
+
+    let cells: Vec<(usize, usize)> = sizes
+        .iter()
+        .enumerate()
+        .flat_map(|(slot, size)| (0..*size).map(move |inner| (slot, inner)))
+        .collect();
+
+    let mut counts = HashMap::<(Point, Point), usize>::new();
+    let mut first_use = Vec::new();
+    for trace in traces {
+        let mut previous = None;
+        for &offset in trace {
+            let point = Some(*cells.get(offset)?);
+            *counts.entry((previous, point)).or_default() += 1;
+            previous = point;
+
+            let (slot, _) = cells[offset];
+            if !first_use.contains(&slot) {
+                first_use.push(slot);
+            }
+        }
+    }
+
+    let mut moves: Vec<_> = counts
+        .into_iter()
+        .map(|((from, to), count)| (from, to, count))
+        .collect();
+    moves.sort();
+
+    // Start from declaration order, and from the order slots are first used in, keeping
+    // declaration order unless the other is shorter.
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    let best = improve(&mut order, &sizes, &moves);
+
+    let unused = (0..sizes.len()).filter(|slot| !first_use.contains(slot));
+    let mut by_use: Vec<usize> = first_use.iter().copied().chain(unused).collect();
+    if improve(&mut by_use, &sizes, &moves) < best {
+        order = by_use;
+    }
+
+    Some(order)
+}
+
+/// Choose the slot order of every `auto` frame, from traces of evaluating the program with
+/// frames in declaration order.
+pub fn choose_slot_orders(scope: &mut Scope, traces: &Traces) {
+    let orders: Vec<(String, Vec<usize>)> = scope
+        .frame_definitions()
+        .filter(|definition| definition.auto)
+        .filter_map(|definition| {
+            let traces = traces
+                .get(&definition.name.value)
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            let order = slot_order(definition, scope, traces)?;
+            Some((definition.name.value.clone(), order))
+        })
+        .collect();
+
+    for (name, order) in orders {
+        scope.set_slot_order(name, order);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn improve_moves_slots_used_together_next_to_each_other() {
+        // Start at slot 2, then go back and forth between slots 2 and 0.
+        let (first, last) = (Some((0, 0)), Some((2, 0)));
+        let moves = [(None, last, 1), (first, last, 5), (last, first, 5)];
+        let sizes = [1, 1, 1];
+
+        let mut order = vec![0, 1, 2];
+        assert_eq!(distance(&order, &sizes, &moves), 22);
+        assert_eq!(improve(&mut order, &sizes, &moves), 10);
+        assert_eq!(order, [2, 0, 1]);
+        assert_eq!(distance(&order, &sizes, &moves), 10);
+    }
+
+    #[test]
+    fn improve_keeps_an_order_it_can_not_shorten() {
+        let moves = [(None, Some((0, 0)), 1), (Some((0, 0)), Some((1, 0)), 3)];
+        let sizes = [1, 1, 1];
+
+        let mut order = vec![0, 1, 2];
+        assert_eq!(improve(&mut order, &sizes, &moves), 3);
+        assert_eq!(order, [0, 1, 2]);
+    }
+
+    #[test]
+    fn improve_accounts_for_the_size_of_sub_frames() {
+        // Slot 0 is a sub-frame of three cells between the two slots used together.
+        let moves = [
+            (Some((1, 0)), Some((2, 0)), 4),
+            (Some((2, 0)), Some((1, 0)), 4),
+        ];
+        let sizes = [3, 1, 1];
+
+        let mut order = vec![1, 0, 2];
+        assert_eq!(distance(&order, &sizes, &moves), 32);
+        assert_eq!(improve(&mut order, &sizes, &moves), 8);
+        assert_eq!(distance(&order, &sizes, &moves), 8);
+    }
+}
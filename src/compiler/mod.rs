@@ -1,32 +1,182 @@
-use crate::bf::BF;
-use ast::set_program_file_path;
+use crate::bf::{Eof, BF};
+use crate::simplify::simplify_program;
+use crate::source_map::SourceMap;
+use builtin::Target;
 use evaluate::evaluate_program;
-use std::fs::File;
-use std::io::Read;
-use std::path::PathBuf;
+use frame::Frame;
+use layout::{choose_slot_orders, Traces};
+use parse::parse_program;
+use scope::Scope;
+use source_map::OriginTable;
+use std::collections::HashMap;
+use std::path::Path;
+
+pub use diagnostic::{
+    Diagnostic, DiagnosticSink, ErrorCode, FileId, Files, Label, Severity, SourceFile,
+};
+pub use frame::{FrameLayout, SlotLayout};
+pub use resolver::{FileResolver, IncludeResolver};
 
 use lalrpop_util::lalrpop_mod;
-lalrpop_mod!(pub macro_parser);
+lalrpop_mod!(#[allow(clippy::all)] pub macro_parser);
 
 mod ast;
+mod builtin;
+mod diagnostic;
 mod error;
 mod evaluate;
+mod expression;
 mod frame;
+mod layout;
+mod parse;
+mod resolver;
 mod scope;
+mod source_map;
+
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub simplify: bool,
+
+    /// What `,` does at the end of input on the target, used by the built-in `read` macro.
+    pub eof: Eof,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            simplify: true,
+            eof: Eof::default(),
+        }
+    }
+}
+
+pub struct Compiler {
+    resolver: Box<dyn IncludeResolver>,
+    options: Options,
+    files: Files,
+    origins: OriginTable,
+    frame_layouts: HashMap<String, FrameLayout>,
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Self {
+            resolver: Box::new(FileResolver),
+            options: Options::default(),
+            files: Files::default(),
+            origins: OriginTable::default(),
+            frame_layouts: HashMap::new(),
+        }
+    }
+
+    pub fn with_resolver(mut self, resolver: impl IncludeResolver + 'static) -> Self {
+        self.resolver = Box::new(resolver);
+        self
+    }
+
+    pub fn with_options(mut self, options: Options) -> Self {
+        self.options = options;
+        self
+    }
 
-pub fn evaluate_file(file_path: &str) -> std::io::Result<Option<BF>> {
-    let mut file = File::open(file_path)?;
-    let mut script = String::new();
-    file.read_to_string(&mut script)?;
+    pub fn files(&self) -> &Files {
+        &self.files
+    }
 
-    let parser = macro_parser::ProgramParser::new();
-    let mut program = parser.parse(&script).unwrap();
-    set_program_file_path(&mut program, &PathBuf::from("test.bfm"));
+    /// Source map covering every program compiled so far.
+    pub fn source_map(&self) -> SourceMap {
+        self.origins.source_map(&self.files)
+    }
+
+    pub fn frame_layouts(&self) -> impl Iterator<Item = &FrameLayout> {
+        self.frame_layouts.values()
+    }
+
+    /// Layout of a frame defined by a program compiled so far.
+    pub fn frame_layout(&self, name: &str) -> Option<&FrameLayout> {
+        self.frame_layouts.get(name)
+    }
+
+    pub fn compile_file(&mut self, file_path: impl AsRef<Path>) -> Result<BF, Vec<Diagnostic>> {
+        let file_path = file_path.as_ref();
+        match self.resolver.load(file_path) {
+            Ok(source) => self.compile_source(file_path, &source),
+            Err(err) => Err(vec![Diagnostic::io(file_path, err)]),
+        }
+    }
+
+    pub fn compile_source(
+        &mut self,
+        file_path: &Path,
+        source: &str,
+    ) -> Result<BF, Vec<Diagnostic>> {
+        let mut diagnostics = DiagnosticSink::default();
+        let file = self.files.add(file_path, source.to_owned());
+        let program = parse_program(file, source, &mut diagnostics);
+        if diagnostics.has_errors() {
+            return Err(diagnostics.into_diagnostics());
+        }
+
+        let program = program.expect("Parsing without errors always produces a program");
+        let mut scope = Scope::new(
+            &program,
+            file,
+            &mut self.files,
+            self.resolver.as_ref(),
+            &mut diagnostics,
+        );
+        if diagnostics.has_errors() {
+            return Err(diagnostics.into_diagnostics());
+        }
+
+        let target = Target {
+            eof: self.options.eof,
+        };
+        if scope.frame_definitions().any(|definition| definition.auto) {
+            // Any errors are reported by evaluating the program again.
+            let mut traces = Traces::new();
+            evaluate_program(
+                &program,
+                &scope,
+                target,
+                &mut OriginTable::default(),
+                Some(&mut traces),
+                &mut DiagnosticSink::default(),
+            );
+            choose_slot_orders(&mut scope, &traces);
+        }
+
+        let bf = evaluate_program(
+            &program,
+            &scope,
+            target,
+            &mut self.origins,
+            None,
+            &mut diagnostics,
+        );
+        // Errors in frame definitions are reported where the frame is used.
+        for definition in scope.frame_definitions() {
+            let mut ignored = DiagnosticSink::default();
+            if let Some(frame) = Frame::from_definition(definition, &scope, &mut ignored) {
+                self.frame_layouts
+                    .insert(definition.name.value.clone(), frame.layout());
+            }
+        }
+
+        if diagnostics.has_errors() {
+            return Err(diagnostics.into_diagnostics());
+        }
+
+        if self.options.simplify {
+            Ok(simplify_program(&bf).expect("Programs without errors have matching brackets"))
+        } else {
+            Ok(bf)
+        }
+    }
+}
 
-    let (bf, did_error) = evaluate_program(&program)?;
-    if did_error {
-        Ok(None)
-    } else {
-        Ok(Some(bf))
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
     }
 }
@@ -0,0 +1,124 @@
+use super::ast::{Program, Span};
+use super::diagnostic::{Diagnostic, DiagnosticSink, ErrorCode, FileId};
+use super::macro_parser::{self, Token};
+use lalrpop_util::ParseError;
+
+type Error<'input> = ParseError<usize, Token<'input>, ActionError>;
+
+/// An error found by an action of the grammar, rather than by the parser.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ActionError {
+    pub message: &'static str,
+    pub span: Span,
+}
+
+fn describe_token(token: &str) -> String {
+    if token.starts_with("r#") {
+        return if token.contains("a-zA-Z_") {
+            "identifier".to_owned()
+        } else if token.contains("0-9") {
+            "number".to_owned()
+        } else if token.starts_with("r#\"'") {
+            "character".to_owned()
+        } else {
+            "string".to_owned()
+        };
+    }
+
+    match token.strip_prefix('"').and_then(|t| t.strip_suffix('"')) {
+        Some(literal) => format!("'{}'", literal.replace("\\\"", "\"")),
+        None => token.to_owned(),
+    }
+}
+
+/// Replace the escape sequences the lexer accepts with the characters they stand for.
+pub fn unescape(text: &str) -> String {
+    let mut result = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('r') => result.push('\r'),
+            Some('t') => result.push('\t'),
+            Some('0') => result.push('\0'),
+            Some(c) => result.push(c),
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+fn describe_expected(expected: &[String]) -> String {
+    let expected = expected
+        .iter()
+        .map(|token| describe_token(token))
+        .collect::<Vec<_>>();
+
+    match expected.as_slice() {
+        [] => "nothing".to_owned(),
+        [only] => only.clone(),
+        [rest @ .., last] => format!("one of {}, or {last}", rest.join(", ")),
+    }
+}
+
+fn parse_error_diagnostic(file: FileId, source: &str, error: Error) -> Diagnostic {
+    match error {
+        ParseError::InvalidToken { location } => {
+            let length = source[location..].chars().next().map_or(0, char::len_utf8);
+            Diagnostic::error(ErrorCode::Syntax, "Invalid token").with_primary(
+                file,
+                (location, location + length),
+                "not recognised",
+            )
+        }
+
+        ParseError::UnrecognizedEof { location, expected } => {
+            Diagnostic::error(ErrorCode::Syntax, "Unexpected end of file")
+                .with_primary(file, (location, location), "file ends here")
+                .with_note(format!("expected {}", describe_expected(&expected)))
+        }
+
+        ParseError::UnrecognizedToken {
+            token: (start, token, end),
+            expected,
+        } => Diagnostic::error(ErrorCode::Syntax, format!("Unexpected token '{token}'"))
+            .with_primary(file, (start, end), "unexpected token")
+            .with_note(format!("expected {}", describe_expected(&expected))),
+
+        ParseError::ExtraToken {
+            token: (start, token, end),
+        } => Diagnostic::error(ErrorCode::Syntax, format!("Unexpected token '{token}'"))
+            .with_primary(file, (start, end), "expected end of file"),
+
+        ParseError::User { error } => {
+            Diagnostic::error(ErrorCode::Syntax, error.message).with_primary(file, error.span, "")
+        }
+    }
+}
+
+pub fn parse_program(
+    file: FileId,
+    source: &str,
+    diagnostics: &mut DiagnosticSink,
+) -> Option<Program> {
+    let mut errors = Vec::new();
+    let parser = macro_parser::ProgramParser::new();
+    let result = parser.parse(file, &mut errors, source);
+
+    for recovery in errors {
+        diagnostics.push(parse_error_diagnostic(file, source, recovery.error));
+    }
+
+    match result {
+        Ok(program) => Some(program),
+        Err(error) => {
+            diagnostics.push(parse_error_diagnostic(file, source, error));
+            None
+        }
+    }
+}
@@ -0,0 +1,22 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+pub trait IncludeResolver {
+    fn resolve(&self, base_path: &Path, include: &str) -> PathBuf {
+        base_path.join(include)
+    }
+
+    fn load(&self, file_path: &Path) -> std::io::Result<String>;
+}
+
+pub struct FileResolver;
+
+impl IncludeResolver for FileResolver {
+    fn load(&self, file_path: &Path) -> std::io::Result<String> {
+        let mut file = File::open(file_path)?;
+        let mut source = String::new();
+        file.read_to_string(&mut source)?;
+        Ok(source)
+    }
+}
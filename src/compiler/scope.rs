@@ -1,57 +1,202 @@
-use super::ast::{set_program_file_path, Definition, FrameDefinition, Macro, Program};
-use super::macro_parser;
+use super::ast::{Constant, Definition, Expression, FrameDefinition, Identifier, Macro, Program};
+use super::diagnostic::{Diagnostic, DiagnosticSink, ErrorCode, FileId, Files};
+use super::error::{Error, Result};
+use super::expression::{evaluate_expression, Constants};
+use super::parse::parse_program;
+use super::resolver::IncludeResolver;
 use std::collections::{HashMap, HashSet};
-use std::fs::File;
-use std::io::Read;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 pub struct Scope {
     frame_definitions: HashMap<String, FrameDefinition>,
     macros: HashMap<String, Macro>,
-    includes: HashSet<String>,
+    constant_definitions: HashMap<String, Constant>,
+    includes: HashSet<PathBuf>,
+    slot_orders: HashMap<String, Vec<usize>>,
+
+    /// Value of each constant, or `None` if it has an error.
+    constants: HashMap<String, Option<i64>>,
+}
+
+fn unknown_constant(name: &Identifier) -> Error {
+    Error::new(
+        ErrorCode::UnknownConstant,
+        name.span,
+        format!("No constant '{}' found", name.value),
+    )
+}
+
+/// Evaluates constants in terms of each other, reporting any errors in their definitions.
+struct ConstantResolver<'a> {
+    definitions: &'a HashMap<String, Constant>,
+    values: HashMap<String, Option<i64>>,
+    parents: Vec<String>,
+    diagnostics: &'a mut DiagnosticSink,
+}
+
+impl ConstantResolver<'_> {
+    fn resolve(&mut self, constant: &Constant) -> Option<i64> {
+        if let Some(value) = self.values.get(&constant.name.value) {
+            return *value;
+        }
+
+        self.parents.push(constant.name.value.clone());
+        let value = match evaluate_expression(&constant.value, self) {
+            Ok(value) => value,
+            Err(err) => {
+                self.diagnostics.push(err.into_diagnostic(constant.file));
+                None
+            }
+        };
+
+        self.parents.pop();
+        self.values.insert(constant.name.value.clone(), value);
+        value
+    }
+}
+
+impl Constants for ConstantResolver<'_> {
+    fn constant(&mut self, name: &Identifier) -> Result<Option<i64>> {
+        let Some(constant) = self.definitions.get(&name.value) else {
+            return Err(unknown_constant(name));
+        };
+
+        if self.parents.contains(&name.value) {
+            return Err(Error::new(
+                ErrorCode::RecursiveConstant,
+                name.span,
+                format!(
+                    "Constant '{}' depends on itself: {} -> {}",
+                    name.value,
+                    self.parents.join(" -> "),
+                    name.value
+                ),
+            ));
+        }
+
+        Ok(self.resolve(constant))
+    }
+}
+
+impl Constants for &Scope {
+    fn constant(&mut self, name: &Identifier) -> Result<Option<i64>> {
+        match self.constants.get(&name.value) {
+            Some(value) => Ok(*value),
+            None => Err(unknown_constant(name)),
+        }
+    }
+}
+
+fn duplicate_definition(
+    kind: &str,
+    name: &Identifier,
+    file: FileId,
+    previous: &Identifier,
+    previous_file: FileId,
+) -> Diagnostic {
+    Diagnostic::error(
+        ErrorCode::DuplicateDefinition,
+        format!("Multiple definitions of {kind} '{}'", name.value),
+    )
+    .with_primary(file, name.span, "redefined here")
+    .with_label(previous_file, previous.span, "previous definition here")
 }
 
 impl Scope {
-    pub fn new(program: &Program, base_path: &Path) -> std::io::Result<Self> {
+    pub fn new(
+        program: &Program,
+        file: FileId,
+        files: &mut Files,
+        resolver: &dyn IncludeResolver,
+        diagnostics: &mut DiagnosticSink,
+    ) -> Self {
         let mut scope = Scope {
             frame_definitions: HashMap::new(),
             macros: HashMap::new(),
+            constant_definitions: HashMap::new(),
             includes: HashSet::new(),
+            slot_orders: HashMap::new(),
+            constants: HashMap::new(),
         };
 
-        scope.add_program(program, base_path)?;
-        Ok(scope)
+        scope.includes.insert(files.get(file).path.clone());
+        scope.add_program(program, file, files, resolver, diagnostics);
+        scope.resolve_constants(diagnostics);
+        scope
+    }
+
+    fn resolve_constants(&mut self, diagnostics: &mut DiagnosticSink) {
+        let mut resolver = ConstantResolver {
+            definitions: &self.constant_definitions,
+            values: HashMap::new(),
+            parents: Vec::new(),
+            diagnostics,
+        };
+
+        let mut names: Vec<&String> = self.constant_definitions.keys().collect();
+        names.sort();
+        for name in names {
+            resolver.resolve(&self.constant_definitions[name]);
+        }
+
+        self.constants = resolver.values;
     }
 
-    fn add_program(&mut self, program: &Program, base_path: &Path) -> std::io::Result<()> {
+    fn add_program(
+        &mut self,
+        program: &Program,
+        file: FileId,
+        files: &mut Files,
+        resolver: &dyn IncludeResolver,
+        diagnostics: &mut DiagnosticSink,
+    ) {
+        let base_path = files
+            .get(file)
+            .path
+            .parent()
+            .unwrap_or(Path::new("."))
+            .to_owned();
+
         for definition in program {
             match definition {
-                Definition::Include(file_path) => {
-                    if self.includes.contains(file_path) {
+                Definition::Include(include, span) => {
+                    let file_path = resolver.resolve(&base_path, include);
+                    if self.includes.contains(&file_path) {
                         continue;
                     }
                     self.includes.insert(file_path.clone());
 
-                    let file_path = base_path.join(file_path);
-                    let mut file = File::open(&file_path)?;
-                    let mut script = String::new();
-                    file.read_to_string(&mut script)?;
-
-                    let parser = macro_parser::ProgramParser::new();
-                    let mut program = parser.parse(&script).unwrap();
-                    set_program_file_path(&mut program, &file_path);
-
-                    self.add_program(
-                        &program,
-                        &file_path
-                            .parent()
-                            .expect("You can't use the root directory as a bfm file"),
-                    )?;
+                    let script = match resolver.load(&file_path) {
+                        Ok(script) => script,
+                        Err(err) => {
+                            diagnostics.push(Diagnostic::io(&file_path, err).with_primary(
+                                file,
+                                *span,
+                                "included here",
+                            ));
+                            continue;
+                        }
+                    };
+
+                    let included_file = files.add(&file_path, script);
+                    let source = &files.get(included_file).source;
+                    let Some(program) = parse_program(included_file, source, diagnostics) else {
+                        continue;
+                    };
+
+                    self.add_program(&program, included_file, files, resolver, diagnostics);
                 }
 
                 Definition::Frame(frame) => {
-                    if self.frame_definitions.contains_key(&frame.name.value) {
-                        panic!("Multiple definitions of {}", frame.name.value);
+                    if let Some(previous) = self.frame_definitions.get(&frame.name.value) {
+                        diagnostics.push(duplicate_definition(
+                            "frame",
+                            &frame.name,
+                            frame.file,
+                            &previous.name,
+                            previous.file,
+                        ));
+                        continue;
                     }
 
                     self.frame_definitions
@@ -59,25 +204,66 @@ impl Scope {
                 }
 
                 Definition::Macro(macro_) => {
-                    if self.macros.contains_key(&macro_.name.value) {
-                        panic!("Multiple definitions of {}", macro_.name.value);
+                    if let Some(previous) = self.macros.get(&macro_.name.value) {
+                        diagnostics.push(duplicate_definition(
+                            "macro",
+                            &macro_.name,
+                            macro_.block.file,
+                            &previous.name,
+                            previous.block.file,
+                        ));
+                        continue;
                     }
 
                     self.macros
                         .insert(macro_.name.value.clone(), macro_.clone());
                 }
 
+                Definition::Constant(constant) => {
+                    if let Some(previous) = self.constant_definitions.get(&constant.name.value) {
+                        diagnostics.push(duplicate_definition(
+                            "constant",
+                            &constant.name,
+                            constant.file,
+                            &previous.name,
+                            previous.file,
+                        ));
+                        continue;
+                    }
+
+                    self.constant_definitions
+                        .insert(constant.name.value.clone(), constant.clone());
+                }
+
                 _ => {}
             }
         }
+    }
 
-        Ok(())
+    pub fn frame_definitions(&self) -> impl Iterator<Item = &FrameDefinition> {
+        self.frame_definitions.values()
     }
 
     pub fn frame_definition(&self, name: &str) -> Option<&FrameDefinition> {
         self.frame_definitions.get(name)
     }
 
+    /// Order to lay out the slot definitions of a frame in, if not declaration order.
+    pub fn slot_order(&self, name: &str) -> Option<&[usize]> {
+        self.slot_orders.get(name).map(Vec::as_slice)
+    }
+
+    pub fn set_slot_order(&mut self, name: String, order: Vec<usize>) {
+        self.slot_orders.insert(name, order);
+    }
+
+    /// Evaluate an expression using the constants in scope, or `None` if it uses a constant with
+    /// an error.
+    pub fn evaluate(&self, expression: &Expression) -> Result<Option<i64>> {
+        let mut constants = self;
+        evaluate_expression(expression, &mut constants)
+    }
+
     pub fn macro_(&self, name: &str) -> Option<&Macro> {
         self.macros.get(name)
     }
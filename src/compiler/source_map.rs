@@ -0,0 +1,66 @@
+use super::ast::Span;
+use super::diagnostic::{FileId, Files};
+use crate::source_map::{Origin, SourceMap};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Entry {
+    file: FileId,
+    span: Span,
+    caller: Option<usize>,
+    name: Option<String>,
+}
+
+#[derive(Default)]
+pub struct OriginTable {
+    entries: Vec<Entry>,
+    indices: HashMap<Entry, usize>,
+}
+
+impl OriginTable {
+    pub fn intern(
+        &mut self,
+        file: FileId,
+        span: Span,
+        caller: Option<usize>,
+        name: Option<&str>,
+    ) -> usize {
+        let entry = Entry {
+            file,
+            span,
+            caller,
+            name: name.map(str::to_owned),
+        };
+
+        if let Some(index) = self.indices.get(&entry) {
+            return *index;
+        }
+
+        self.entries.push(entry.clone());
+        self.indices.insert(entry, self.entries.len() - 1);
+        self.entries.len() - 1
+    }
+
+    pub fn source_map(&self, files: &Files) -> SourceMap {
+        let origins = self
+            .entries
+            .iter()
+            .map(|entry| {
+                let (line, column) = files.get(entry.file).location(entry.span.0);
+                Origin {
+                    file: entry.file.index(),
+                    span: entry.span,
+                    line,
+                    column,
+                    caller: entry.caller,
+                    name: entry.name.clone(),
+                }
+            })
+            .collect();
+
+        SourceMap {
+            files: files.iter().map(|file| file.path.clone()).collect(),
+            origins,
+        }
+    }
+}
@@ -0,0 +1,547 @@
+use crate::bf::BF;
+use crate::compiler::FrameLayout;
+use crate::interpreter::{Config, Machine, RuntimeError, Step};
+use crate::source_map::SourceMap;
+use std::collections::HashMap;
+use std::io::{BufRead, Read, Write};
+use std::path::Path;
+
+const HELP: &str = "\
+Commands:
+   break <line>            Break on a line of the main .bfm file
+   break <file>:<line>     Break on a line of a .bfm file
+   break pc <n>            Break on an instruction
+   delete [n]              Delete a breakpoint, or all of them
+   watch <cell|slot>       Stop when a cell changes
+   unwatch [cell|slot]     Remove a watch, or all of them
+   info                    List breakpoints and watches
+   step [n]                Execute n bf instructions (default 1)
+   next                    Run to the next line, stepping over macros
+   continue                Run until a breakpoint, watch or '*'
+   where                   Show the current location and macro stack
+   list                    Show the source around the current location
+   memory [radius]         Show cells around the pointer
+   frame [name] [base]     Label cells with the slots of a frame at base
+   print <cell|slot>       Show the value of a cell
+   quit                    Exit the debugger";
+
+#[derive(Debug, Clone, PartialEq)]
+enum Breakpoint {
+    Pc(usize),
+    Line { file: usize, line: usize },
+}
+
+struct Watch {
+    name: String,
+    cell: usize,
+    value: u64,
+}
+
+enum Resume {
+    Step(usize),
+    Next,
+    Continue,
+}
+
+enum Stop {
+    Step,
+    Halted,
+    Break,
+    Error(RuntimeError),
+    Breakpoint(usize),
+    Watch(usize, u64),
+}
+
+pub struct Debugger<'a> {
+    machine: Machine<'a>,
+    source_map: SourceMap,
+    frames: HashMap<String, FrameLayout>,
+    frame: Option<(String, usize)>,
+    breakpoints: Vec<Breakpoint>,
+    watches: Vec<Watch>,
+    sources: HashMap<usize, Option<String>>,
+}
+
+impl<'a> Debugger<'a> {
+    pub fn new(
+        program: &'a BF,
+        config: Config,
+        source_map: Option<SourceMap>,
+        frames: impl IntoIterator<Item = FrameLayout>,
+    ) -> Self {
+        Self {
+            machine: Machine::new(program, config),
+            source_map: source_map.unwrap_or_default(),
+            frames: frames
+                .into_iter()
+                .map(|frame| (frame.name.clone(), frame))
+                .collect(),
+            frame: None,
+            breakpoints: Vec::new(),
+            watches: Vec::new(),
+            sources: HashMap::new(),
+        }
+    }
+
+    /// Read the program's `,` from `input` instead of its embedded input.
+    pub fn with_input(mut self, input: impl Read + 'a) -> Self {
+        self.machine = self.machine.with_input(input);
+        self
+    }
+
+    /// Write the program's `.` to `output`, which is discarded otherwise.
+    pub fn with_output(mut self, output: impl Write + 'a) -> Self {
+        self.machine = self.machine.with_output(output);
+        self
+    }
+
+    pub fn select_frame(&mut self, name: &str, base: usize) -> bool {
+        if !self.frames.contains_key(name) {
+            return false;
+        }
+
+        self.frame = Some((name.to_owned(), base));
+        true
+    }
+
+    pub fn run(&mut self, input: impl BufRead, mut output: impl Write) -> std::io::Result<()> {
+        self.show_location(&mut output)?;
+
+        write!(output, "(bfdb) ")?;
+        output.flush()?;
+        for line in input.lines() {
+            let line = line?;
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            if let Some((command, arguments)) = words.split_first() {
+                if matches!(*command, "quit" | "q") {
+                    break;
+                }
+
+                if let Err(message) = self.command(command, arguments, &mut output)? {
+                    writeln!(output, "error: {message}")?;
+                }
+            }
+
+            write!(output, "(bfdb) ")?;
+            output.flush()?;
+        }
+
+        Ok(())
+    }
+
+    fn command(
+        &mut self,
+        command: &str,
+        arguments: &[&str],
+        output: &mut impl Write,
+    ) -> std::io::Result<Result<(), String>> {
+        match (command, arguments) {
+            ("help" | "h", _) => writeln!(output, "{HELP}")?,
+
+            ("break" | "b", ["pc", pc]) => match pc.parse() {
+                Ok(pc) => self.add_breakpoint(Breakpoint::Pc(pc), output)?,
+                Err(_) => return Ok(Err(format!("invalid pc '{pc}'"))),
+            },
+            ("break" | "b", [location]) => match self.parse_line(location) {
+                Ok((file, line)) => self.add_breakpoint(Breakpoint::Line { file, line }, output)?,
+                Err(message) => return Ok(Err(message)),
+            },
+
+            ("delete" | "d", []) => self.breakpoints.clear(),
+            ("delete" | "d", [index]) => match index.parse::<usize>() {
+                Ok(index) if index < self.breakpoints.len() => {
+                    self.breakpoints.remove(index);
+                }
+                _ => return Ok(Err(format!("no breakpoint {index}"))),
+            },
+
+            ("watch" | "w", [target]) => match self.parse_cell(target) {
+                Ok(cell) => {
+                    let value = self.machine.cell(cell);
+                    writeln!(output, "Watching {target} (cell {cell}) = {value}")?;
+                    self.watches.push(Watch {
+                        name: target.to_string(),
+                        cell,
+                        value,
+                    });
+                }
+                Err(message) => return Ok(Err(message)),
+            },
+            ("unwatch", []) => self.watches.clear(),
+            ("unwatch", [target]) => match self.parse_cell(target) {
+                Ok(cell) => self.watches.retain(|watch| watch.cell != cell),
+                Err(message) => return Ok(Err(message)),
+            },
+
+            ("info" | "i", []) => self.show_info(output)?,
+
+            ("step" | "s", []) => self.resume(Resume::Step(1), output)?,
+            ("step" | "s", [count]) => match count.parse() {
+                Ok(count) => self.resume(Resume::Step(count), output)?,
+                Err(_) => return Ok(Err(format!("invalid count '{count}'"))),
+            },
+            ("next" | "n", []) => self.resume(Resume::Next, output)?,
+            ("continue" | "c", []) => self.resume(Resume::Continue, output)?,
+
+            ("where" | "bt", []) => self.show_location(output)?,
+            ("list" | "l", []) => self.show_source(output)?,
+
+            ("memory" | "m", []) => self.show_memory(8, output)?,
+            ("memory" | "m", [radius]) => match radius.parse() {
+                Ok(radius) => self.show_memory(radius, output)?,
+                Err(_) => return Ok(Err(format!("invalid radius '{radius}'"))),
+            },
+
+            ("frame" | "f", []) => self.show_frame(output)?,
+            ("frame" | "f", [name, base @ ..]) if base.len() <= 1 => {
+                let base = match base.first().map(|base| base.parse()) {
+                    None => 0,
+                    Some(Ok(base)) => base,
+                    Some(Err(_)) => return Ok(Err(format!("invalid base '{}'", base[0]))),
+                };
+
+                if !self.select_frame(name, base) {
+                    return Ok(Err(format!("no frame '{name}'")));
+                }
+                self.show_frame(output)?;
+            }
+
+            ("print" | "p", [target]) => match self.parse_cell(target) {
+                Ok(cell) => {
+                    let value = self.machine.cell(cell);
+                    writeln!(
+                        output,
+                        "{target} (cell {cell}) = {value} {:?}",
+                        value as u8 as char
+                    )?;
+                }
+                Err(message) => return Ok(Err(message)),
+            },
+
+            _ => return Ok(Err(format!("unknown command '{command}', try 'help'"))),
+        }
+
+        Ok(Ok(()))
+    }
+
+    fn add_breakpoint(
+        &mut self,
+        breakpoint: Breakpoint,
+        output: &mut impl Write,
+    ) -> std::io::Result<()> {
+        let description = self.describe_breakpoint(&breakpoint);
+        writeln!(
+            output,
+            "Breakpoint {} at {description}",
+            self.breakpoints.len()
+        )?;
+        self.breakpoints.push(breakpoint);
+        Ok(())
+    }
+
+    fn describe_breakpoint(&self, breakpoint: &Breakpoint) -> String {
+        match breakpoint {
+            Breakpoint::Pc(pc) => format!("pc {pc}"),
+            Breakpoint::Line { file, line } => {
+                format!("{}:{line}", self.source_map.files[*file].display())
+            }
+        }
+    }
+
+    fn parse_line(&self, location: &str) -> Result<(usize, usize), String> {
+        if self.source_map.files.is_empty() {
+            return Err("no source map loaded, use 'break pc <n>'".to_owned());
+        }
+
+        let (file, line) = match location.rsplit_once(':') {
+            Some((file_name, line)) => {
+                let file = self
+                    .source_map
+                    .files
+                    .iter()
+                    .position(|file| file.ends_with(Path::new(file_name)))
+                    .ok_or_else(|| format!("no file '{file_name}' in the source map"))?;
+                (file, line)
+            }
+            None => (0, location),
+        };
+
+        let line = line.parse().map_err(|_| format!("invalid line '{line}'"))?;
+        Ok((file, line))
+    }
+
+    fn parse_cell(&self, target: &str) -> Result<usize, String> {
+        if let Ok(cell) = target.parse() {
+            return Ok(cell);
+        }
+
+        let Some((frame, base)) = &self.frame else {
+            return Err(format!(
+                "'{target}' is not a cell, select a frame to use slot names"
+            ));
+        };
+
+        self.frames[frame]
+            .slot(target)
+            .map(|slot| base + slot.offset)
+            .ok_or_else(|| format!("no slot '{target}' in frame '{frame}'"))
+    }
+
+    fn line_at(&self, pc: usize, file: usize, line: usize) -> bool {
+        let Some(origin) = self.machine.program().origin(pc) else {
+            return false;
+        };
+
+        self.source_map
+            .stack(origin)
+            .iter()
+            .any(|origin| origin.file == file && origin.line == line)
+    }
+
+    fn breakpoint_at(&self, pc: usize) -> Option<usize> {
+        self.breakpoints
+            .iter()
+            .position(|breakpoint| match breakpoint {
+                Breakpoint::Pc(breakpoint_pc) => *breakpoint_pc == pc,
+
+                // Only stop on the first instruction generated for a line.
+                Breakpoint::Line { file, line } => {
+                    self.line_at(pc, *file, *line)
+                        && (pc == 0 || !self.line_at(pc - 1, *file, *line))
+                }
+            })
+    }
+
+    /// Source locations of the instruction at `pc`, from the top level `using` inwards.
+    fn lines(&self, pc: usize) -> Vec<(usize, usize)> {
+        let Some(origin) = self.machine.program().origin(pc) else {
+            return Vec::new();
+        };
+
+        let mut lines = self
+            .source_map
+            .stack(origin)
+            .iter()
+            .map(|origin| (origin.file, origin.line))
+            .collect::<Vec<_>>();
+        lines.reverse();
+        lines.dedup();
+        lines
+    }
+
+    fn step(&mut self) -> Option<Stop> {
+        match self.machine.step() {
+            Ok(Step::Halted) => return Some(Stop::Halted),
+            Ok(Step::Break) => return Some(Stop::Break),
+            Ok(Step::Running) => {}
+            Err(err) => return Some(Stop::Error(err)),
+        }
+
+        for (index, watch) in self.watches.iter_mut().enumerate() {
+            let value = self.machine.cell(watch.cell);
+            if value != watch.value {
+                let old_value = watch.value;
+                watch.value = value;
+                return Some(Stop::Watch(index, old_value));
+            }
+        }
+
+        self.breakpoint_at(self.machine.pc()).map(Stop::Breakpoint)
+    }
+
+    fn resume(&mut self, resume: Resume, output: &mut impl Write) -> std::io::Result<()> {
+        if self.machine.is_halted() {
+            writeln!(output, "The program has finished")?;
+            return Ok(());
+        }
+
+        let stop = match resume {
+            Resume::Step(count) => (0..count).find_map(|_| self.step()).unwrap_or(Stop::Step),
+
+            Resume::Continue => loop {
+                if let Some(stop) = self.step() {
+                    break stop;
+                }
+            },
+
+            Resume::Next => {
+                let start = self.lines(self.machine.pc());
+                loop {
+                    if let Some(stop) = self.step() {
+                        break stop;
+                    }
+
+                    // Stop once we're on a different line, and not deeper inside a macro.
+                    let lines = self.lines(self.machine.pc());
+                    if start.is_empty() || (lines.len() <= start.len() && lines != start) {
+                        break Stop::Step;
+                    }
+                }
+            }
+        };
+
+        self.machine.flush()?;
+        match stop {
+            Stop::Step => {}
+            Stop::Halted => {
+                writeln!(output, "The program has finished")?;
+                return Ok(());
+            }
+            Stop::Break => writeln!(output, "Stopped at '*'")?,
+            Stop::Error(err) => writeln!(output, "Runtime error: {err}")?,
+            Stop::Breakpoint(index) => writeln!(
+                output,
+                "Breakpoint {index}, {}",
+                self.describe_breakpoint(&self.breakpoints[index])
+            )?,
+            Stop::Watch(index, old_value) => {
+                let watch = &self.watches[index];
+                writeln!(
+                    output,
+                    "Watch {}: {old_value} -> {}",
+                    watch.name, watch.value
+                )?;
+            }
+        }
+
+        self.show_location(output)
+    }
+
+    fn source_line(&mut self, file: usize, line: usize) -> Option<String> {
+        let path = self.source_map.files.get(file)?.clone();
+        let source = self
+            .sources
+            .entry(file)
+            .or_insert_with(|| std::fs::read_to_string(path).ok());
+
+        source
+            .as_ref()?
+            .lines()
+            .nth(line.checked_sub(1)?)
+            .map(str::to_owned)
+    }
+
+    fn show_location(&mut self, output: &mut impl Write) -> std::io::Result<()> {
+        let pc = self.machine.pc();
+        if self.machine.is_halted() {
+            return writeln!(output, "pc {pc}: end of program");
+        }
+
+        let instruction = self.machine.program().code()[pc];
+        let pointer = self.machine.pointer();
+        let cell = self.machine.cell(pointer);
+        writeln!(
+            output,
+            "pc {pc}: {instruction:?}, pointer {pointer} = {cell}"
+        )?;
+
+        let Some(origin) = self.machine.program().origin(pc) else {
+            return Ok(());
+        };
+
+        let stack = self
+            .source_map
+            .stack(origin)
+            .into_iter()
+            .cloned()
+            .collect::<Vec<_>>();
+        for (depth, origin) in stack.iter().enumerate() {
+            let location = self.source_map.describe(origin);
+            let name = match &origin.name {
+                Some(name) => format!("{name}(...) "),
+                None => String::new(),
+            };
+
+            writeln!(output, "  #{depth} {name}at {location}")?;
+            if depth == 0 {
+                if let Some(line) = self.source_line(origin.file, origin.line) {
+                    writeln!(output, "     {}", line.trim())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn show_source(&mut self, output: &mut impl Write) -> std::io::Result<()> {
+        let Some(origin) = self.machine.program().origin(self.machine.pc()) else {
+            return writeln!(output, "No source location for pc {}", self.machine.pc());
+        };
+
+        let origin = self.source_map.origin(origin).clone();
+        for line in origin.line.saturating_sub(5).max(1)..=origin.line + 5 {
+            if let Some(text) = self.source_line(origin.file, line) {
+                let marker = if line == origin.line { ">" } else { " " };
+                writeln!(output, "{marker}{line:>5} {text}")?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn slot_name(&self, cell: usize) -> Option<&str> {
+        let (frame, base) = self.frame.as_ref()?;
+        let offset = cell.checked_sub(*base)?;
+        self.frames[frame]
+            .slot_at(offset)
+            .map(|slot| slot.name.as_str())
+    }
+
+    fn show_cell(&self, cell: usize, output: &mut impl Write) -> std::io::Result<()> {
+        let marker = if cell == self.machine.pointer() {
+            ">"
+        } else {
+            " "
+        };
+        let value = self.machine.cell(cell);
+        let name = self.slot_name(cell).unwrap_or_default();
+        writeln!(output, "{marker}{cell:>6} {value:>4}  {name}")
+    }
+
+    fn show_memory(&self, radius: usize, output: &mut impl Write) -> std::io::Result<()> {
+        let pointer = self.machine.pointer();
+        for cell in pointer.saturating_sub(radius)..=pointer + radius {
+            self.show_cell(cell, output)?;
+        }
+        Ok(())
+    }
+
+    fn show_frame(&self, output: &mut impl Write) -> std::io::Result<()> {
+        let Some((frame, base)) = &self.frame else {
+            return writeln!(output, "No frame selected");
+        };
+
+        let layout = &self.frames[frame];
+        writeln!(output, "Frame {frame} at cell {base}")?;
+        for cell in *base..base + layout.size {
+            self.show_cell(cell, output)?;
+        }
+        Ok(())
+    }
+
+    fn show_info(&self, output: &mut impl Write) -> std::io::Result<()> {
+        if self.breakpoints.is_empty() {
+            writeln!(output, "No breakpoints")?;
+        }
+        for (index, breakpoint) in self.breakpoints.iter().enumerate() {
+            writeln!(
+                output,
+                "Breakpoint {index} at {}",
+                self.describe_breakpoint(breakpoint)
+            )?;
+        }
+
+        if self.watches.is_empty() {
+            writeln!(output, "No watches")?;
+        }
+        for watch in &self.watches {
+            writeln!(
+                output,
+                "Watch {} (cell {}) = {}",
+                watch.name, watch.cell, watch.value
+            )?;
+        }
+
+        Ok(())
+    }
+}
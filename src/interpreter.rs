@@ -1,77 +1,524 @@
-use crate::bf::{Instruction, BF};
-use std::num::Wrapping;
-use std::io::Read;
+use crate::bf::{Eof, Instruction, BF};
+use crate::bytecode::{Bytecode, Vm};
+use std::fmt;
+use std::io::{ErrorKind, Read, Write};
 
-fn print_memory(memory: &[Wrapping<u8>], _pointer: usize) {
-    for cell in memory {
-        print!("{cell:03} ");
+/// Number of cells given to a program that needs a fixed size tape, when the config doesn't
+/// set a memory size.
+pub const DEFAULT_MEMORY_SIZE: usize = 1 << 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CellWidth {
+    #[default]
+    U8,
+    U16,
+    U32,
+    U64,
+}
+
+impl CellWidth {
+    pub fn from_bits(bits: u32) -> Option<Self> {
+        match bits {
+            8 => Some(CellWidth::U8),
+            16 => Some(CellWidth::U16),
+            32 => Some(CellWidth::U32),
+            64 => Some(CellWidth::U64),
+            _ => None,
+        }
+    }
+
+    pub fn bits(&self) -> u32 {
+        match self {
+            CellWidth::U8 => 8,
+            CellWidth::U16 => 16,
+            CellWidth::U32 => 32,
+            CellWidth::U64 => 64,
+        }
+    }
+
+    pub fn max(&self) -> u64 {
+        u64::MAX >> (64 - self.bits())
     }
-    println!();
-    println!();
 }
 
-pub fn run_program(program: BF) {
-    let mut memory = vec![Wrapping(0u8); 1];
-    let mut pointer = 0usize;
-    let mut pc = 0usize;
-    let mut stack = Vec::<usize>::new();
-    let mut input_pointer = 0usize;
+/// What happens when a cell is incremented past its maximum, or decremented below zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+    #[default]
+    Wrapping,
+    Trapping,
+    Saturating,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub cell_width: CellWidth,
+    pub overflow: Overflow,
+    pub eof: Eof,
+
+    /// Stop with an error after executing this many steps. A `Machine` steps once per bf
+    /// instruction, while `run_program` steps once per bytecode op, which may stand for a whole
+    /// run of instructions or a loop idiom.
+    pub step_limit: Option<u64>,
+
+    /// Number of cells available, unlimited if `None`.
+    pub memory_size: Option<usize>,
+}
+
+impl Config {
+    /// Add `amount` to a cell value, or `None` if it traps.
+    pub fn add(&self, value: u64, amount: i64) -> Option<u64> {
+        let max = self.cell_width.max();
+        let result = if amount >= 0 {
+            value
+                .checked_add(amount as u64)
+                .filter(|result| *result <= max)
+        } else {
+            value.checked_sub(amount.unsigned_abs())
+        };
 
-    let code = program.code();
-    while pc < program.len() {
-        let instruction = code[pc];
-        pc += 1;
+        match (result, self.overflow) {
+            (Some(result), _) => Some(result),
+            (None, Overflow::Trapping) => None,
+            (None, Overflow::Saturating) => Some(if amount >= 0 { max } else { 0 }),
+            (None, Overflow::Wrapping) => Some(value.wrapping_add(amount as u64) & max),
+        }
+    }
+
+    /// The value `,` leaves in a cell holding `value` once the input has run out.
+    pub fn end_of_input(&self, value: u64) -> u64 {
+        match self.eof {
+            Eof::Unchanged => value,
+            Eof::Zero => 0,
+            Eof::MinusOne => self.cell_width.max(),
+        }
+    }
+}
+
+/// Read a single byte, or `None` at the end of input.
+pub(crate) fn read_byte(input: &mut dyn Read) -> std::io::Result<Option<u8>> {
+    let mut byte = [0u8];
+    loop {
+        match input.read(&mut byte) {
+            Ok(0) => return Ok(None),
+            Ok(_) => return Ok(Some(byte[0])),
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// An error stopping a program. Programs run by `run_program` are optimised first, so they only
+/// report the pointer leaving the tape when a cell there is used: `<>+` runs without error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuntimeError {
+    CellOverflow {
+        pc: usize,
+        pointer: usize,
+    },
+
+    /// The pointer moved, or an optimised program used a cell, left of cell 0.
+    PointerUnderflow {
+        pc: usize,
+    },
+
+    /// The pointer moved, or an optimised program used a cell, past the end of memory.
+    PointerOverflow {
+        pc: usize,
+    },
+
+    UnmatchedBracket {
+        pc: usize,
+    },
+    StepLimitExceeded {
+        limit: u64,
+    },
+    Io {
+        pc: usize,
+        kind: ErrorKind,
+    },
+    Unsupported(String),
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeError::CellOverflow { pc, pointer } => {
+                write!(f, "Cell {pointer} overflowed at pc {pc}")
+            }
+            RuntimeError::PointerUnderflow { pc } => {
+                write!(f, "Pointer moved left of cell 0 at pc {pc}")
+            }
+            RuntimeError::PointerOverflow { pc } => {
+                write!(f, "Pointer moved past the end of memory at pc {pc}")
+            }
+            RuntimeError::UnmatchedBracket { pc } => {
+                write!(f, "Unmatched bracket at pc {pc}")
+            }
+            RuntimeError::StepLimitExceeded { limit } => {
+                write!(f, "Exceeded the limit of {limit} steps")
+            }
+            RuntimeError::Io { pc, kind } => {
+                write!(f, "Input or output failed at pc {pc}: {kind}")
+            }
+            RuntimeError::Unsupported(reason) => write!(f, "{reason}"),
+        }
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    Running,
+    Break,
+    Halted,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RunStats {
+    /// Number of instructions executed.
+    pub steps: u64,
+
+    /// Number of cells the pointer has reached.
+    pub cells: usize,
+}
+
+pub struct Machine<'a> {
+    program: &'a BF,
+    config: Config,
+    memory: Vec<u64>,
+    pointer: usize,
+    pc: usize,
+    stack: Vec<usize>,
+    steps: u64,
+    input: Box<dyn Read + 'a>,
+    output: Box<dyn Write + 'a>,
+}
+
+impl<'a> Machine<'a> {
+    /// A machine reading the program's embedded input, and discarding its output.
+    pub fn new(program: &'a BF, config: Config) -> Self {
+        Self {
+            program,
+            config,
+            memory: vec![0; 1],
+            pointer: 0,
+            pc: 0,
+            stack: Vec::new(),
+            steps: 0,
+            input: Box::new(program.input.as_slice()),
+            output: Box::new(std::io::sink()),
+        }
+    }
+
+    /// Read `,` from `input` instead of the input embedded in the program.
+    pub fn with_input(mut self, input: impl Read + 'a) -> Self {
+        self.input = Box::new(input);
+        self
+    }
+
+    pub fn with_output(mut self, output: impl Write + 'a) -> Self {
+        self.output = Box::new(output);
+        self
+    }
+
+    /// Flush anything the program has written with `.`.
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.output.flush()
+    }
+
+    pub fn program(&self) -> &BF {
+        self.program
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn pointer(&self) -> usize {
+        self.pointer
+    }
+
+    pub fn memory(&self) -> &[u64] {
+        &self.memory
+    }
+
+    pub fn cell(&self, index: usize) -> u64 {
+        self.memory.get(index).copied().unwrap_or(0)
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.pc >= self.program.len()
+    }
+
+    pub fn stats(&self) -> RunStats {
+        RunStats {
+            steps: self.steps,
+            cells: self.memory.len(),
+        }
+    }
+
+    fn add(&mut self, amount: i64) -> Result<(), RuntimeError> {
+        let cell = &mut self.memory[self.pointer];
+        *cell = self
+            .config
+            .add(*cell, amount)
+            .ok_or(RuntimeError::CellOverflow {
+                pc: self.pc - 1,
+                pointer: self.pointer,
+            })?;
+        Ok(())
+    }
+
+    fn io_error(&self, err: std::io::Error) -> RuntimeError {
+        RuntimeError::Io {
+            pc: self.pc - 1,
+            kind: err.kind(),
+        }
+    }
+
+    fn input(&mut self) -> Result<(), RuntimeError> {
+        self.output.flush().map_err(|err| self.io_error(err))?;
+
+        let cell = self.memory[self.pointer];
+        self.memory[self.pointer] = match read_byte(&mut self.input) {
+            Ok(Some(byte)) => byte as u64,
+            Ok(None) => self.config.end_of_input(cell),
+            Err(err) => return Err(self.io_error(err)),
+        };
+        Ok(())
+    }
+
+    pub fn step(&mut self) -> Result<Step, RuntimeError> {
+        if self.is_halted() {
+            return Ok(Step::Halted);
+        }
+
+        if let Some(limit) = self.config.step_limit {
+            if self.steps >= limit {
+                return Err(RuntimeError::StepLimitExceeded { limit });
+            }
+        }
+
+        let code = self.program.code();
+        let instruction = code[self.pc];
+        self.pc += 1;
+        self.steps += 1;
 
         match instruction {
-            Instruction::Add => memory[pointer] += 1,
-            Instruction::Subtract => memory[pointer] -= 1,
+            Instruction::Add => self.add(1)?,
+            Instruction::Subtract => self.add(-1)?,
 
             Instruction::Left => {
-                if pointer == 0 {
-                    println!("pointer={pointer} pc={pc}");
-                    unreachable!();
+                if self.pointer == 0 {
+                    return Err(RuntimeError::PointerUnderflow { pc: self.pc - 1 });
                 }
-                pointer -= 1;
+                self.pointer -= 1;
             }
             Instruction::Right => {
-                pointer += 1;
-                if pointer >= memory.len() {
-                    memory.push(Wrapping(0));
+                if self.config.memory_size == Some(self.pointer + 1) {
+                    return Err(RuntimeError::PointerOverflow { pc: self.pc - 1 });
+                }
+                self.pointer += 1;
+                if self.pointer >= self.memory.len() {
+                    self.memory.push(0);
                 }
             }
 
-            Instruction::Input => {
-                if input_pointer >= program.input.len() {
-                    memory[pointer] = Wrapping(0);
-                } else {
-                    memory[pointer] = Wrapping(program.input[input_pointer]);
-                    input_pointer += 1;
-                }
+            Instruction::Input => self.input()?,
+            Instruction::Output => {
+                let byte = self.memory[self.pointer] as u8;
+                self.output
+                    .write_all(&[byte])
+                    .map_err(|err| self.io_error(err))?;
             }
-            Instruction::Output => print!("{}", memory[pointer].0 as char),
 
             Instruction::OpenLoop => {
-                if memory[pointer].0 == 0 {
+                let start = self.pc - 1;
+                if self.memory[self.pointer] == 0 {
                     let mut depth = 1;
-                    while pc < program.len() && depth > 0 {
-                        pc += 1;
-                        match code[pc] {
+                    while self.pc < code.len() && depth > 0 {
+                        match code[self.pc] {
                             Instruction::OpenLoop => depth += 1,
                             Instruction::CloseLoop => depth -= 1,
                             _ => {}
                         }
+                        self.pc += 1;
+                    }
+
+                    if depth > 0 {
+                        return Err(RuntimeError::UnmatchedBracket { pc: start });
                     }
-                    pc += 1;
                 } else {
-                    stack.push(pc - 1);
+                    self.stack.push(start);
                 }
             }
-            Instruction::CloseLoop => pc = stack.pop().unwrap(),
+            Instruction::CloseLoop => match self.stack.pop() {
+                Some(start) => self.pc = start,
+                None => return Err(RuntimeError::UnmatchedBracket { pc: self.pc - 1 }),
+            },
+
+            Instruction::Break => return Ok(Step::Break),
+        }
+
+        if !self.is_halted() {
+            return Ok(Step::Running);
+        }
+
+        self.output.flush().map_err(|err| self.io_error(err))?;
+        match self.stack.last() {
+            Some(start) => Err(RuntimeError::UnmatchedBracket { pc: *start }),
+            None => Ok(Step::Halted),
+        }
+    }
+
+    /// Run until the program halts or reaches a `*` breakpoint.
+    pub fn run(&mut self) -> Result<Step, RuntimeError> {
+        loop {
+            match self.step()? {
+                Step::Running => {}
+                step => return Ok(step),
+            }
+        }
+    }
+}
+
+/// Run a program to completion as bytecode, ignoring breakpoints.
+pub fn run_program(
+    program: &BF,
+    config: Config,
+    input: impl Read,
+    output: impl Write,
+) -> Result<RunStats, RuntimeError> {
+    let bytecode = Bytecode::compile(program, &config)?;
+    let mut vm = Vm::new(&bytecode, config)
+        .with_input(input)
+        .with_output(output);
+    while vm.run()? != Step::Halted {}
+    Ok(vm.stats())
+}
 
-            Instruction::Break => {
-                print_memory(&memory, pointer);
-                std::io::stdin().read(&mut [0u8]).unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTHS: [CellWidth; 4] = [
+        CellWidth::U8,
+        CellWidth::U16,
+        CellWidth::U32,
+        CellWidth::U64,
+    ];
+
+    fn config(cell_width: CellWidth, overflow: Overflow) -> Config {
+        Config {
+            cell_width,
+            overflow,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn adding_within_range_is_the_same_for_every_overflow() {
+        for cell_width in WIDTHS {
+            let max = cell_width.max();
+            for overflow in [Overflow::Wrapping, Overflow::Saturating, Overflow::Trapping] {
+                let config = config(cell_width, overflow);
+                assert_eq!(config.add(max - 1, 1), Some(max));
+                assert_eq!(config.add(1, -1), Some(0));
+                assert_eq!(config.add(max / 2, -((max / 2) as i64)), Some(0));
+                assert_eq!(config.add(max, -1), Some(max - 1));
             }
         }
     }
+
+    #[test]
+    fn wrapping_goes_round_to_the_other_end() {
+        for cell_width in WIDTHS {
+            let max = cell_width.max();
+            let config = config(cell_width, Overflow::Wrapping);
+            assert_eq!(config.add(max, 1), Some(0), "{cell_width:?}");
+            assert_eq!(config.add(0, -1), Some(max), "{cell_width:?}");
+            assert_eq!(config.add(max, 3), Some(2), "{cell_width:?}");
+            assert_eq!(config.add(1, -3), Some(max - 1), "{cell_width:?}");
+        }
+
+        let config = config(CellWidth::U8, Overflow::Wrapping);
+        assert_eq!(config.add(5, i64::MIN), Some(5));
+        assert_eq!(config.add(5, i64::MAX), Some(4));
+    }
+
+    #[test]
+    fn saturating_stops_at_the_ends() {
+        for cell_width in WIDTHS {
+            let max = cell_width.max();
+            let config = config(cell_width, Overflow::Saturating);
+            assert_eq!(config.add(max, 1), Some(max), "{cell_width:?}");
+            assert_eq!(config.add(0, -1), Some(0), "{cell_width:?}");
+            assert_eq!(config.add(max - 1, i64::MAX), Some(max), "{cell_width:?}");
+            assert_eq!(config.add(1, i64::MIN), Some(0), "{cell_width:?}");
+        }
+    }
+
+    #[test]
+    fn trapping_fails_past_the_ends() {
+        for cell_width in WIDTHS {
+            let max = cell_width.max();
+            let config = config(cell_width, Overflow::Trapping);
+            assert_eq!(config.add(max, 1), None, "{cell_width:?}");
+            assert_eq!(config.add(0, -1), None, "{cell_width:?}");
+            assert_eq!(config.add(max - 1, i64::MAX), None, "{cell_width:?}");
+            assert_eq!(config.add(1, i64::MIN), None, "{cell_width:?}");
+        }
+    }
+
+    #[test]
+    fn the_most_negative_amount_is_handled_at_64_bits() {
+        let half = 1 << 63;
+        let wrapping = config(CellWidth::U64, Overflow::Wrapping);
+        assert_eq!(wrapping.add(0, i64::MIN), Some(half));
+        assert_eq!(wrapping.add(half, i64::MIN), Some(0));
+        assert_eq!(wrapping.add(u64::MAX, i64::MIN), Some(half - 1));
+
+        let saturating = config(CellWidth::U64, Overflow::Saturating);
+        assert_eq!(saturating.add(half - 1, i64::MIN), Some(0));
+        assert_eq!(saturating.add(u64::MAX, i64::MIN), Some(half - 1));
+
+        let trapping = config(CellWidth::U64, Overflow::Trapping);
+        assert_eq!(trapping.add(half - 1, i64::MIN), None);
+        assert_eq!(trapping.add(half, i64::MIN), Some(0));
+    }
+
+    fn run_machine(source: &str, config: Config) -> Result<Step, RuntimeError> {
+        let program = BF::parse(source.as_bytes()).unwrap();
+        let mut machine = Machine::new(&program, config);
+        machine.run()
+    }
+
+    #[test]
+    fn machine_reports_the_instruction_that_overflows() {
+        let trapping = config(CellWidth::U8, Overflow::Trapping);
+        assert_eq!(
+            run_machine("+>+-<-->", trapping.clone()),
+            Err(RuntimeError::CellOverflow { pc: 6, pointer: 0 })
+        );
+        assert_eq!(
+            run_machine(">>-", trapping.clone()),
+            Err(RuntimeError::CellOverflow { pc: 2, pointer: 2 })
+        );
+
+        // 255 increments fill an 8 bit cell, so the next one overflows.
+        let source = format!("{}+", "+".repeat(255));
+        assert_eq!(
+            run_machine(&source, trapping),
+            Err(RuntimeError::CellOverflow {
+                pc: 255,
+                pointer: 0
+            })
+        );
+        assert_eq!(
+            run_machine(&source, config(CellWidth::U16, Overflow::Trapping)),
+            Ok(Step::Halted)
+        );
+    }
 }
@@ -0,0 +1,378 @@
+use crate::bf::{Instruction, BF};
+use crate::interpreter::{Config, RuntimeError};
+
+/// Where a node was compiled from, for reporting errors and keeping source maps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Source {
+    /// The bf instruction the node starts at.
+    pub pc: usize,
+    pub origin: Option<usize>,
+}
+
+/// An operation on the tape. Offsets are relative to the pointer, so a pass can fold moves into
+/// the nodes around them. A program only reports moving outside the tape when it touches a cell
+/// there, or stays there.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeKind {
+    Add {
+        offset: isize,
+        amount: i64,
+    },
+
+    /// Set the cell to `value`, which wraps to the cell width like an `Add` to zero.
+    Set {
+        offset: isize,
+        value: i64,
+    },
+
+    Move(isize),
+    Input,
+    Output,
+
+    /// Add the current cell times each factor to the cell at its offset, then clear the current
+    /// cell, as in `[->++>+<<]`.
+    Multiply(Vec<(isize, i64)>),
+
+    /// Move by `step` until reaching a zero cell, as in `[>]`.
+    Scan(isize),
+
+    /// Repeat the body while the current cell isn't zero. A `balanced` loop always ends each
+    /// iteration at the cell it started at.
+    Loop {
+        body: Vec<Node>,
+        end: Source,
+        balanced: bool,
+    },
+
+    Break,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub kind: NodeKind,
+    pub source: Source,
+}
+
+/// A program as a tree of nodes that passes can rewrite.
+#[derive(Debug, Clone, Default)]
+pub struct Ir {
+    pub nodes: Vec<Node>,
+    pub input: Vec<u8>,
+}
+
+/// Net pointer movement of a loop body, or `None` if it can't be known without running it.
+pub fn balance(nodes: &[Node]) -> Option<isize> {
+    let mut distance = 0isize;
+    for node in nodes {
+        match &node.kind {
+            NodeKind::Move(step) => distance += step,
+            NodeKind::Scan(_) => return None,
+            NodeKind::Loop { balanced, .. } if !balanced => return None,
+            _ => {}
+        }
+    }
+    Some(distance)
+}
+
+impl Ir {
+    /// Translate each bf instruction into its own node.
+    pub fn from_bf(program: &BF) -> Result<Self, RuntimeError> {
+        let mut stack = vec![(Vec::<Node>::new(), None)];
+        for (pc, instruction) in program.code().iter().enumerate() {
+            let source = Source {
+                pc,
+                origin: program.origin(pc),
+            };
+
+            let kind = match instruction {
+                Instruction::Add => NodeKind::Add {
+                    offset: 0,
+                    amount: 1,
+                },
+                Instruction::Subtract => NodeKind::Add {
+                    offset: 0,
+                    amount: -1,
+                },
+                Instruction::Left => NodeKind::Move(-1),
+                Instruction::Right => NodeKind::Move(1),
+                Instruction::Input => NodeKind::Input,
+                Instruction::Output => NodeKind::Output,
+                Instruction::Break => NodeKind::Break,
+
+                Instruction::OpenLoop => {
+                    stack.push((Vec::new(), Some(source)));
+                    continue;
+                }
+                Instruction::CloseLoop => {
+                    let (body, start) = stack.pop().expect("The outermost level is never popped");
+                    let Some(start) = start else {
+                        return Err(RuntimeError::UnmatchedBracket { pc });
+                    };
+
+                    let balanced = balance(&body) == Some(0);
+                    let node = Node {
+                        kind: NodeKind::Loop {
+                            body,
+                            end: source,
+                            balanced,
+                        },
+                        source: start,
+                    };
+                    stack.last_mut().unwrap().0.push(node);
+                    continue;
+                }
+            };
+
+            stack.last_mut().unwrap().0.push(Node { kind, source });
+        }
+
+        let (nodes, start) = stack.pop().unwrap();
+        if let Some(start) = start {
+            return Err(RuntimeError::UnmatchedBracket { pc: start.pc });
+        }
+
+        Ok(Self {
+            nodes,
+            input: program.input.clone(),
+        })
+    }
+
+    /// Lower back into bf, attributing each instruction to the node it came from.
+    pub fn to_bf(&self) -> BF {
+        let mut emitter = Emitter {
+            bf: BF::new(),
+            pointer: 0,
+        };
+        emitter.nodes(&self.nodes);
+        emitter.bf.input = self.input.clone();
+        emitter.bf
+    }
+}
+
+/// Writes bf for nodes, only moving the pointer when a cell is used.
+struct Emitter {
+    bf: BF,
+
+    /// Where the real pointer is, relative to where the nodes think it is.
+    pointer: isize,
+}
+
+impl Emitter {
+    fn repeat(&mut self, count: i64, up: Instruction, down: Instruction, source: Source) {
+        let instruction = if count < 0 { down } else { up };
+        for _ in 0..count.unsigned_abs() {
+            self.bf.push_from(instruction, source.origin);
+        }
+    }
+
+    fn move_to(&mut self, offset: isize, source: Source) {
+        let distance = offset - self.pointer;
+        self.repeat(
+            distance as i64,
+            Instruction::Right,
+            Instruction::Left,
+            source,
+        );
+        self.pointer = offset;
+    }
+
+    fn push(&mut self, instruction: Instruction, source: Source) {
+        self.bf.push_from(instruction, source.origin);
+    }
+
+    fn nodes(&mut self, nodes: &[Node]) {
+        for node in nodes {
+            let source = node.source;
+            match &node.kind {
+                NodeKind::Add { offset, amount } => {
+                    self.move_to(*offset, source);
+                    self.repeat(*amount, Instruction::Add, Instruction::Subtract, source);
+                }
+                NodeKind::Set { offset, value } => {
+                    self.move_to(*offset, source);
+                    self.push(Instruction::OpenLoop, source);
+                    self.push(Instruction::Subtract, source);
+                    self.push(Instruction::CloseLoop, source);
+                    self.repeat(*value, Instruction::Add, Instruction::Subtract, source);
+                }
+                NodeKind::Move(distance) => self.pointer -= distance,
+
+                NodeKind::Input => {
+                    self.move_to(0, source);
+                    self.push(Instruction::Input, source);
+                }
+                NodeKind::Output => {
+                    self.move_to(0, source);
+                    self.push(Instruction::Output, source);
+                }
+                NodeKind::Break => {
+                    self.move_to(0, source);
+                    self.push(Instruction::Break, source);
+                }
+
+                NodeKind::Multiply(targets) => {
+                    self.move_to(0, source);
+                    self.push(Instruction::OpenLoop, source);
+                    self.push(Instruction::Subtract, source);
+                    for (offset, factor) in targets {
+                        self.move_to(*offset, source);
+                        self.repeat(*factor, Instruction::Add, Instruction::Subtract, source);
+                    }
+                    self.move_to(0, source);
+                    self.push(Instruction::CloseLoop, source);
+                }
+                NodeKind::Scan(step) => {
+                    self.move_to(0, source);
+                    self.push(Instruction::OpenLoop, source);
+                    self.move_to(*step, source);
+                    self.pointer = 0;
+                    self.push(Instruction::CloseLoop, source);
+                }
+
+                NodeKind::Loop { body, end, .. } => {
+                    self.move_to(0, source);
+                    self.push(Instruction::OpenLoop, source);
+                    self.nodes(body);
+                    self.move_to(0, *end);
+                    self.push(Instruction::CloseLoop, *end);
+                }
+            }
+        }
+    }
+}
+
+/// Rewrites the nodes of a program, including those inside loops.
+pub type Pass = fn(&mut Vec<Node>, &Config);
+
+/// Runs a sequence of passes over a program.
+#[derive(Default)]
+pub struct PassManager {
+    passes: Vec<(&'static str, Pass)>,
+}
+
+impl PassManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_pass(mut self, name: &'static str, pass: Pass) -> Self {
+        self.passes.push((name, pass));
+        self
+    }
+
+    /// Remove every pass called `name`.
+    pub fn without_pass(mut self, name: &str) -> Self {
+        self.passes.retain(|(pass, _)| *pass != name);
+        self
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.passes.iter().map(|(name, _)| *name)
+    }
+
+    /// Run each pass in order, assuming the program runs with `config`.
+    pub fn run(&self, ir: &mut Ir, config: &Config) {
+        for (_, pass) in &self.passes {
+            pass(&mut ir.nodes, config);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Ir {
+        Ir::from_bf(&BF::parse(source.as_bytes()).unwrap()).unwrap()
+    }
+
+    fn kinds(nodes: &[Node]) -> Vec<NodeKind> {
+        nodes.iter().map(|node| node.kind.clone()).collect()
+    }
+
+    fn to_bf(nodes: Vec<NodeKind>) -> String {
+        let source = Source {
+            pc: 0,
+            origin: None,
+        };
+        let ir = Ir {
+            nodes: nodes
+                .into_iter()
+                .map(|kind| Node { kind, source })
+                .collect(),
+            input: Vec::new(),
+        };
+
+        let mut output = Vec::new();
+        ir.to_bf().write(&mut output).unwrap();
+        String::from_utf8(output).unwrap().trim_end().to_owned()
+    }
+
+    #[test]
+    fn each_instruction_becomes_a_node() {
+        let ir = parse("+<.[,]");
+        let NodeKind::Loop { body, balanced, .. } = &ir.nodes[3].kind else {
+            panic!("expected a loop, got {:?}", ir.nodes[3]);
+        };
+        assert_eq!(
+            kinds(&ir.nodes[..3]),
+            [
+                NodeKind::Add {
+                    offset: 0,
+                    amount: 1
+                },
+                NodeKind::Move(-1),
+                NodeKind::Output,
+            ]
+        );
+        assert_eq!(kinds(body), [NodeKind::Input]);
+        assert!(balanced);
+    }
+
+    #[test]
+    fn loops_know_if_they_are_balanced() {
+        let balanced = |source| match &parse(source).nodes[0].kind {
+            NodeKind::Loop { balanced, .. } => *balanced,
+            kind => panic!("expected a loop, got {kind:?}"),
+        };
+        assert!(balanced("[>+<-]"));
+        assert!(!balanced("[>]"));
+        assert!(!balanced("[[>]<]"));
+    }
+
+    #[test]
+    fn unmatched_brackets_are_errors() {
+        let error = |source: &str| Ir::from_bf(&BF::parse(source.as_bytes()).unwrap()).err();
+        assert_eq!(error("+]"), Some(RuntimeError::UnmatchedBracket { pc: 1 }));
+        assert_eq!(
+            error("+[[]"),
+            Some(RuntimeError::UnmatchedBracket { pc: 1 })
+        );
+    }
+
+    #[test]
+    fn lowering_only_moves_to_used_cells() {
+        assert_eq!(
+            to_bf(vec![
+                NodeKind::Add {
+                    offset: 2,
+                    amount: 1
+                },
+                NodeKind::Set {
+                    offset: -1,
+                    value: 2
+                },
+                NodeKind::Move(3),
+            ]),
+            ">>+<<<[-]++"
+        );
+        assert_eq!(
+            to_bf(vec![
+                NodeKind::Move(2),
+                NodeKind::Multiply(vec![(-1, 3)]),
+                NodeKind::Output,
+            ]),
+            ">>[-<+++>].",
+        );
+    }
+}
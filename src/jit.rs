@@ -0,0 +1,560 @@
+use crate::bf::BF;
+use crate::bytecode::{Bytecode, Op};
+use crate::interpreter::{
+    read_byte, CellWidth, Config, Overflow, RunStats, RuntimeError, DEFAULT_MEMORY_SIZE,
+};
+use std::ffi::c_void;
+use std::io::{Read, Write};
+
+const PROT_READ: i32 = 1;
+const PROT_WRITE: i32 = 2;
+const PROT_EXEC: i32 = 4;
+const MAP_PRIVATE: i32 = 2;
+const MAP_ANONYMOUS: i32 = 0x20;
+const MAP_FAILED: *mut c_void = usize::MAX as *mut c_void;
+
+extern "C" {
+    fn mmap(
+        addr: *mut c_void,
+        length: usize,
+        prot: i32,
+        flags: i32,
+        fd: i32,
+        offset: i64,
+    ) -> *mut c_void;
+    fn mprotect(addr: *mut c_void, length: usize, prot: i32) -> i32;
+    fn munmap(addr: *mut c_void, length: usize) -> i32;
+}
+
+const STATUS_HALTED: u32 = 0;
+const STATUS_POINTER: u32 = 1;
+const STATUS_IO: u32 = 2;
+const STATUS_STEP_LIMIT: u32 = 3;
+
+/// Shared with the generated code, which writes the first four fields before returning.
+#[repr(C)]
+struct Context<'a> {
+    pc: u64,
+    pointer: u64,
+    max_pointer: u64,
+    steps_left: u64,
+
+    config: &'a Config,
+    input: &'a mut dyn Read,
+    output: &'a mut dyn Write,
+    on_break: &'a mut dyn FnMut(&[u64], usize) -> std::io::Result<()>,
+    error: Option<std::io::Error>,
+}
+
+#[repr(C)]
+struct InputResult {
+    value: u64,
+    failed: u64,
+}
+
+extern "sysv64" fn jit_input(context: *mut Context, cell: u64) -> InputResult {
+    let context = unsafe { &mut *context };
+    let read = context
+        .output
+        .flush()
+        .and_then(|_| read_byte(&mut context.input));
+
+    match read {
+        Ok(byte) => InputResult {
+            value: byte.map_or_else(|| context.config.end_of_input(cell), u64::from),
+            failed: 0,
+        },
+        Err(err) => {
+            context.error = Some(err);
+            InputResult {
+                value: cell,
+                failed: 1,
+            }
+        }
+    }
+}
+
+extern "sysv64" fn jit_output(context: *mut Context, cell: u64) -> u64 {
+    let context = unsafe { &mut *context };
+    match context.output.write_all(&[cell as u8]) {
+        Ok(()) => 0,
+        Err(err) => {
+            context.error = Some(err);
+            1
+        }
+    }
+}
+
+extern "sysv64" fn jit_break(
+    context: *mut Context,
+    memory: *const u8,
+    pointer: usize,
+    max_pointer: usize,
+) -> u64 {
+    let context = unsafe { &mut *context };
+    let width = context.config.cell_width;
+    let bytes =
+        unsafe { std::slice::from_raw_parts(memory, (max_pointer + 1) * cell_bytes(width)) };
+    let memory = read_cells(bytes, width);
+
+    match (context.on_break)(&memory, pointer) {
+        Ok(()) => 0,
+        Err(err) => {
+            context.error = Some(err);
+            1
+        }
+    }
+}
+
+fn cell_bytes(width: CellWidth) -> usize {
+    width.bits() as usize / 8
+}
+
+fn read_cells(bytes: &[u8], width: CellWidth) -> Vec<u64> {
+    bytes
+        .chunks(cell_bytes(width))
+        .map(|cell| {
+            let mut value = [0u8; 8];
+            value[..cell.len()].copy_from_slice(cell);
+            u64::from_le_bytes(value)
+        })
+        .collect()
+}
+
+/// Where a forward jump should land once the code is laid out.
+enum Target {
+    Op(usize),
+    PointerError,
+    IoError,
+    StepLimit,
+}
+
+/// Emits x86-64 machine code. While running, `rbx` is the pointer, `rbp` the highest cell
+/// reached, `r12` the base of memory, `r13` the memory size, `r14` the context and `r15` the
+/// number of steps left.
+struct Assembler {
+    code: Vec<u8>,
+    width: CellWidth,
+    fixups: Vec<(usize, Target)>,
+}
+
+const RCX: u8 = 1;
+const RBX: u8 = 3;
+
+impl Assembler {
+    fn emit(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    fn emit_u32(&mut self, value: u32) {
+        self.emit(&value.to_le_bytes());
+    }
+
+    fn emit_u64(&mut self, value: u64) {
+        self.emit(&value.to_le_bytes());
+    }
+
+    /// Emit a jump with a 32 bit displacement to be filled in later.
+    fn jump(&mut self, opcode: &[u8], target: Target) {
+        self.emit(opcode);
+        self.fixups.push((self.code.len(), target));
+        self.emit_u32(0);
+    }
+
+    /// Emit a jump with a 32 bit displacement, returning where to patch it.
+    fn local_jump(&mut self, opcode: &[u8]) -> usize {
+        self.emit(opcode);
+        self.emit_u32(0);
+        self.code.len() - 4
+    }
+
+    fn patch(&mut self, at: usize, target: usize) {
+        let displacement = (target as i64 - (at as i64 + 4)) as i32;
+        self.code[at..at + 4].copy_from_slice(&displacement.to_le_bytes());
+    }
+
+    /// The `[r12 + index * cell size]` operand, with `rax` as the register.
+    fn cell_operand(&mut self, index: u8) {
+        let scale = match self.width {
+            CellWidth::U8 => 0,
+            CellWidth::U16 => 1,
+            CellWidth::U32 => 2,
+            CellWidth::U64 => 3,
+        };
+        self.emit(&[0b00_000_100, scale << 6 | index << 3 | 0b100]);
+    }
+
+    /// `op [cell], al/ax/eax/rax`
+    fn cell_from_rax(&mut self, opcode_byte: u8, opcode: u8, index: u8) {
+        match self.width {
+            CellWidth::U8 => self.emit(&[0x41, opcode_byte]),
+            CellWidth::U16 => self.emit(&[0x66, 0x41, opcode]),
+            CellWidth::U32 => self.emit(&[0x41, opcode]),
+            CellWidth::U64 => self.emit(&[0x49, opcode]),
+        }
+        self.cell_operand(index);
+    }
+
+    fn store_cell(&mut self, index: u8) {
+        self.cell_from_rax(0x88, 0x89, index);
+    }
+
+    fn add_to_cell(&mut self, index: u8) {
+        self.cell_from_rax(0x00, 0x01, index);
+    }
+
+    /// Zero extend a cell into `rax`.
+    fn load_cell(&mut self, index: u8) {
+        match self.width {
+            CellWidth::U8 => self.emit(&[0x49, 0x0F, 0xB6]),
+            CellWidth::U16 => self.emit(&[0x49, 0x0F, 0xB7]),
+            CellWidth::U32 => self.emit(&[0x41, 0x8B]),
+            CellWidth::U64 => self.emit(&[0x49, 0x8B]),
+        }
+        self.cell_operand(index);
+    }
+
+    fn mov_rax(&mut self, value: u64) {
+        self.emit(&[0x48, 0xB8]);
+        self.emit_u64(value);
+    }
+
+    fn mov_rdx(&mut self, value: u64) {
+        self.emit(&[0x48, 0xBA]);
+        self.emit_u64(value);
+    }
+
+    fn call(&mut self, function: usize) {
+        self.mov_rax(function as u64);
+        self.emit(&[0xFF, 0xD0]);
+    }
+
+    /// Leave through `target` with `pc` in `esi`, unless the last test set the zero flag.
+    fn fail_unless_zero(&mut self, pc: usize, target: Target) {
+        self.emit(&[0x74, 10]); // jz +10
+        self.emit(&[0xBE]); // mov esi, pc
+        self.emit_u32(pc as u32);
+        self.jump(&[0xE9], target);
+    }
+
+    /// Check the cell index in `rbx` or `rcx` is within memory, and track the highest reached.
+    fn check_pointer(&mut self, index: u8, pc: usize) {
+        let (compare_size, copy_to_rdx, compare_max, move_max) = match index {
+            RBX => (
+                [0x4C, 0x39, 0xEB],
+                [0x48, 0x89, 0xDA],
+                [0x48, 0x39, 0xEB],
+                0xEB,
+            ),
+            _ => (
+                [0x4C, 0x39, 0xE9],
+                [0x48, 0x89, 0xCA],
+                [0x48, 0x39, 0xE9],
+                0xE9,
+            ),
+        };
+
+        self.emit(&compare_size); // cmp index, r13
+        self.emit(&[0x72, 13]); // jb +13
+        self.emit(&copy_to_rdx); // mov rdx, index
+        self.emit(&[0xBE]); // mov esi, pc
+        self.emit_u32(pc as u32);
+        self.jump(&[0xE9], Target::PointerError);
+
+        self.emit(&compare_max); // cmp index, rbp
+        self.emit(&[0x48, 0x0F, 0x47, move_max]); // cmova rbp, index
+    }
+
+    fn move_pointer(&mut self, distance: isize, pc: usize) {
+        self.mov_rax(distance as u64);
+        self.emit(&[0x48, 0x01, 0xC3]); // add rbx, rax
+        self.check_pointer(RBX, pc);
+    }
+
+    /// Put the checked index of the cell at `offset` from the pointer in `rcx`, and return the
+    /// register holding it.
+    fn offset_index(&mut self, offset: isize, pc: usize) -> u8 {
+        if offset == 0 {
+            return RBX;
+        }
+
+        self.emit(&[0x48, 0x89, 0xD9]); // mov rcx, rbx
+        self.mov_rdx(offset as u64);
+        self.emit(&[0x48, 0x01, 0xD1]); // add rcx, rdx
+        self.check_pointer(RCX, pc);
+        RCX
+    }
+
+    fn test_rax(&mut self) {
+        self.emit(&[0x48, 0x85, 0xC0]);
+    }
+
+    fn op(&mut self, op: Op, pc: usize) {
+        match op {
+            Op::Add { offset, amount } => {
+                let index = self.offset_index(offset, pc);
+                self.mov_rax(amount as u64);
+                self.add_to_cell(index);
+            }
+            Op::Set { offset, value } => {
+                let index = self.offset_index(offset, pc);
+                self.mov_rax(value as u64);
+                self.store_cell(index);
+            }
+            Op::Move(distance) => self.move_pointer(distance, pc),
+
+            Op::Input => {
+                self.load_cell(RBX);
+                self.emit(&[0x4C, 0x89, 0xF7]); // mov rdi, r14
+                self.emit(&[0x48, 0x89, 0xC6]); // mov rsi, rax
+                self.call(jit_input as *const () as usize);
+                self.emit(&[0x48, 0x85, 0xD2]); // test rdx, rdx
+                self.fail_unless_zero(pc, Target::IoError);
+                self.store_cell(RBX);
+            }
+            Op::Output => {
+                self.load_cell(RBX);
+                self.emit(&[0x4C, 0x89, 0xF7]); // mov rdi, r14
+                self.emit(&[0x48, 0x89, 0xC6]); // mov rsi, rax
+                self.call(jit_output as *const () as usize);
+                self.test_rax();
+                self.fail_unless_zero(pc, Target::IoError);
+            }
+
+            Op::JumpIfZero(target) => {
+                self.load_cell(RBX);
+                self.test_rax();
+                self.jump(&[0x0F, 0x84], Target::Op(target));
+            }
+            Op::JumpIfNotZero(target) => {
+                self.load_cell(RBX);
+                self.test_rax();
+                self.jump(&[0x0F, 0x85], Target::Op(target));
+            }
+
+            Op::Multiply { offset, factor } => {
+                self.load_cell(RBX);
+                self.test_rax();
+                let skip = self.local_jump(&[0x0F, 0x84]);
+
+                let index = self.offset_index(offset, pc);
+                self.mov_rdx(factor as u64);
+                self.emit(&[0x48, 0x0F, 0xAF, 0xC2]); // imul rax, rdx
+                self.add_to_cell(index);
+                self.patch(skip, self.code.len());
+            }
+            Op::Scan(step) => {
+                let start = self.code.len();
+                self.load_cell(RBX);
+                self.test_rax();
+                let done = self.local_jump(&[0x0F, 0x84]);
+                self.move_pointer(step, pc);
+                let back = self.local_jump(&[0xE9]);
+                self.patch(back, start);
+                self.patch(done, self.code.len());
+            }
+
+            Op::Break => {
+                self.emit(&[0x4C, 0x89, 0xF7]); // mov rdi, r14
+                self.emit(&[0x4C, 0x89, 0xE6]); // mov rsi, r12
+                self.emit(&[0x48, 0x89, 0xDA]); // mov rdx, rbx
+                self.emit(&[0x48, 0x89, 0xE9]); // mov rcx, rbp
+                self.call(jit_break as *const () as usize);
+                self.test_rax();
+                self.fail_unless_zero(pc, Target::IoError);
+            }
+        }
+    }
+
+    /// Return `status`, through a jump to the epilogue that's patched later.
+    fn exit(&mut self, status: u32) -> usize {
+        self.emit(&[0xB8]); // mov eax, status
+        self.emit_u32(status);
+        self.local_jump(&[0xE9])
+    }
+
+    fn assemble(bytecode: &Bytecode, width: CellWidth) -> Vec<u8> {
+        let mut assembler = Self {
+            code: Vec::new(),
+            width,
+            fixups: Vec::new(),
+        };
+
+        // Save the callee saved registers, keeping the stack 16 byte aligned for calls.
+        assembler.emit(&[0x53, 0x55, 0x41, 0x54, 0x41, 0x55, 0x41, 0x56, 0x41, 0x57]);
+        assembler.emit(&[0x48, 0x83, 0xEC, 0x08]); // sub rsp, 8
+        assembler.emit(&[0x49, 0x89, 0xFC]); // mov r12, rdi
+        assembler.emit(&[0x49, 0x89, 0xF5]); // mov r13, rsi
+        assembler.emit(&[0x49, 0x89, 0xD6]); // mov r14, rdx
+        assembler.emit(&[0x49, 0x89, 0xCF]); // mov r15, rcx
+        assembler.emit(&[0x31, 0xDB, 0x31, 0xED]); // xor ebx, ebx; xor ebp, ebp
+
+        let mut op_offsets = Vec::new();
+        for (index, op) in bytecode.ops().iter().enumerate() {
+            op_offsets.push(assembler.code.len());
+            assembler.emit(&[0x49, 0x83, 0xEF, 0x01]); // sub r15, 1
+            assembler.jump(&[0x0F, 0x82], Target::StepLimit);
+            assembler.op(*op, bytecode.pc(index));
+        }
+        op_offsets.push(assembler.code.len());
+
+        let halted = assembler.exit(STATUS_HALTED);
+
+        let pointer_error = assembler.code.len();
+        assembler.emit(&[0x49, 0x89, 0x36]); // mov [r14], rsi
+        assembler.emit(&[0x49, 0x89, 0x56, 0x08]); // mov [r14 + 8], rdx
+        let pointer_exit = assembler.exit(STATUS_POINTER);
+
+        let io_error = assembler.code.len();
+        assembler.emit(&[0x49, 0x89, 0x36]); // mov [r14], rsi
+        let io_exit = assembler.exit(STATUS_IO);
+
+        let step_limit = assembler.code.len();
+        let step_limit_exit = assembler.exit(STATUS_STEP_LIMIT);
+
+        let epilogue = assembler.code.len();
+        assembler.emit(&[0x49, 0x89, 0x6E, 0x10]); // mov [r14 + 16], rbp
+        assembler.emit(&[0x4D, 0x89, 0x7E, 0x18]); // mov [r14 + 24], r15
+        assembler.emit(&[0x48, 0x83, 0xC4, 0x08]); // add rsp, 8
+        assembler.emit(&[
+            0x41, 0x5F, 0x41, 0x5E, 0x41, 0x5D, 0x41, 0x5C, 0x5D, 0x5B, 0xC3,
+        ]);
+
+        for exit in [halted, pointer_exit, io_exit, step_limit_exit] {
+            assembler.patch(exit, epilogue);
+        }
+        for (at, target) in std::mem::take(&mut assembler.fixups) {
+            let target = match target {
+                Target::Op(index) => op_offsets[index],
+                Target::PointerError => pointer_error,
+                Target::IoError => io_error,
+                Target::StepLimit => step_limit,
+            };
+            assembler.patch(at, target);
+        }
+
+        assembler.code
+    }
+}
+
+struct ExecutableBuffer {
+    pointer: *mut c_void,
+    length: usize,
+}
+
+impl ExecutableBuffer {
+    fn new(code: &[u8]) -> std::io::Result<Self> {
+        let length = code.len();
+        let pointer = unsafe {
+            mmap(
+                std::ptr::null_mut(),
+                length,
+                PROT_READ | PROT_WRITE,
+                MAP_PRIVATE | MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if pointer == MAP_FAILED {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        let buffer = Self { pointer, length };
+        unsafe {
+            std::ptr::copy_nonoverlapping(code.as_ptr(), pointer as *mut u8, length);
+            if mprotect(pointer, length, PROT_READ | PROT_EXEC) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        Ok(buffer)
+    }
+}
+
+impl Drop for ExecutableBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            munmap(self.pointer, self.length);
+        }
+    }
+}
+
+type JitFunction = unsafe extern "sysv64" fn(*mut u8, usize, *mut Context, u64) -> u32;
+
+/// A program compiled to native x86-64 code.
+pub struct Jit {
+    buffer: ExecutableBuffer,
+    config: Config,
+}
+
+impl Jit {
+    pub fn compile(program: &BF, config: Config) -> Result<Self, RuntimeError> {
+        if config.overflow != Overflow::Wrapping {
+            return Err(RuntimeError::Unsupported(
+                "The JIT only supports wrapping cells".to_owned(),
+            ));
+        }
+
+        let bytecode = Bytecode::compile(program, &config)?;
+        let code = Assembler::assemble(&bytecode, config.cell_width);
+        let buffer = ExecutableBuffer::new(&code).map_err(|err| RuntimeError::Io {
+            pc: 0,
+            kind: err.kind(),
+        })?;
+        Ok(Self { buffer, config })
+    }
+
+    /// Run the program, calling `on_break` with the memory and pointer at each `*`.
+    pub fn run(
+        &self,
+        mut input: impl Read,
+        mut output: impl Write,
+        mut on_break: impl FnMut(&[u64], usize) -> std::io::Result<()>,
+    ) -> Result<RunStats, RuntimeError> {
+        let size = self.config.memory_size.unwrap_or(DEFAULT_MEMORY_SIZE);
+        let mut memory = vec![0u8; size * cell_bytes(self.config.cell_width)];
+        let steps = self.config.step_limit.unwrap_or(u64::MAX);
+
+        let mut context = Context {
+            pc: 0,
+            pointer: 0,
+            max_pointer: 0,
+            steps_left: 0,
+            config: &self.config,
+            input: &mut input,
+            output: &mut output,
+            on_break: &mut on_break,
+            error: None,
+        };
+
+        let function: JitFunction = unsafe { std::mem::transmute(self.buffer.pointer) };
+        let status = unsafe { function(memory.as_mut_ptr(), size, &mut context, steps) };
+
+        let pc = context.pc as usize;
+        let stats = RunStats {
+            steps: steps.wrapping_sub(context.steps_left),
+            cells: context.max_pointer as usize + 1,
+        };
+        match status {
+            STATUS_HALTED => match context.output.flush() {
+                Ok(()) => Ok(stats),
+                Err(err) => Err(RuntimeError::Io {
+                    pc,
+                    kind: err.kind(),
+                }),
+            },
+            STATUS_POINTER if (context.pointer as isize) < 0 => {
+                Err(RuntimeError::PointerUnderflow { pc })
+            }
+            STATUS_POINTER => Err(RuntimeError::PointerOverflow { pc }),
+            STATUS_STEP_LIMIT => Err(RuntimeError::StepLimitExceeded {
+                limit: self.config.step_limit.unwrap_or(u64::MAX),
+            }),
+            _ => {
+                let kind = context
+                    .error
+                    .map_or(std::io::ErrorKind::Other, |err| err.kind());
+                Err(RuntimeError::Io { pc, kind })
+            }
+        }
+    }
+}
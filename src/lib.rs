@@ -0,0 +1,11 @@
+pub mod bf;
+pub mod bytecode;
+pub mod codegen;
+pub mod compiler;
+pub mod debugger;
+pub mod interpreter;
+pub mod ir;
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+pub mod jit;
+pub mod simplify;
+pub mod source_map;
@@ -1,41 +1,200 @@
-use bf::BF;
-use compiler::evaluate_file;
-use interpreter::run_program;
-use simplify::simplify_program;
+use bfmacro::bf::{Eof, Instruction, BF};
+use bfmacro::bytecode::{Bytecode, Vm};
+use bfmacro::codegen::{self, Target};
+use bfmacro::compiler::{Compiler, Options};
+use bfmacro::debugger::Debugger;
+use bfmacro::interpreter::{CellWidth, Config, Overflow, RuntimeError, Step};
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+use bfmacro::jit::Jit;
+use bfmacro::simplify::{simplify_program, simplify_with, standard_passes};
+use bfmacro::source_map::SourceMap;
+use std::collections::HashMap;
 use std::env::{args, Args};
 use std::fs::File;
-use std::io::stdout;
+use std::io::{stderr, stdin, stdout, BufReader, BufWriter, Read, Write};
 use std::process::ExitCode;
 
-mod bf;
-mod compiler;
-mod interpreter;
-mod simplify;
-
 fn usage(executable: &str) {
-    eprintln!("Usage: {executable} <action> <file>...");
+    eprintln!("Usage: {executable} <action> [options] <file>...");
     eprintln!();
     eprintln!("Actions:");
     eprintln!("   compile    Compile bfmacro files into bf");
     eprintln!("   run        Run the given bf file");
+    eprintln!("   debug      Debug the given bf or bfmacro file");
     eprintln!("   format     Format an simplify bf files");
     eprintln!();
+    eprintln!("Compile options:");
+    eprintln!("   --message-format=<human|json>    How to report diagnostics");
+    eprintln!("   --source-map=<file>              Write a source map for the output");
+    eprintln!(
+        "   --target=<language>              bf (default), asm, c, rust, rust-lib, wasm or wat"
+    );
+    eprintln!("   --cell-width, --memory-size      Cells of the generated code, as for run");
+    eprintln!(
+        "   --eof=<unchanged|0|-1>           What ',' does at the end of input on the target"
+    );
+    eprintln!("   --stats                          Report how much simplifying saved, on stderr");
+    eprintln!();
+    eprintln!("Run and debug options:");
+    eprintln!("   --cell-width=<8|16|32|64>        Number of bits in each cell (default 8)");
+    eprintln!("   --overflow=<wrap|trap|saturate>  What happens when a cell overflows");
+    eprintln!("   --eof=<unchanged|0|-1>           What ',' does at the end of input (default 0)");
+    eprintln!("   --input=<file>                   Read ',' from a file ('-' for stdin)");
+    eprintln!("   --step-limit=<n>                 Stop with an error after n steps");
+    eprintln!("                                    (run counts each folded op as one step)");
+    eprintln!(
+        "   --memory-size=<n>                Number of cells (default unlimited, 2^20 with --jit)"
+    );
+    eprintln!();
+    eprintln!("Run options:");
+    eprintln!(
+        "   --jit                            Compile to native x86-64 code (wrapping cells only)"
+    );
+    eprintln!();
+    eprintln!("When running without --input, ',' reads the input embedded after a '!' in the");
+    eprintln!("bf file, or stdin if there is none.");
+    eprintln!();
+    eprintln!("Debug options:");
+    eprintln!("   --source-map=<file>              Source map of a bf file (default <file>.map)");
+    eprintln!("   --frame=<name>                   Label cells with the slots of a frame");
+    eprintln!();
 }
 
-fn compile(executable: &str, args: Args) -> std::io::Result<ExitCode> {
-    if args.len() == 0 {
+struct Arguments {
+    files: Vec<String>,
+    options: HashMap<String, String>,
+}
+
+impl Arguments {
+    fn option(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(String::as_str)
+    }
+}
+
+fn parse_arguments(executable: &str, args: Args, allowed_options: &[&str]) -> Option<Arguments> {
+    let mut arguments = Arguments {
+        files: Vec::new(),
+        options: HashMap::new(),
+    };
+
+    for arg in args {
+        let Some(option) = arg.strip_prefix("--") else {
+            arguments.files.push(arg);
+            continue;
+        };
+
+        let (name, value) = match option.split_once('=') {
+            Some((name, value)) => (name.to_owned(), value.to_owned()),
+            None => (option.to_owned(), String::new()),
+        };
+
+        if !allowed_options.contains(&name.as_str()) {
+            usage(executable);
+            eprintln!("{executable}: error: unknown option '--{name}'");
+            return None;
+        }
+        arguments.options.insert(name, value);
+    }
+
+    if arguments.files.is_empty() {
         usage(executable);
         eprintln!("{executable}: error: no input files given");
+        return None;
+    }
+
+    Some(arguments)
+}
+
+fn compile(executable: &str, args: Args) -> std::io::Result<ExitCode> {
+    let options = [
+        "message-format",
+        "source-map",
+        "target",
+        "eof",
+        "cell-width",
+        "memory-size",
+        "stats",
+    ];
+    let Some(arguments) = parse_arguments(executable, args, &options) else {
+        return Ok(ExitCode::FAILURE);
+    };
+    let Some(config) = interpreter_config(executable, &arguments) else {
+        return Ok(ExitCode::FAILURE);
+    };
+
+    let source_map_path = arguments.option("source-map");
+    if source_map_path.is_some() && arguments.files.len() > 1 {
+        eprintln!("{executable}: error: can only write a source map for a single input file");
         return Ok(ExitCode::FAILURE);
     }
 
+    let target = match arguments.option("target") {
+        None | Some("bf") => None,
+        Some(name) => match Target::from_name(name) {
+            Some(target) => Some(target),
+            None => {
+                eprintln!("{executable}: error: unknown target '{name}'");
+                return Ok(ExitCode::FAILURE);
+            }
+        },
+    };
+    if target.is_some() && arguments.files.len() > 1 {
+        eprintln!("{executable}: error: can only generate code for a single input file");
+        return Ok(ExitCode::FAILURE);
+    }
+
+    let is_json = match arguments.option("message-format") {
+        None | Some("human") => false,
+        Some("json") => true,
+        Some(format) => {
+            eprintln!("{executable}: error: unknown message format '{format}'");
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+
+    // Stats compare the simplified program against the unsimplified one, so simplify here.
+    let stats = arguments.option("stats").is_some();
+    let mut compiler = Compiler::new().with_options(Options {
+        simplify: !stats,
+        eof: config.eof,
+    });
     let mut did_error = false;
-    for file_path in args {
-        let program = evaluate_file(&file_path)?;
-        if let Some(program) = program {
-            simplify_program(&program).write(stdout())?;
-        } else {
-            did_error = true;
+    for file_path in &arguments.files {
+        match compiler.compile_file(file_path) {
+            Ok(program) => {
+                let program = if stats {
+                    simplify_with_stats(file_path, &program)
+                } else {
+                    program
+                };
+
+                match target {
+                    Some(target) => {
+                        if let Err(err) = codegen::generate(target, &program, &config, stdout()) {
+                            eprintln!("{executable}: error: {err}");
+                            did_error = true;
+                        }
+                    }
+                    None => program.write(stdout())?,
+                }
+
+                if let Some(source_map_path) = source_map_path {
+                    let file = File::create(source_map_path)?;
+                    compiler
+                        .source_map()
+                        .write(&program, BufWriter::new(file))?;
+                }
+            }
+            Err(diagnostics) => {
+                for diagnostic in diagnostics {
+                    if is_json {
+                        eprintln!("{}", diagnostic.to_json(compiler.files()));
+                    } else {
+                        diagnostic.render(compiler.files(), stderr())?;
+                    }
+                }
+                did_error = true;
+            }
         }
     }
 
@@ -46,18 +205,252 @@ fn compile(executable: &str, args: Args) -> std::io::Result<ExitCode> {
     }
 }
 
-fn run(executable: &str, mut args: Args) -> std::io::Result<ExitCode> {
-    if args.len() == 0 {
-        usage(executable);
-        eprintln!("{executable}: error: no input files given");
+fn pointer_moves(program: &BF) -> usize {
+    program
+        .code()
+        .iter()
+        .filter(|instruction| matches!(instruction, Instruction::Left | Instruction::Right))
+        .count()
+}
+
+fn simplify_with_stats(file_path: &str, program: &BF) -> BF {
+    let simplified =
+        simplify_program(program).expect("Programs without errors have matching brackets");
+    let unordered = simplify_with(program, &standard_passes().without_pass("reorder"))
+        .expect("Programs without errors have matching brackets");
+
+    let (before, after) = (pointer_moves(&unordered), pointer_moves(&simplified));
+    eprintln!(
+        "{file_path}: {} instructions, {} after simplifying",
+        program.len(),
+        simplified.len()
+    );
+    eprintln!(
+        "{file_path}: {before} pointer moves without reordering, {after} with (saved {})",
+        before.saturating_sub(after)
+    );
+    simplified
+}
+
+fn eof_option(executable: &str, arguments: &Arguments) -> Option<Eof> {
+    match arguments.option("eof") {
+        None | Some("0") => Some(Eof::Zero),
+        Some("unchanged") => Some(Eof::Unchanged),
+        Some("-1") => Some(Eof::MinusOne),
+        Some(eof) => {
+            eprintln!("{executable}: error: invalid end of input behaviour '{eof}'");
+            None
+        }
+    }
+}
+
+const CONFIG_OPTIONS: [&str; 5] = ["cell-width", "overflow", "eof", "step-limit", "memory-size"];
+
+fn interpreter_config(executable: &str, arguments: &Arguments) -> Option<Config> {
+    let mut config = Config::default();
+
+    if let Some(bits) = arguments.option("cell-width") {
+        match bits.parse().ok().and_then(CellWidth::from_bits) {
+            Some(cell_width) => config.cell_width = cell_width,
+            None => {
+                eprintln!("{executable}: error: invalid cell width '{bits}'");
+                return None;
+            }
+        }
+    }
+
+    if let Some(size) = arguments.option("memory-size") {
+        match size.parse() {
+            Ok(size) if size > 0 => config.memory_size = Some(size),
+            _ => {
+                eprintln!("{executable}: error: invalid memory size '{size}'");
+                return None;
+            }
+        }
+    }
+
+    if let Some(limit) = arguments.option("step-limit") {
+        match limit.parse() {
+            Ok(limit) => config.step_limit = Some(limit),
+            Err(_) => {
+                eprintln!("{executable}: error: invalid step limit '{limit}'");
+                return None;
+            }
+        }
+    }
+
+    if let Some(overflow) = arguments.option("overflow") {
+        config.overflow = match overflow {
+            "wrap" => Overflow::Wrapping,
+            "trap" => Overflow::Trapping,
+            "saturate" => Overflow::Saturating,
+            _ => {
+                eprintln!("{executable}: error: invalid overflow behaviour '{overflow}'");
+                return None;
+            }
+        };
+    }
+
+    config.eof = eof_option(executable, arguments)?;
+    Some(config)
+}
+
+fn input_file(path: &str) -> std::io::Result<Box<dyn Read>> {
+    if path == "-" {
+        Ok(Box::new(stdin()))
+    } else {
+        Ok(Box::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+fn run(executable: &str, args: Args) -> std::io::Result<ExitCode> {
+    let options = [&CONFIG_OPTIONS[..], &["input", "jit"]].concat();
+    let Some(arguments) = parse_arguments(executable, args, &options) else {
+        return Ok(ExitCode::FAILURE);
+    };
+    let Some(config) = interpreter_config(executable, &arguments) else {
+        return Ok(ExitCode::FAILURE);
+    };
+
+    if arguments.files.len() > 1 {
+        eprintln!("{executable}: error: can only run a single file");
         return Ok(ExitCode::FAILURE);
     }
 
-    let file_path = args.next().unwrap();
-    let file = File::open(file_path)?;
+    let file = File::open(&arguments.files[0])?;
     let program = BF::parse(file)?;
-    run_program(program);
+    let input: Box<dyn Read> = match arguments.option("input") {
+        Some(path) => input_file(path)?,
+        None if !program.input.is_empty() => Box::new(program.input.as_slice()),
+        None => Box::new(stdin()),
+    };
+
+    let result = if arguments.option("jit").is_some() {
+        run_jit(&program, config, input)
+    } else {
+        run_bytecode(&program, config, input)?
+    };
+
+    if let Err(err) = result {
+        eprintln!("{executable}: error: {err}");
+        return Ok(ExitCode::FAILURE);
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn run_bytecode(
+    program: &BF,
+    config: Config,
+    input: impl Read,
+) -> std::io::Result<Result<(), RuntimeError>> {
+    let bytecode = match Bytecode::compile(program, &config) {
+        Ok(bytecode) => bytecode,
+        Err(err) => return Ok(Err(err)),
+    };
+
+    let mut vm = Vm::new(&bytecode, config)
+        .with_input(input)
+        .with_output(stdout());
+    loop {
+        match vm.run() {
+            Ok(Step::Break) => pause_at_break(vm.memory(), vm.pointer())?,
+            Ok(_) => return Ok(Ok(())),
+            Err(err) => return Ok(Err(err)),
+        }
+    }
+}
+
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+fn run_jit(program: &BF, config: Config, input: impl Read) -> Result<(), RuntimeError> {
+    let jit = Jit::compile(program, config)?;
+    jit.run(input, stdout(), pause_at_break)?;
+    Ok(())
+}
+
+#[cfg(not(all(target_arch = "x86_64", target_os = "linux")))]
+fn run_jit(_program: &BF, _config: Config, _input: impl Read) -> Result<(), RuntimeError> {
+    Err(RuntimeError::Unsupported(
+        "The JIT is only available on x86-64 Linux".to_owned(),
+    ))
+}
+
+fn pause_at_break(memory: &[u64], _pointer: usize) -> std::io::Result<()> {
+    let mut output = stdout().lock();
+    for cell in memory {
+        write!(output, "{cell:03} ")?;
+    }
+    writeln!(output)?;
+    writeln!(output)?;
+    output.flush()?;
+    drop(output);
+
+    let _ = stdin().read(&mut [0u8])?;
+    Ok(())
+}
+
+fn debug(executable: &str, args: Args) -> std::io::Result<ExitCode> {
+    let options = [&CONFIG_OPTIONS[..], &["source-map", "frame", "input"]].concat();
+    let Some(arguments) = parse_arguments(executable, args, &options) else {
+        return Ok(ExitCode::FAILURE);
+    };
+    let Some(config) = interpreter_config(executable, &arguments) else {
+        return Ok(ExitCode::FAILURE);
+    };
+
+    if arguments.files.len() > 1 {
+        eprintln!("{executable}: error: can only debug a single file");
+        return Ok(ExitCode::FAILURE);
+    }
+
+    let file_path = &arguments.files[0];
+    let (program, source_map, frames) = if file_path.ends_with(".bfm") {
+        let mut compiler = Compiler::new().with_options(Options {
+            eof: config.eof,
+            ..Options::default()
+        });
+        match compiler.compile_file(file_path) {
+            Ok(program) => {
+                let frames = compiler.frame_layouts().cloned().collect::<Vec<_>>();
+                (program, Some(compiler.source_map()), frames)
+            }
+            Err(diagnostics) => {
+                for diagnostic in diagnostics {
+                    diagnostic.render(compiler.files(), stderr())?;
+                }
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+    } else {
+        let mut program = BF::parse(File::open(file_path)?)?;
+        let source_map = match arguments.option("source-map") {
+            Some(source_map_path) => {
+                Some(SourceMap::read(&mut program, File::open(source_map_path)?)?)
+            }
+            None => match File::open(format!("{file_path}.map")) {
+                Ok(file) => Some(SourceMap::read(&mut program, file)?),
+                Err(_) => None,
+            },
+        };
+        (program, source_map, Vec::new())
+    };
 
+    let mut debugger = Debugger::new(&program, config, source_map, frames).with_output(stdout());
+    if let Some(path) = arguments.option("input") {
+        if path == "-" {
+            eprintln!("{executable}: error: stdin is used for debugger commands");
+            return Ok(ExitCode::FAILURE);
+        }
+        debugger = debugger.with_input(input_file(path)?);
+    }
+    if let Some(frame) = arguments.option("frame") {
+        if !debugger.select_frame(frame, 0) {
+            eprintln!("{executable}: error: no frame '{frame}'");
+            return Ok(ExitCode::FAILURE);
+        }
+    }
+
+    debugger.run(stdin().lock(), stdout())?;
     Ok(ExitCode::SUCCESS)
 }
 
@@ -69,9 +462,15 @@ fn format(executable: &str, args: Args) -> std::io::Result<ExitCode> {
     }
 
     for file_path in args {
-        let file = File::open(file_path)?;
+        let file = File::open(&file_path)?;
         let program = BF::parse(file)?;
-        simplify_program(&program).write(stdout())?;
+        match simplify_program(&program) {
+            Ok(simplified) => simplified.write(stdout())?,
+            Err(err) => {
+                eprintln!("{executable}: error: {file_path}: {err}");
+                return Ok(ExitCode::FAILURE);
+            }
+        }
     }
 
     Ok(ExitCode::SUCCESS)
@@ -91,6 +490,7 @@ fn main() -> std::io::Result<ExitCode> {
     match action.as_str() {
         "compile" => compile(&executable, args),
         "run" => run(&executable, args),
+        "debug" => debug(&executable, args),
         "format" => format(&executable, args),
         _ => {
             usage(&executable);
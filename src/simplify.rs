@@ -1,63 +1,712 @@
-use crate::bf::{Instruction, BF};
+use crate::bf::BF;
+use crate::interpreter::{CellWidth, Config, Overflow, RuntimeError};
+use crate::ir::{Ir, Node, NodeKind, PassManager};
+use std::collections::BTreeMap;
 
-fn resolve_combined_move(out: &mut BF, count: &mut i32) {
-    for _ in 0..count.abs() {
-        if *count > 0 {
-            out.push(Instruction::Right);
-        } else {
-            out.push(Instruction::Left);
+fn in_range(value: i64, config: &Config) -> bool {
+    value >= 0 && value as u64 <= config.cell_width.max()
+}
+
+/// The single node with the effect of `first` followed by `second`, if there is one.
+fn combine(first: &NodeKind, second: &NodeKind, config: &Config) -> Option<NodeKind> {
+    let wrapping = config.overflow == Overflow::Wrapping;
+    match (first, second) {
+        // Changes in both directions can only cancel out when wrapping.
+        (
+            NodeKind::Add { offset, amount },
+            NodeKind::Add {
+                offset: second_offset,
+                amount: second_amount,
+            },
+        ) if offset == second_offset && (wrapping || amount.signum() == second_amount.signum()) => {
+            Some(NodeKind::Add {
+                offset: *offset,
+                amount: amount.checked_add(*second_amount)?,
+            })
+        }
+
+        (
+            NodeKind::Set { offset, value },
+            NodeKind::Add {
+                offset: second_offset,
+                amount,
+            },
+        ) if offset == second_offset => {
+            let value = value.checked_add(*amount)?;
+            (wrapping || in_range(value, config)).then_some(NodeKind::Set {
+                offset: *offset,
+                value,
+            })
+        }
+
+        (
+            NodeKind::Set { offset, .. },
+            NodeKind::Set {
+                offset: second_offset,
+                ..
+            },
+        ) if offset == second_offset => Some(second.clone()),
+
+        // An add before a set can only be dropped if it can't trap.
+        (
+            NodeKind::Add { offset, .. },
+            NodeKind::Set {
+                offset: second_offset,
+                ..
+            },
+        ) if offset == second_offset && wrapping => Some(second.clone()),
+
+        (NodeKind::Move(distance), NodeKind::Move(second_distance)) => {
+            Some(NodeKind::Move(distance + second_distance))
+        }
+
+        _ => None,
+    }
+}
+
+fn is_noop(kind: &NodeKind) -> bool {
+    matches!(kind, NodeKind::Add { amount: 0, .. } | NodeKind::Move(0))
+}
+
+/// Combine adjacent changes to the same cell, and adjacent moves.
+pub fn fold(nodes: &mut Vec<Node>, config: &Config) {
+    let mut folded = Vec::<Node>::with_capacity(nodes.len());
+    for mut node in nodes.drain(..) {
+        if let NodeKind::Loop { body, .. } = &mut node.kind {
+            fold(body, config);
+        }
+
+        if let Some(last) = folded.last_mut() {
+            if let Some(kind) = combine(&last.kind, &node.kind, config) {
+                last.kind = kind;
+                if is_noop(&last.kind) {
+                    folded.pop();
+                }
+                continue;
+            }
         }
+
+        if !is_noop(&node.kind) {
+            folded.push(node);
+        }
+    }
+
+    *nodes = folded;
+}
+
+/// Recognise a loop body made only of changes and moves, as one of the known idioms.
+fn loop_idiom(body: &[Node], config: &Config) -> Option<NodeKind> {
+    let mut position = 0isize;
+    let mut changes = BTreeMap::<isize, i64>::new();
+    for node in body {
+        match node.kind {
+            NodeKind::Add { offset, amount } => {
+                *changes.entry(position + offset).or_default() += amount
+            }
+            NodeKind::Move(distance) => position += distance,
+            _ => return None,
+        }
+    }
+    changes.retain(|_, change| *change != 0);
+
+    let wrapping = config.overflow == Overflow::Wrapping;
+    if changes.is_empty() {
+        return (position != 0).then_some(NodeKind::Scan(position));
+    }
+
+    // Anything else only terminates if it counts the current cell down to zero. Counting up
+    // reaches zero too, but only by wrapping around.
+    let counter = changes.remove(&0);
+    let counts_down = match counter {
+        Some(-1) => true,
+        Some(1) => wrapping,
+        _ => false,
+    };
+    if position != 0 || !counts_down {
+        return None;
+    }
+
+    if changes.is_empty() {
+        return Some(NodeKind::Set {
+            offset: 0,
+            value: 0,
+        });
     }
 
-    *count = 0;
+    // Only with wrapping is the result independent of the order the cells are changed in.
+    if !wrapping {
+        return None;
+    }
+
+    // Counting up runs `max + 1 - value` times, which is the same as `-value` when wrapping.
+    let sign = if counter == Some(1) { -1 } else { 1 };
+    let targets = changes
+        .into_iter()
+        .map(|(offset, change)| (offset, change * sign))
+        .collect();
+    Some(NodeKind::Multiply(targets))
 }
 
-fn resolve_combined_change(out: &mut BF, count: &mut i32) {
-    for _ in 0..count.abs() {
-        if *count > 0 {
-            out.push(Instruction::Add);
-        } else {
-            out.push(Instruction::Subtract);
+/// Replace loops like `[-]`, `[>]` and `[->+<]` with a single node.
+pub fn loop_idioms(nodes: &mut Vec<Node>, config: &Config) {
+    for node in nodes {
+        if let NodeKind::Loop { body, .. } = &mut node.kind {
+            match loop_idiom(body, config) {
+                Some(kind) => node.kind = kind,
+                None => loop_idioms(body, config),
+            }
         }
     }
+}
+
+/// Fold moves between changes into the changes' offsets, only moving the pointer before
+/// anything that uses it.
+pub fn offsets(nodes: &mut Vec<Node>, _config: &Config) {
+    let mut result = Vec::with_capacity(nodes.len());
+    let mut pending: Option<Node> = None;
+    for mut node in nodes.drain(..) {
+        let distance = pending.as_ref().map_or(0, |pending| match pending.kind {
+            NodeKind::Move(distance) => distance,
+            _ => unreachable!("Only moves are pending"),
+        });
+
+        match &mut node.kind {
+            NodeKind::Add { offset, .. } | NodeKind::Set { offset, .. } => {
+                *offset += distance;
+                result.push(node);
+                continue;
+            }
+            NodeKind::Move(step) => {
+                match &mut pending {
+                    Some(pending) => pending.kind = NodeKind::Move(distance + *step),
+                    None => pending = Some(node),
+                }
+                continue;
+            }
+
+            NodeKind::Loop { body, .. } => offsets(body, _config),
+            _ => {}
+        }
+
+        result.extend(pending.take().filter(|pending| !is_noop(&pending.kind)));
+        result.push(node);
+    }
+
+    result.extend(pending.filter(|pending| !is_noop(&pending.kind)));
+    *nodes = result;
+}
+
+/// Sort a run of changes by cell, in the direction that moves the pointer the least, given it
+/// starts at offset 0 and has to end at `end`.
+fn order_run(run: &mut Vec<Node>, end: Option<isize>, config: &Config) {
+    let offset = |node: &Node| match node.kind {
+        NodeKind::Add { offset, .. } | NodeKind::Set { offset, .. } => offset,
+        _ => unreachable!("Runs only contain changes"),
+    };
+    let Some(lowest) = run.iter().map(offset).min() else {
+        return;
+    };
+    let highest = run.iter().map(offset).max().unwrap();
+
+    let distance = |first: isize, last: isize| {
+        first.abs() + (highest - lowest) + end.map_or(0, |end| (last - end).abs())
+    };
+
+    // Sorting is stable, so changes to the same cell stay in order.
+    if distance(lowest, highest) <= distance(highest, lowest) {
+        run.sort_by_key(offset);
+    } else {
+        run.sort_by_key(|node| std::cmp::Reverse(offset(node)));
+    }
+    fold(run, config);
+}
+
+fn reorder_in(nodes: &mut Vec<Node>, end: Option<isize>, config: &Config) {
+    let mut result = Vec::with_capacity(nodes.len());
+    let mut run = Vec::new();
+    for mut node in nodes.drain(..) {
+        match &mut node.kind {
+            NodeKind::Add { .. } | NodeKind::Set { .. } => {
+                run.push(node);
+                continue;
+            }
+            NodeKind::Loop { body, .. } => reorder_in(body, Some(0), config),
+            _ => {}
+        }
+
+        let next = match node.kind {
+            NodeKind::Move(distance) => distance,
+            _ => 0,
+        };
+        order_run(&mut run, Some(next), config);
+        result.append(&mut run);
+        result.push(node);
+    }
+
+    order_run(&mut run, end, config);
+    result.append(&mut run);
+    *nodes = result;
+}
+
+/// Reorder changes to different cells between anything that uses the pointer, so the bf for
+/// each run visits every cell once with as little pointer movement as possible.
+pub fn reorder(nodes: &mut Vec<Node>, config: &Config) {
+    reorder_in(nodes, None, config);
+}
+
+/// What is known about the tape at one point in a program.
+struct Known<'a> {
+    config: &'a Config,
+
+    /// Cells that differ from `rest`, indexed from where the pointer started.
+    cells: BTreeMap<isize, Option<u64>>,
+    rest: Option<u64>,
+    pointer: isize,
+}
+
+impl<'a> Known<'a> {
+    /// Every cell is zero at the start of a program.
+    fn start(config: &'a Config) -> Self {
+        Self {
+            config,
+            cells: BTreeMap::new(),
+            rest: Some(0),
+            pointer: 0,
+        }
+    }
+
+    /// Forget everything except that the current cell is zero.
+    fn forget(&mut self) {
+        self.cells.clear();
+        self.rest = None;
+        self.pointer = 0;
+        self.set(0, Some(0));
+    }
+
+    fn get(&self, offset: isize) -> Option<u64> {
+        self.cells
+            .get(&(self.pointer + offset))
+            .copied()
+            .unwrap_or(self.rest)
+    }
+
+    fn set(&mut self, offset: isize, value: Option<u64>) {
+        self.cells.insert(self.pointer + offset, value);
+    }
 
-    *count = 0;
+    fn wrap(&self, value: u64) -> u64 {
+        value & self.config.cell_width.max()
+    }
 }
 
-pub fn simplify_program(program: &BF) -> BF {
-    let mut simplified = BF::new();
+/// Collect the cells a loop body writes to relative to where it starts, or `None` if the pointer
+/// can't be followed.
+fn written_cells(nodes: &[Node], mut position: isize, cells: &mut Vec<isize>) -> Option<()> {
+    for node in nodes {
+        match &node.kind {
+            NodeKind::Add { offset, .. } | NodeKind::Set { offset, .. } => {
+                cells.push(position + offset)
+            }
+            NodeKind::Move(distance) => position += distance,
+            NodeKind::Input => cells.push(position),
+            NodeKind::Multiply(targets) => {
+                cells.push(position);
+                cells.extend(targets.iter().map(|(offset, _)| position + offset));
+            }
+            NodeKind::Loop {
+                body,
+                balanced: true,
+                ..
+            } => written_cells(body, position, cells)?,
+            NodeKind::Scan(_) | NodeKind::Loop { .. } => return None,
+            NodeKind::Output | NodeKind::Break => {}
+        }
+    }
+    Some(())
+}
 
-    let mut combined_move = 0i32;
-    let mut combined_change = 0i32;
-    for instruction in program.code() {
-        match instruction {
-            Instruction::Left => {
-                resolve_combined_change(&mut simplified, &mut combined_change);
-                combined_move -= 1;
+fn known_values_in(nodes: &mut Vec<Node>, known: &mut Known) {
+    let mut result = Vec::with_capacity(nodes.len());
+    for mut node in nodes.drain(..) {
+        let current = known.get(0);
+        match &mut node.kind {
+            NodeKind::Add { offset, amount } => {
+                let value = known
+                    .get(*offset)
+                    .and_then(|value| known.config.add(value, *amount));
+                known.set(*offset, value);
             }
-            Instruction::Right => {
-                resolve_combined_change(&mut simplified, &mut combined_change);
-                combined_move += 1;
+            NodeKind::Set { offset, value } => {
+                let value = known.wrap(*value as u64);
+                if known.get(*offset) == Some(value) {
+                    continue;
+                }
+                known.set(*offset, Some(value));
             }
+            NodeKind::Move(distance) => known.pointer += *distance,
+            NodeKind::Input => known.set(0, None),
+            NodeKind::Output | NodeKind::Break => {}
 
-            Instruction::Add => {
-                resolve_combined_move(&mut simplified, &mut combined_move);
-                combined_change += 1;
+            // Nothing that only runs while the current cell isn't zero can run when it's zero.
+            NodeKind::Multiply(_) | NodeKind::Scan(_) | NodeKind::Loop { .. }
+                if current == Some(0) =>
+            {
+                continue;
             }
-            Instruction::Subtract => {
-                resolve_combined_move(&mut simplified, &mut combined_move);
-                combined_change -= 1;
+
+            NodeKind::Multiply(targets) => {
+                for (offset, factor) in targets.iter() {
+                    let value = current.zip(known.get(*offset)).map(|(current, value)| {
+                        known.wrap(value.wrapping_add(current.wrapping_mul(*factor as u64)))
+                    });
+                    known.set(*offset, value);
+                }
+                known.set(0, Some(0));
             }
+            NodeKind::Scan(_) => known.forget(),
+            NodeKind::Loop { body, balanced, .. } => {
+                let mut inside = Known {
+                    config: known.config,
+                    cells: BTreeMap::new(),
+                    rest: None,
+                    pointer: 0,
+                };
+                known_values_in(body, &mut inside);
 
-            instruction => {
-                resolve_combined_move(&mut simplified, &mut combined_move);
-                resolve_combined_change(&mut simplified, &mut combined_change);
-                simplified.push(*instruction);
+                let mut written = Vec::new();
+                if *balanced && written_cells(body, 0, &mut written).is_some() {
+                    for offset in written {
+                        known.set(offset, None);
+                    }
+                    known.set(0, Some(0));
+                } else {
+                    known.forget();
+                }
             }
         }
+
+        result.push(node);
+    }
+
+    *nodes = result;
+}
+
+/// Track which cells have known values, removing loops that start on a cell known to be zero,
+/// like one at the start of the program or straight after another loop, and sets that don't
+/// change the cell.
+pub fn known_values(nodes: &mut Vec<Node>, config: &Config) {
+    known_values_in(nodes, &mut Known::start(config));
+}
+
+/// The passes used before running or generating code for a program.
+pub fn standard_passes() -> PassManager {
+    PassManager::new()
+        .with_pass("fold", fold)
+        .with_pass("loop-idioms", loop_idioms)
+        .with_pass("known-values", known_values)
+        .with_pass("offsets", offsets)
+        .with_pass("reorder", reorder)
+        .with_pass("fold", fold)
+}
+
+/// Optimise a bf program, keeping each instruction's origin. The result assumes wrapping cells,
+/// but works for any cell width.
+pub fn simplify_program(program: &BF) -> Result<BF, RuntimeError> {
+    simplify_with(program, &standard_passes())
+}
+
+/// Simplify a bf program with a custom set of passes.
+pub fn simplify_with(program: &BF, passes: &PassManager) -> Result<BF, RuntimeError> {
+    // A cell that's known to be zero with 64 bits is also zero with fewer.
+    let config = Config {
+        cell_width: CellWidth::U64,
+        ..Config::default()
+    };
+
+    let mut ir = Ir::from_bf(program)?;
+    passes.run(&mut ir, &config);
+    Ok(ir.to_bf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::Pass;
+
+    fn trapping() -> Config {
+        Config {
+            overflow: Overflow::Trapping,
+            ..Config::default()
+        }
+    }
+
+    /// Run `passes` over a program, and lower it back into bf.
+    fn simplify(passes: &[Pass], source: &str, config: &Config) -> String {
+        let mut ir = Ir::from_bf(&BF::parse(source.as_bytes()).unwrap()).unwrap();
+        for pass in passes {
+            pass(&mut ir.nodes, config);
+        }
+
+        let mut output = Vec::new();
+        ir.to_bf().write(&mut output).unwrap();
+        String::from_utf8(output).unwrap().lines().collect()
     }
 
-    resolve_combined_move(&mut simplified, &mut combined_move);
-    resolve_combined_change(&mut simplified, &mut combined_change);
-    simplified
+    /// Run `passes` over a program, returning its top level nodes.
+    fn nodes(passes: &[Pass], source: &str, config: &Config) -> Vec<NodeKind> {
+        let mut ir = Ir::from_bf(&BF::parse(source.as_bytes()).unwrap()).unwrap();
+        for pass in passes {
+            pass(&mut ir.nodes, config);
+        }
+        ir.nodes.into_iter().map(|node| node.kind).collect()
+    }
+
+    #[test]
+    fn fold_combines_runs() {
+        let config = Config::default();
+        assert_eq!(simplify(&[fold], "+++-->><.", &config), "+>.");
+        assert_eq!(simplify(&[fold], ",+-<>.", &config), ",.");
+        assert_eq!(simplify(&[fold], ",[+-+>><]", &config), ",[+>]");
+        assert_eq!(
+            nodes(&[fold], "++--", &config),
+            [] as [NodeKind; 0],
+            "changes cancelling out are removed"
+        );
+    }
+
+    #[test]
+    fn fold_keeps_changes_that_can_trap() {
+        assert_eq!(simplify(&[fold], "++--", &trapping()), "++--");
+        assert_eq!(simplify(&[fold], "-+", &trapping()), "-+");
+    }
+
+    #[test]
+    fn fold_drops_moves_that_leave_the_tape_and_come_back() {
+        assert_eq!(simplify(&[fold], "<<>>+.", &Config::default()), "+.");
+    }
+
+    #[test]
+    fn loop_idioms_replace_clears_multiplies_and_scans() {
+        let config = Config::default();
+        let passes: [Pass; 2] = [fold, loop_idioms];
+        assert_eq!(
+            nodes(&passes, "[-]", &config),
+            [NodeKind::Set {
+                offset: 0,
+                value: 0
+            }]
+        );
+        assert_eq!(
+            nodes(&passes, "[->++>-<<]", &config),
+            [NodeKind::Multiply(vec![(1, 2), (2, -1)])]
+        );
+        assert_eq!(
+            nodes(&passes, "[+<<+>>]", &config),
+            [NodeKind::Multiply(vec![(-2, -1)])],
+            "counting up negates each factor"
+        );
+        assert_eq!(nodes(&passes, "[<<]", &config), [NodeKind::Scan(-2)]);
+    }
+
+    #[test]
+    fn loop_idioms_keep_loops_they_cannot_replace() {
+        let config = Config::default();
+        let passes: [Pass; 2] = [fold, loop_idioms];
+        assert_eq!(simplify(&passes, "[->+<<]", &config), "[->+<<]");
+        assert_eq!(simplify(&passes, "[--]", &config), "[--]");
+        assert_eq!(simplify(&passes, "[-.]", &config), "[-.]");
+
+        // Only a clear stays the same without wrapping.
+        assert_eq!(simplify(&passes, "[+]", &trapping()), "[+]");
+        assert_eq!(simplify(&passes, "[->+<]", &trapping()), "[->+<]");
+        assert_eq!(
+            nodes(&passes, "[-]", &trapping()),
+            [NodeKind::Set {
+                offset: 0,
+                value: 0
+            }]
+        );
+    }
+
+    #[test]
+    fn loop_idioms_replace_nested_loops() {
+        let passes: [Pass; 2] = [fold, loop_idioms];
+        let nodes = nodes(&passes, "[>[-]<-]", &Config::default());
+        let [NodeKind::Loop { body, .. }] = nodes.as_slice() else {
+            panic!("expected a loop, got {nodes:?}");
+        };
+        assert_eq!(
+            body[1].kind,
+            NodeKind::Set {
+                offset: 0,
+                value: 0
+            }
+        );
+    }
+
+    #[test]
+    fn offsets_fold_moves_into_changes() {
+        let config = Config::default();
+        assert_eq!(
+            nodes(&[fold, offsets], ">+>--<<", &config),
+            [
+                NodeKind::Add {
+                    offset: 1,
+                    amount: 1
+                },
+                NodeKind::Add {
+                    offset: 2,
+                    amount: -2
+                },
+            ]
+        );
+        assert_eq!(
+            nodes(&[fold, offsets], ">+>.<+", &config),
+            [
+                NodeKind::Add {
+                    offset: 1,
+                    amount: 1
+                },
+                NodeKind::Move(2),
+                NodeKind::Output,
+                NodeKind::Add {
+                    offset: -1,
+                    amount: 1
+                },
+                NodeKind::Move(-1),
+            ]
+        );
+    }
+
+    #[test]
+    fn offsets_keep_moves_around_loops() {
+        let config = Config::default();
+        assert_eq!(simplify(&[fold, offsets], ">+[>+<-]<", &config), ">+[>+<-]");
+        assert_eq!(
+            simplify(&[fold, offsets], ",[>>+<<-]>>.", &config),
+            ",[>>+<<-]>>."
+        );
+    }
+
+    #[test]
+    fn offsets_only_leave_the_tape_when_a_cell_there_is_used() {
+        let config = Config::default();
+        assert_eq!(
+            nodes(&[fold, offsets], "<+>", &config),
+            [NodeKind::Add {
+                offset: -1,
+                amount: 1
+            }]
+        );
+        assert_eq!(
+            nodes(&[fold, offsets], "<>+", &config),
+            [NodeKind::Add {
+                offset: 0,
+                amount: 1
+            }]
+        );
+    }
+
+    #[test]
+    fn known_values_remove_loops_on_a_zero_cell() {
+        let config = Config::default();
+        let passes: [Pass; 3] = [fold, loop_idioms, known_values];
+
+        // Every cell starts at zero.
+        assert_eq!(simplify(&passes, "[-]>[>]<[.,]+.", &config), "+.");
+
+        // A loop always ends on a zero cell.
+        assert_eq!(simplify(&passes, ",[.,][-][>+<-]", &config), ",[.,]");
+        assert_eq!(simplify(&passes, ",[>]<[-]", &config), ",[>]<[-]");
+        assert_eq!(simplify(&passes, ",[>][.]", &config), ",[>]");
+    }
+
+    #[test]
+    fn known_values_follow_changes_to_cells() {
+        let config = Config::default();
+        let passes: [Pass; 3] = [fold, loop_idioms, known_values];
+        assert_eq!(simplify(&passes, "+-[.]", &config), "");
+        assert_eq!(simplify(&passes, "+>-<-[.]", &config), "+>-<-");
+        assert_eq!(
+            simplify(&passes, ",[->+<]>[-<+>]<[.]", &config),
+            ",[->+<]>[-<+>]<[.]"
+        );
+
+        // A loop only changes the cells it writes to.
+        assert_eq!(simplify(&passes, ",[>+<-]>>[.]", &config), ",[->+<]");
+        assert_eq!(simplify(&passes, ",[>,<-]>[.]", &config), ",[>,<-]>[.]");
+    }
+
+    #[test]
+    fn known_values_remove_clears_of_a_zero_cell() {
+        let config = Config::default();
+        let passes: [Pass; 3] = [fold, loop_idioms, known_values];
+        assert_eq!(simplify(&passes, "+>[-]+<[-]", &config), "+>+<[-]");
+        assert_eq!(simplify(&passes, ",[-]>[-]<[-]+", &config), ",[-]+");
+    }
+
+    #[test]
+    fn known_values_wrap_to_the_cell_width() {
+        let passes: [Pass; 3] = [fold, loop_idioms, known_values];
+        let program = "-".repeat(256) + "[.]";
+        assert_eq!(
+            simplify(&passes, &program, &Config::default()),
+            "-".repeat(256)
+        );
+
+        let config = Config {
+            cell_width: CellWidth::U16,
+            ..Config::default()
+        };
+        assert_eq!(
+            simplify(&passes, &program, &config),
+            "-".repeat(256) + "[.]"
+        );
+    }
+
+    #[test]
+    fn reorder_visits_each_cell_once() {
+        let config = Config::default();
+        let passes: [Pass; 3] = [fold, offsets, reorder];
+        assert_eq!(simplify(&passes, ">>+<<+>+>+", &config), "+>+>++");
+        assert_eq!(simplify(&passes, "+>>+<-", &config), "+>->+");
+    }
+
+    #[test]
+    fn reorder_chooses_the_shorter_direction() {
+        let config = Config::default();
+        let passes: [Pass; 3] = [fold, offsets, reorder];
+
+        // Starting from the far end saves coming back before the output.
+        assert_eq!(simplify(&passes, "<+>>>+<<<.", &config), ">>+<<<+.");
+        assert_eq!(simplify(&passes, "<+>>+>.", &config), "<+>>+>.");
+    }
+
+    #[test]
+    fn reorder_keeps_changes_to_the_same_cell_in_order() {
+        let config = Config::default();
+        let passes: [Pass; 4] = [fold, loop_idioms, offsets, reorder];
+        assert_eq!(
+            nodes(&passes, ",[-]>+<++", &config),
+            [
+                NodeKind::Input,
+                NodeKind::Set {
+                    offset: 0,
+                    value: 2
+                },
+                NodeKind::Add {
+                    offset: 1,
+                    amount: 1
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn reorder_does_not_move_changes_past_the_pointer_being_used() {
+        let config = Config::default();
+        let passes: [Pass; 3] = [fold, offsets, reorder];
+        assert_eq!(simplify(&passes, ">+<.>>+<<+", &config), ">+<.+>>+");
+        assert_eq!(simplify(&passes, ",[>>+<+<-]", &config), ",[->+>+<<]");
+    }
 }
@@ -0,0 +1,172 @@
+use crate::bf::BF;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+
+const HEADER: &str = "bfmacro-source-map 1";
+
+/// The place in a .bfm file that generated some bf instructions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Origin {
+    pub file: usize,
+    pub span: (usize, usize),
+    pub line: usize,
+    pub column: usize,
+
+    /// The origin of the macro invocation or block argument this was expanded from.
+    pub caller: Option<usize>,
+
+    /// Name of the invoked macro, if this origin is a macro invocation.
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    pub files: Vec<PathBuf>,
+    pub origins: Vec<Origin>,
+}
+
+fn invalid_data(message: impl Into<String>) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.into())
+}
+
+impl SourceMap {
+    pub fn origin(&self, origin: usize) -> &Origin {
+        &self.origins[origin]
+    }
+
+    /// The chain of origins from `origin` out to the top level `using` block.
+    pub fn stack(&self, origin: usize) -> Vec<&Origin> {
+        let mut stack = Vec::new();
+        let mut current = Some(origin);
+        while let Some(origin) = current {
+            let origin = self.origin(origin);
+            stack.push(origin);
+            current = origin.caller;
+        }
+        stack
+    }
+
+    pub fn describe(&self, origin: &Origin) -> String {
+        format!(
+            "{}:{}:{}",
+            self.files[origin.file].display(),
+            origin.line,
+            origin.column
+        )
+    }
+
+    pub fn write(&self, program: &BF, mut output: impl Write) -> std::io::Result<()> {
+        writeln!(output, "{HEADER}")?;
+        for (index, file) in self.files.iter().enumerate() {
+            writeln!(output, "file {index} {}", file.display())?;
+        }
+
+        for (index, origin) in self.origins.iter().enumerate() {
+            let caller = origin
+                .caller
+                .map_or_else(|| "-".to_owned(), |caller| caller.to_string());
+            let name = origin.name.as_deref().unwrap_or("-");
+            writeln!(
+                output,
+                "origin {index} {} {} {} {} {} {caller} {name}",
+                origin.file, origin.span.0, origin.span.1, origin.line, origin.column,
+            )?;
+        }
+
+        // Runs of instructions with the same origin are written as a single range.
+        let origins = program.origins();
+        let mut start = 0;
+        while start < origins.len() {
+            let mut end = start + 1;
+            while end < origins.len() && origins[end] == origins[start] {
+                end += 1;
+            }
+
+            if let Some(origin) = origins[start] {
+                writeln!(output, "map {start} {end} {origin}")?;
+            }
+            start = end;
+        }
+
+        Ok(())
+    }
+
+    /// Read a source map, and attach its instruction origins to `program`.
+    pub fn read(program: &mut BF, input: impl Read) -> std::io::Result<Self> {
+        let mut source_map = SourceMap::default();
+        let mut origins = vec![None; program.len()];
+
+        let mut lines = BufReader::new(input).lines();
+        if lines.next().transpose()?.as_deref() != Some(HEADER) {
+            return Err(invalid_data("Not a bfmacro source map"));
+        }
+
+        for line in lines {
+            let line = line?;
+            let mut fields = line.split(' ');
+            let kind = fields.next().unwrap_or_default();
+            let mut number = || -> std::io::Result<usize> {
+                fields
+                    .next()
+                    .and_then(|field| field.parse().ok())
+                    .ok_or_else(|| invalid_data(format!("Invalid source map line '{line}'")))
+            };
+
+            let out_of_range =
+                || invalid_data(format!("Index out of range in source map line '{line}'"));
+            match kind {
+                "file" => {
+                    if number()? != source_map.files.len() {
+                        return Err(out_of_range());
+                    }
+
+                    let path = line.splitn(3, ' ').nth(2).unwrap_or_default();
+                    source_map.files.push(PathBuf::from(path));
+                }
+
+                // Files come before the origins in them, and callers before the origins they call.
+                "origin" => {
+                    let index = number()?;
+                    let file = number()?;
+                    let span = (number()?, number()?);
+                    let (line_number, column) = (number()?, number()?);
+                    let caller = match line.split(' ').nth(7) {
+                        Some("-") => None,
+                        _ => Some(number()?),
+                    };
+                    let name = line.split(' ').nth(8).filter(|name| *name != "-");
+                    if index != source_map.origins.len()
+                        || file >= source_map.files.len()
+                        || caller.is_some_and(|caller| caller >= index)
+                    {
+                        return Err(out_of_range());
+                    }
+
+                    source_map.origins.push(Origin {
+                        file,
+                        span,
+                        line: line_number,
+                        column,
+                        caller,
+                        name: name.map(str::to_owned),
+                    });
+                }
+
+                "map" => {
+                    let (start, end, origin) = (number()?, number()?, number()?);
+                    if start > end || end > origins.len() || origin >= source_map.origins.len() {
+                        return Err(out_of_range());
+                    }
+
+                    origins[start..end].fill(Some(origin));
+                }
+
+                "" => {}
+                _ => return Err(invalid_data(format!("Invalid source map line '{line}'"))),
+            }
+        }
+
+        program.set_origins(origins);
+        Ok(source_map)
+    }
+}
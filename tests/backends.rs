@@ -0,0 +1,68 @@
+#![cfg(all(target_arch = "x86_64", target_os = "linux"))]
+
+use bfmacro::bf::BF;
+use bfmacro::interpreter::{run_program, Config, RunStats, RuntimeError};
+use bfmacro::jit::Jit;
+use common::{CAT, CELL_WIDTHS, EOFS, EOF_PROBE, HELLO_WORLD, MULTIPLY, REVERSE};
+
+mod common;
+
+const PROGRAMS: [(&str, &str, &[u8]); 6] = [
+    ("hello world", HELLO_WORLD, b""),
+    ("cat", CAT, b"cat\ninput"),
+    ("reverse", REVERSE, b"reverse me"),
+    ("multiply", MULTIPLY, &[6, 7]),
+    ("multiply overflow", MULTIPLY, &[200, 200]),
+    ("eof probe", EOF_PROBE, b"A"),
+];
+
+type Outcome = (Result<RunStats, RuntimeError>, Vec<u8>);
+
+fn interpret(program: &BF, config: Config, input: &[u8]) -> Outcome {
+    let mut output = Vec::new();
+    let result = run_program(program, config, input, &mut output);
+    (result, output)
+}
+
+fn jit(program: &BF, config: Config, input: &[u8]) -> Outcome {
+    let mut output = Vec::new();
+    let result =
+        Jit::compile(program, config).and_then(|jit| jit.run(input, &mut output, |_, _| Ok(())));
+    (result, output)
+}
+
+#[test]
+fn jit_matches_interpreter() {
+    for (name, source, input) in PROGRAMS {
+        let program = BF::parse(source.as_bytes()).unwrap();
+        for cell_width in CELL_WIDTHS {
+            for eof in EOFS {
+                // Some programs never see the end of input with `-1`, so stop them early.
+                let config = Config {
+                    cell_width,
+                    eof,
+                    step_limit: Some(100_000),
+                    memory_size: Some(1000),
+                    ..Config::default()
+                };
+
+                let expected = interpret(&program, config.clone(), input);
+                let actual = jit(&program, config, input);
+                assert_eq!(
+                    actual,
+                    expected,
+                    "'{name}' with {}-bit cells and {eof:?} at the end of input",
+                    cell_width.bits(),
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn hello_world_runs() {
+    let program = BF::parse(HELLO_WORLD.as_bytes()).unwrap();
+    let (interpreted, output) = interpret(&program, Config::default(), b"");
+    assert!(interpreted.is_ok());
+    assert_eq!(output, b"Hello World!\n");
+}
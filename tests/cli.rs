@@ -0,0 +1,84 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+/// Write `contents` to a file only used by `test`.
+fn scratch_file(test: &str, name: &str, contents: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("bfmacro-cli-{test}-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+/// Run the bfmacro binary with `stdin` as its input.
+fn bfmacro(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_bfmacro"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // Programs that don't read stdin may exit before it's written.
+    let _ = child.stdin.take().unwrap().write_all(stdin.as_bytes());
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> &str {
+    assert!(
+        output.status.success(),
+        "failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    std::str::from_utf8(&output.stdout).unwrap()
+}
+
+const CAT: &str = ",[.,]";
+
+#[test]
+fn run_reads_stdin() {
+    let program = scratch_file("stdin", "cat.bf", CAT);
+    let output = bfmacro(&["run", program.to_str().unwrap()], "from stdin");
+    assert_eq!(stdout(&output), "from stdin");
+}
+
+#[test]
+fn run_reads_the_input_file() {
+    let program = scratch_file("input", "cat.bf", CAT);
+    let input = scratch_file("input", "input.txt", "from a file");
+    let input = format!("--input={}", input.display());
+    let output = bfmacro(&["run", &input, program.to_str().unwrap()], "from stdin");
+    assert_eq!(stdout(&output), "from a file");
+}
+
+#[test]
+fn run_reads_embedded_input_instead_of_stdin() {
+    let program = scratch_file("embedded", "cat.bf", &format!("{CAT}!embedded"));
+    let output = bfmacro(&["run", program.to_str().unwrap()], "from stdin");
+    assert_eq!(stdout(&output), "embedded");
+}
+
+#[test]
+fn input_option_overrides_embedded_input() {
+    let program = scratch_file("override", "cat.bf", &format!("{CAT}!embedded"));
+    let input = scratch_file("override", "input.txt", "from a file");
+    let input = format!("--input={}", input.display());
+    let output = bfmacro(&["run", &input, program.to_str().unwrap()], "from stdin");
+    assert_eq!(stdout(&output), "from a file");
+
+    let output = bfmacro(
+        &["run", "--input=-", program.to_str().unwrap()],
+        "from stdin",
+    );
+    assert_eq!(stdout(&output), "from stdin");
+}
+
+#[test]
+fn run_rejects_more_than_one_file() {
+    let program = scratch_file("files", "cat.bf", CAT);
+    let program = program.to_str().unwrap();
+    let output = bfmacro(&["run", program, program], "");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("can only run a single file"));
+}
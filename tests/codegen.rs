@@ -0,0 +1,360 @@
+use bfmacro::bf::BF;
+use bfmacro::codegen::{generate, Target};
+use bfmacro::interpreter::{run_program, Config};
+use common::{CAT, CELL_WIDTHS, EOFS, EOF_PROBE, HELLO_WORLD, MULTIPLY, REVERSE};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+mod common;
+
+/// Small enough to read the generated code of, but using every kind of op.
+const GOLDEN: &str = "+++[->++<]>[>+<-],.[-]>[>]<.";
+
+struct Case {
+    name: String,
+    program: BF,
+    config: Config,
+    input: &'static [u8],
+}
+
+/// Programs with the configs to run them under, avoiding the ones that never see the end of
+/// input with some end of input behaviours.
+fn cases() -> Vec<Case> {
+    let mut cases = Vec::new();
+    let mut add = |name: &str, source: &str, input: &'static [u8], config: Config| {
+        cases.push(Case {
+            name: format!("{name}-{}-{:?}", config.cell_width.bits(), config.eof).replace(' ', "-"),
+            program: BF::parse(source.as_bytes()).unwrap(),
+            config,
+            input,
+        });
+    };
+
+    for cell_width in CELL_WIDTHS {
+        let config = Config {
+            cell_width,
+            ..Config::default()
+        };
+        add("hello world", HELLO_WORLD, b"", config.clone());
+        add("cat", CAT, b"cat\ninput", config.clone());
+        add("reverse", REVERSE, b"reverse me", config.clone());
+        add("multiply", MULTIPLY, &[200, 200], config);
+
+        for eof in EOFS {
+            let config = Config {
+                cell_width,
+                eof,
+                ..Config::default()
+            };
+            add("eof probe", EOF_PROBE, b"A", config);
+        }
+    }
+
+    cases
+}
+
+fn expected_output(case: &Case) -> Vec<u8> {
+    let mut output = Vec::new();
+    run_program(&case.program, case.config.clone(), case.input, &mut output).unwrap();
+    output
+}
+
+fn generate_code(target: Target, program: &BF, config: &Config) -> Vec<u8> {
+    let mut code = Vec::new();
+    generate(target, program, config, &mut code).unwrap();
+    code
+}
+
+/// Whether `tool` can be run, so tests needing it can be skipped when it's missing.
+fn tool_available(tool: &str) -> bool {
+    let available = Command::new(tool)
+        .arg("--version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok();
+    if !available {
+        eprintln!("skipping, '{tool}' is not installed");
+    }
+    available
+}
+
+/// A fresh directory for the files of one test.
+fn scratch_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("bfmacro-{test}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn run_tool(command: &mut Command) {
+    let output = command.output().unwrap();
+    assert!(
+        output.status.success(),
+        "{command:?} failed:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+/// Run a program with `input` on stdin, returning what it wrote to stdout.
+fn run_with_input(command: &mut Command, input: &[u8]) -> Vec<u8> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+
+    let output = child.wait_with_output().unwrap();
+    assert!(
+        output.status.success(),
+        "{command:?} failed:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output.stdout
+}
+
+/// Compare generated code with `tests/golden/<name>`, or rewrite it when `BFMACRO_BLESS` is set.
+fn assert_golden(name: &str, code: &[u8]) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(name);
+    if std::env::var_os("BFMACRO_BLESS").is_some() {
+        std::fs::write(&path, code).unwrap();
+        return;
+    }
+
+    let golden = std::fs::read(&path).unwrap();
+    assert!(
+        golden == code,
+        "generated code differs from {}, set BFMACRO_BLESS=1 to update it",
+        path.display()
+    );
+}
+
+#[test]
+fn c_matches_golden() {
+    let program = BF::parse(GOLDEN.as_bytes()).unwrap();
+    let code = generate_code(Target::C, &program, &Config::default());
+    assert_golden("golden.c", &code);
+}
+
+#[test]
+fn c_matches_interpreter() {
+    if !tool_available("cc") {
+        return;
+    }
+
+    let dir = scratch_dir("c");
+    for case in cases() {
+        let source = dir.join(format!("{}.c", case.name));
+        let binary = dir.join(&case.name);
+        std::fs::write(
+            &source,
+            generate_code(Target::C, &case.program, &case.config),
+        )
+        .unwrap();
+        run_tool(
+            Command::new("cc")
+                .arg("-O1")
+                .arg(&source)
+                .arg("-o")
+                .arg(&binary),
+        );
+
+        let output = run_with_input(&mut Command::new(&binary), case.input);
+        assert_eq!(output, expected_output(&case), "'{}'", case.name);
+    }
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn rust_matches_golden() {
+    let program = BF::parse(GOLDEN.as_bytes()).unwrap();
+    let code = generate_code(Target::Rust, &program, &Config::default());
+    assert_golden("golden.rs", &code);
+}
+
+#[test]
+fn rust_matches_interpreter() {
+    if !tool_available("rustc") {
+        return;
+    }
+
+    let dir = scratch_dir("rust");
+    for case in cases() {
+        let source = dir.join(format!("{}.rs", case.name));
+        let binary = dir.join(&case.name);
+        std::fs::write(
+            &source,
+            generate_code(Target::Rust, &case.program, &case.config),
+        )
+        .unwrap();
+        run_tool(Command::new("rustc").arg(&source).arg("-o").arg(&binary));
+
+        let output = run_with_input(&mut Command::new(&binary), case.input);
+        assert_eq!(output, expected_output(&case), "'{}'", case.name);
+    }
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn rust_library_can_be_included() {
+    if !tool_available("rustc") {
+        return;
+    }
+
+    let dir = scratch_dir("rust-lib");
+    let program = BF::parse(HELLO_WORLD.as_bytes()).unwrap();
+    let config = Config::default();
+    let library = dir.join("hello_world.rs");
+    let main = dir.join("main.rs");
+    let binary = dir.join("main");
+    std::fs::write(
+        &library,
+        generate_code(Target::RustLibrary, &program, &config),
+    )
+    .unwrap();
+    std::fs::write(
+        &main,
+        "include!(\"hello_world.rs\");\n\
+        fn main() {\n\
+            run(std::io::empty(), std::io::stdout()).unwrap();\n\
+        }\n",
+    )
+    .unwrap();
+    run_tool(Command::new("rustc").arg(&main).arg("-o").arg(&binary));
+
+    let output = run_with_input(&mut Command::new(&binary), b"");
+    assert_eq!(output, b"Hello World!\n");
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+/// Runs a WebAssembly module under node, feeding it stdin through the imports it expects.
+const WASM_RUNNER: &str = "
+const fs = require('fs');
+const bytes = fs.readFileSync(process.argv[1]);
+if (!WebAssembly.validate(bytes)) {
+    console.error('invalid module');
+    process.exit(1);
+}
+
+const input = fs.readFileSync(0);
+let position = 0;
+const output = [];
+const env = {
+    getchar: () => position < input.length ? input[position++] : -1,
+    putchar: c => output.push(c & 255),
+};
+const instance = new WebAssembly.Instance(new WebAssembly.Module(bytes), { env });
+instance.exports.run();
+process.stdout.write(Buffer.from(output));
+";
+
+#[test]
+fn wat_matches_golden() {
+    let program = BF::parse(GOLDEN.as_bytes()).unwrap();
+    let code = generate_code(Target::Wat, &program, &Config::default());
+    assert_golden("golden.wat", &code);
+}
+
+#[test]
+fn wat_is_well_formed() {
+    for case in cases() {
+        let code = generate_code(Target::Wat, &case.program, &case.config);
+        let code = String::from_utf8(code)
+            .unwrap()
+            .lines()
+            .map(|line| line.split(";;").next().unwrap())
+            .collect::<String>();
+        assert!(code.trim_start().starts_with("(module"), "'{}'", case.name);
+
+        let mut depth = 0;
+        for char in code.chars() {
+            match char {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                _ => {}
+            }
+            assert!(depth >= 0, "'{}' closes too many brackets", case.name);
+        }
+        assert_eq!(depth, 0, "'{}' leaves brackets open", case.name);
+    }
+}
+
+#[test]
+fn wat_is_valid() {
+    if !tool_available("wasm-tools") {
+        return;
+    }
+
+    let dir = scratch_dir("wat");
+    for case in cases() {
+        let source = dir.join(format!("{}.wat", case.name));
+        std::fs::write(
+            &source,
+            generate_code(Target::Wat, &case.program, &case.config),
+        )
+        .unwrap();
+        run_tool(Command::new("wasm-tools").arg("validate").arg(&source));
+    }
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn wasm_matches_interpreter() {
+    if !tool_available("node") {
+        return;
+    }
+
+    let dir = scratch_dir("wasm");
+    for case in cases() {
+        let module = dir.join(format!("{}.wasm", case.name));
+        std::fs::write(
+            &module,
+            generate_code(Target::Wasm, &case.program, &case.config),
+        )
+        .unwrap();
+
+        let mut command = Command::new("node");
+        command.arg("-e").arg(WASM_RUNNER).arg(&module);
+        let output = run_with_input(&mut command, case.input);
+        assert_eq!(output, expected_output(&case), "'{}'", case.name);
+    }
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn asm_matches_golden() {
+    let program = BF::parse(GOLDEN.as_bytes()).unwrap();
+    let code = generate_code(Target::Asm, &program, &Config::default());
+    assert_golden("golden.s", &code);
+}
+
+#[test]
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+fn asm_matches_interpreter() {
+    if !tool_available("as") || !tool_available("ld") {
+        return;
+    }
+
+    let dir = scratch_dir("asm");
+    for case in cases() {
+        let source = dir.join(format!("{}.s", case.name));
+        let object = dir.join(format!("{}.o", case.name));
+        let binary = dir.join(&case.name);
+        std::fs::write(
+            &source,
+            generate_code(Target::Asm, &case.program, &case.config),
+        )
+        .unwrap();
+        run_tool(Command::new("as").arg(&source).arg("-o").arg(&object));
+        run_tool(Command::new("ld").arg(&object).arg("-o").arg(&binary));
+
+        let output = run_with_input(&mut Command::new(&binary), case.input);
+        assert_eq!(output, expected_output(&case), "'{}'", case.name);
+    }
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,21 @@
+//! Programs and configs shared by the tests comparing backends with the interpreter.
+
+use bfmacro::bf::Eof;
+use bfmacro::interpreter::CellWidth;
+
+pub const HELLO_WORLD: &str = "
+    ++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]
+    >>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.
+";
+pub const CAT: &str = ",[.[-],]";
+pub const REVERSE: &str = ">,[>,]<[.<]";
+pub const MULTIPLY: &str = ",>,<[>[>+>+<<-]>>[<<+>>-]<<<-]>>.";
+pub const EOF_PROBE: &str = ",.,.+++++,.-.";
+
+pub const CELL_WIDTHS: [CellWidth; 4] = [
+    CellWidth::U8,
+    CellWidth::U16,
+    CellWidth::U32,
+    CellWidth::U64,
+];
+pub const EOFS: [Eof; 3] = [Eof::Unchanged, Eof::Zero, Eof::MinusOne];
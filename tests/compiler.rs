@@ -0,0 +1,452 @@
+use bfmacro::bf::{Eof, BF};
+use bfmacro::compiler::{Compiler, Diagnostic, ErrorCode, IncludeResolver, Options};
+use bfmacro::interpreter::{run_program, CellWidth, Config};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+fn code(program: &BF) -> String {
+    let mut output = Vec::new();
+    program.write(&mut output).unwrap();
+    String::from_utf8(output).unwrap().lines().collect()
+}
+
+fn compile(source: &str) -> Result<String, Vec<Diagnostic>> {
+    let mut compiler = Compiler::new();
+    let program = compiler.compile_source(Path::new("test.bfm"), source)?;
+    Ok(code(&program))
+}
+
+fn compile_with(options: Options, source: &str) -> Result<BF, Vec<Diagnostic>> {
+    let mut compiler = Compiler::new().with_options(options);
+    compiler.compile_source(Path::new("test.bfm"), source)
+}
+
+fn run(program: &BF, config: Config, input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    run_program(program, config, input, &mut output).unwrap();
+    output
+}
+
+#[test]
+fn slots_are_reached_by_moving_towards_them() {
+    let source = "
+        frame Main { a b c }
+        using Main {
+            a + @
+            c ++ @
+            b +++ @
+        }
+    ";
+
+    assert_eq!(compile(source).unwrap(), "+.>>++.<+++.");
+}
+
+#[test]
+fn unbounded_recursion_hits_the_expansion_limit() {
+    let source = "
+        frame Main { a }
+        macro g(x) { x + g(x) }
+        macro f(x) { g(x) }
+        using Main { f(a) }
+    ";
+
+    let diagnostics = compile(source).unwrap_err();
+    assert_eq!(diagnostics.len(), 1);
+
+    let diagnostic = &diagnostics[0];
+    assert_eq!(diagnostic.code, Some(ErrorCode::RecursionLimit));
+    let primary = diagnostic.primary.as_ref().unwrap();
+    assert_eq!(&source[primary.span.0..primary.span.1], "g");
+    assert_eq!(
+        diagnostic.notes,
+        ["Expanded through 'f' -> 'g' (127 times)"]
+    );
+}
+
+#[test]
+fn branching_recursion_is_only_reported_once() {
+    let source = "
+        frame Main { a }
+        macro g(x) { g(x) g(x) }
+        using Main { g(a) }
+    ";
+
+    let diagnostics = compile(source).unwrap_err();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].code, Some(ErrorCode::RecursionLimit));
+}
+
+#[test]
+fn recursion_stopped_by_an_if_expands() {
+    let source = "
+        frame Main { a }
+        macro count(x, n: int) {
+            if n > 0 {
+                x +
+                count(x, (n - 1))
+            }
+        }
+        using Main { count(a, 100) }
+    ";
+
+    assert_eq!(compile(source).unwrap(), "+".repeat(100));
+}
+
+#[test]
+fn recursion_fanning_out_hits_the_expansion_limit() {
+    let source = "
+        frame Main { a }
+        macro g(x, n: int) {
+            if n > 0 {
+                g(x, (n - 1))
+                g(x, (n - 1))
+            }
+        }
+        using Main { g(a, 60) }
+    ";
+
+    let diagnostics = compile(source).unwrap_err();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].code, Some(ErrorCode::RecursionLimit));
+    assert_eq!(
+        diagnostics[0].message,
+        "Macros and repeats are expanded more than 262144 times"
+    );
+}
+
+#[test]
+fn nested_repeats_hit_the_expansion_limit() {
+    let source = "
+        frame Main { a }
+        using Main {
+            repeat 65535 {
+                repeat 65535 { a + }
+            }
+        }
+    ";
+
+    let diagnostics = compile(source).unwrap_err();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].code, Some(ErrorCode::RecursionLimit));
+    let primary = diagnostics[0].primary.as_ref().unwrap();
+    assert!(source[primary.span.0..].starts_with("repeat 65535"));
+}
+
+#[test]
+fn readme_examples_compile_as_documented() {
+    let main = "frame Main { a b c }\n";
+    let examples = [
+        ("using Main { a + c ++ b +++ }", "+>+++>++"),
+        (
+            "macro move(src, dest) { src[ dest+ src- ] }
+            using Main { b , move(b, c) }",
+            ">,[->+<]",
+        ),
+        (
+            "macro while(a, do: block) { a[ do a ] }
+            using Main { b , while(b, { a+ b- }) }",
+            ">,[-<+>]",
+        ),
+        (
+            "macro add(x, n: int) { x +(n) }
+            using Main { add(b, 3) add(c, ('A' - 60)) }",
+            ">+++>+++++",
+        ),
+    ];
+
+    for (example, expected) in examples {
+        assert_eq!(compile(&format!("{main}{example}")).unwrap(), expected);
+    }
+}
+
+#[test]
+fn read_leaves_zero_at_the_end_of_input() {
+    // The second read is at the end of input, with the cell still holding the first byte.
+    let source = "
+        frame Main { c temp }
+        using Main {
+            read(c, temp) c @
+            read(c, temp) c @ temp @
+        }
+    ";
+
+    for eof in [Eof::Zero, Eof::Unchanged, Eof::MinusOne] {
+        for cell_width in [CellWidth::U8, CellWidth::U16] {
+            let options = Options {
+                eof,
+                ..Options::default()
+            };
+            let program = compile_with(options, source).unwrap();
+            let config = Config {
+                cell_width,
+                eof,
+                ..Config::default()
+            };
+            assert_eq!(
+                run(&program, config, b"A"),
+                b"A\0\0",
+                "{eof:?} with {}-bit cells",
+                cell_width.bits()
+            );
+        }
+    }
+}
+
+#[test]
+fn read_needs_a_temporary_cell_when_end_of_input_is_minus_one() {
+    let options = Options {
+        eof: Eof::MinusOne,
+        ..Options::default()
+    };
+    let diagnostics = compile_with(options, "frame Main { c } using Main { read(c) }")
+        .err()
+        .unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].code, Some(ErrorCode::ArgumentCount));
+}
+
+fn print_code(text: &str) -> String {
+    let options = Options {
+        simplify: false,
+        ..Options::default()
+    };
+    let source = format!("frame Main {{ a temp }} using Main {{ print(temp, \"{text}\") }}");
+    code(&compile_with(options, &source).ok().unwrap())
+}
+
+#[test]
+fn print_changes_the_cell_from_each_character_to_the_next() {
+    // Going up from 'a' to 'b' and back down is shorter than starting again from zero.
+    let code = print_code("aba");
+    let expected = format!(">[-]{}.+.-.[-]", "+".repeat(97));
+    assert_eq!(code, expected);
+
+    // '\n' is closer to zero than to 'b', so the cell is cleared first.
+    let code = print_code("ab\\na");
+    let expected = format!(
+        ">[-]{}.+.[-]{}.{}.[-]",
+        "+".repeat(97),
+        "+".repeat(10),
+        "+".repeat(87)
+    );
+    assert_eq!(code, expected);
+
+    assert_eq!(print_code(""), "");
+}
+
+#[test]
+fn print_writes_the_text_and_clears_its_cell() {
+    let source = "
+        frame Main { a temp }
+        using Main {
+            print(temp, \"Hello, World!\\n\")
+            temp @
+        }
+    ";
+
+    for simplify in [false, true] {
+        let options = Options {
+            simplify,
+            ..Options::default()
+        };
+        let program = compile_with(options, source).ok().unwrap();
+        assert_eq!(run(&program, Config::default(), b""), b"Hello, World!\n\0");
+    }
+}
+
+#[test]
+fn strings_support_escapes() {
+    let source = r#"
+        frame Main { temp }
+        using Main { print(temp, "\n\r\t\0\\\"\'") }
+    "#;
+
+    let program = compile_with(Options::default(), source).ok().unwrap();
+    assert_eq!(run(&program, Config::default(), b""), b"\n\r\t\0\\\"'");
+}
+
+#[test]
+fn unknown_escapes_are_rejected() {
+    for escape in ["\\x41", "\\q", "\\"] {
+        let source = format!("frame Main {{ temp }} using Main {{ print(temp, \"a{escape}\") }}");
+        let diagnostics = compile_with(Options::default(), &source).err().unwrap();
+
+        let string_start = source.find('"').unwrap();
+        let primary = diagnostics[0].primary.as_ref().unwrap();
+        assert_eq!(diagnostics[0].code, Some(ErrorCode::Syntax), "{escape}");
+        assert_eq!(primary.span, (string_start, string_start + 1), "{escape}");
+    }
+}
+
+#[test]
+fn print_needs_a_cell_and_a_string() {
+    let code_of = |arguments: &str| {
+        let source = format!("frame Main {{ temp }} using Main {{ print({arguments}) }}");
+        let diagnostics = compile_with(Options::default(), &source).err().unwrap();
+        assert_eq!(diagnostics.len(), 1, "{arguments}");
+        diagnostics[0].code
+    };
+
+    assert_eq!(code_of("temp"), Some(ErrorCode::ArgumentCount));
+    assert_eq!(
+        code_of("temp, \"a\", \"b\""),
+        Some(ErrorCode::ArgumentCount)
+    );
+    assert_eq!(code_of("\"a\", temp"), Some(ErrorCode::ArgumentType));
+    assert_eq!(code_of("temp, 3"), Some(ErrorCode::ArgumentType));
+}
+
+/// Serves includes from memory instead of the file system.
+#[derive(Default)]
+struct MemoryResolver {
+    files: HashMap<PathBuf, String>,
+}
+
+impl MemoryResolver {
+    fn with_file(mut self, path: &str, source: &str) -> Self {
+        self.files.insert(PathBuf::from(path), source.to_owned());
+        self
+    }
+}
+
+impl IncludeResolver for MemoryResolver {
+    fn load(&self, file_path: &Path) -> io::Result<String> {
+        self.files
+            .get(file_path)
+            .cloned()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+    }
+}
+
+#[test]
+fn includes_are_loaded_through_the_resolver() {
+    let resolver = MemoryResolver::default().with_file(
+        "src/lib.bfm",
+        "
+        frame Pair { a b }
+        macro twice(x) { x ++ }
+        ",
+    );
+    let source = "
+        include \"lib.bfm\"
+        using Pair { b twice(b) @ }
+    ";
+
+    let mut compiler = Compiler::new().with_resolver(resolver);
+    let program = compiler
+        .compile_source(Path::new("src/main.bfm"), source)
+        .ok()
+        .unwrap();
+    assert_eq!(code(&program), ">++.");
+
+    let included = compiler.files().find(Path::new("src/lib.bfm"));
+    assert!(included.is_some());
+    assert_eq!(compiler.frame_layout("Pair").unwrap().size, 2);
+}
+
+#[test]
+fn missing_includes_are_reported_where_they_are_included() {
+    let source = "include \"missing.bfm\"\n";
+
+    let mut compiler = Compiler::new().with_resolver(MemoryResolver::default());
+    let diagnostics = compiler
+        .compile_source(Path::new("main.bfm"), source)
+        .err()
+        .unwrap();
+    assert_eq!(diagnostics.len(), 1);
+
+    let diagnostic = &diagnostics[0];
+    assert_eq!(diagnostic.code, Some(ErrorCode::Io));
+    assert!(diagnostic.message.contains("missing.bfm"));
+    let primary = diagnostic.primary.as_ref().unwrap();
+    assert_eq!(
+        primary.file,
+        compiler.files().find(Path::new("main.bfm")).unwrap()
+    );
+    assert_eq!(
+        &source[primary.span.0..primary.span.1],
+        "include \"missing.bfm\""
+    );
+    assert_eq!(primary.message, "included here");
+}
+
+#[test]
+fn errors_in_included_files_point_into_them() {
+    let lib = "macro broken(x) { x + unknown(x) }\n";
+    let resolver = MemoryResolver::default().with_file("lib.bfm", lib);
+    let source = "
+        include \"lib.bfm\"
+        frame Main { a }
+        using Main { broken(a) }
+    ";
+
+    let mut compiler = Compiler::new().with_resolver(resolver);
+    let diagnostics = compiler
+        .compile_source(Path::new("main.bfm"), source)
+        .err()
+        .unwrap();
+    assert_eq!(diagnostics.len(), 1);
+
+    let diagnostic = &diagnostics[0];
+    assert_eq!(diagnostic.code, Some(ErrorCode::UnknownMacro));
+    let primary = diagnostic.primary.as_ref().unwrap();
+    assert_eq!(
+        primary.file,
+        compiler.files().find(Path::new("lib.bfm")).unwrap()
+    );
+    assert_eq!(&lib[primary.span.0..primary.span.1], "unknown");
+}
+
+#[test]
+fn options_turn_off_simplifying() {
+    let source = "
+        frame Main { a b c }
+        using Main {
+            a +
+            c ++
+            b +++
+        }
+    ";
+
+    let options = Options {
+        simplify: false,
+        ..Options::default()
+    };
+    let program = compile_with(options, source).ok().unwrap();
+    assert_eq!(code(&program), "+>>++<+++");
+}
+
+#[test]
+fn auto_frames_move_the_pointer_less_with_the_same_output() {
+    let source = |auto: &str| {
+        format!(
+            "
+            frame Main {auto} {{ a b c d }}
+            macro copy_to(from, to) {{ from [ - to + from ] }}
+            using Main {{
+                d , copy_to(d, a)
+                a ++ @ d @
+                a - @ d @
+            }}
+            "
+        )
+    };
+    let options = || Options {
+        simplify: false,
+        ..Options::default()
+    };
+    let moves = |program: &BF| code(program).matches(['<', '>']).count();
+
+    let declared = compile_with(options(), &source("")).ok().unwrap();
+    let chosen = compile_with(options(), &source("auto")).ok().unwrap();
+    assert_eq!(moves(&declared), 21);
+    assert_eq!(moves(&chosen), 6);
+
+    let input = b"x";
+    assert_eq!(run(&chosen, Config::default(), input), b"z\0y\0");
+    assert_eq!(
+        run(&chosen, Config::default(), input),
+        run(&declared, Config::default(), input)
+    );
+}
@@ -0,0 +1,202 @@
+use bfmacro::bf::BF;
+use bfmacro::compiler::{Compiler, Options};
+use bfmacro::debugger::Debugger;
+use bfmacro::interpreter::Config;
+use std::path::Path;
+
+const SOURCE: &str = "\
+frame Main { a b }
+macro add_two(x) {
+    x +
+    x +
+}
+using Main {
+    a +
+    add_two(b)
+    a +
+}
+";
+
+/// Run the debugger over `SOURCE` with the commands in `script`, returning everything it wrote.
+fn session(script: &str) -> String {
+    let mut compiler = Compiler::new().with_options(Options {
+        simplify: false,
+        ..Options::default()
+    });
+    let program = compiler
+        .compile_source(Path::new("main.bfm"), SOURCE)
+        .ok()
+        .unwrap();
+    let frames = compiler.frame_layouts().cloned().collect::<Vec<_>>();
+    let source_map = Some(compiler.source_map());
+
+    let mut debugger = Debugger::new(&program, Config::default(), source_map, frames);
+    let mut output = Vec::new();
+    debugger.run(script.as_bytes(), &mut output).unwrap();
+    String::from_utf8(output).unwrap()
+}
+
+/// The output of each command in `script`, without the prompts.
+fn responses(script: &str) -> Vec<String> {
+    session(script)
+        .split("(bfdb) ")
+        .skip(1)
+        .map(str::to_owned)
+        .collect()
+}
+
+#[test]
+fn starts_at_the_first_instruction() {
+    assert_eq!(
+        session(""),
+        "pc 0: Add, pointer 0 = 0\n  #0 at main.bfm:7:7\n(bfdb) "
+    );
+}
+
+#[test]
+fn break_on_a_line_stops_at_its_first_instruction() {
+    let responses = responses("break 9\ncontinue\ncontinue\n");
+    assert_eq!(responses[0], "Breakpoint 0 at main.bfm:9\n");
+    assert_eq!(
+        responses[1],
+        "Breakpoint 0, main.bfm:9\npc 4: Left, pointer 1 = 2\n  #0 at main.bfm:9:5\n"
+    );
+    assert_eq!(responses[2], "The program has finished\n");
+}
+
+#[test]
+fn break_on_a_pc() {
+    let responses = responses("break pc 3\ncontinue\ninfo\n");
+    assert_eq!(responses[0], "Breakpoint 0 at pc 3\n");
+    assert_eq!(
+        responses[1],
+        "Breakpoint 0, pc 3\n\
+        pc 3: Add, pointer 1 = 1\n  \
+        #0 at main.bfm:4:7\n  \
+        #1 add_two(...) at main.bfm:8:5\n"
+    );
+    assert_eq!(responses[2], "Breakpoint 0 at pc 3\nNo watches\n");
+}
+
+#[test]
+fn step_runs_a_number_of_instructions() {
+    let responses = responses("step\nstep 2\nstep 100\n");
+    assert_eq!(
+        responses[0],
+        "pc 1: Right, pointer 0 = 1\n  #0 at main.bfm:3:5\n  #1 add_two(...) at main.bfm:8:5\n"
+    );
+    assert_eq!(
+        responses[1],
+        "pc 3: Add, pointer 1 = 1\n  #0 at main.bfm:4:7\n  #1 add_two(...) at main.bfm:8:5\n"
+    );
+    assert_eq!(responses[2], "The program has finished\n");
+}
+
+#[test]
+fn next_steps_over_macros() {
+    // All of `add_two(b)` on line 8 is generated inside the macro, so it runs in one go.
+    let responses = responses("next\nprint 1\nnext\n");
+    assert_eq!(
+        responses[0],
+        "pc 4: Left, pointer 1 = 2\n  #0 at main.bfm:9:5\n"
+    );
+    assert_eq!(responses[1], "1 (cell 1) = 2 '\\u{2}'\n");
+    assert_eq!(responses[2], "The program has finished\n");
+}
+
+#[test]
+fn watch_stops_when_the_cell_changes() {
+    let responses = responses("frame Main\nwatch b\ncontinue\ncontinue\ncontinue\n");
+    assert_eq!(responses[1], "Watching b (cell 1) = 0\n");
+    assert_eq!(
+        responses[2],
+        "Watch b: 0 -> 1\n\
+        pc 3: Add, pointer 1 = 1\n  \
+        #0 at main.bfm:4:7\n  \
+        #1 add_two(...) at main.bfm:8:5\n"
+    );
+    assert!(responses[3].starts_with("Watch b: 1 -> 2\n"));
+    assert_eq!(responses[4], "The program has finished\n");
+}
+
+#[test]
+fn slots_are_named_by_the_selected_frame() {
+    let responses = responses("print b\nframe\nframe Main\nstep 4\nprint b\nprint 0\n");
+    assert_eq!(
+        responses[0],
+        "error: 'b' is not a cell, select a frame to use slot names\n"
+    );
+    assert_eq!(responses[1], "No frame selected\n");
+    assert_eq!(
+        responses[2],
+        "Frame Main at cell 0\n>     0    0  a\n      1    0  b\n"
+    );
+    assert_eq!(responses[4], "b (cell 1) = 2 '\\u{2}'\n");
+    assert_eq!(responses[5], "0 (cell 0) = 1 '\\u{1}'\n");
+}
+
+#[test]
+fn continue_stops_at_break_instructions() {
+    let program = BF::parse("+*+.".as_bytes()).unwrap();
+    let mut debugger = Debugger::new(&program, Config::default(), None, []);
+    let mut output = Vec::new();
+    debugger
+        .run("continue\ncontinue\n".as_bytes(), &mut output)
+        .unwrap();
+
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "pc 0: Add, pointer 0 = 0\n\
+        (bfdb) Stopped at '*'\n\
+        pc 2: Add, pointer 0 = 1\n\
+        (bfdb) The program has finished\n\
+        (bfdb) "
+    );
+}
+
+#[test]
+fn bad_commands_are_reported() {
+    let script = "\
+        foo\n\
+        break x\n\
+        break other.bfm:3\n\
+        break pc x\n\
+        delete 0\n\
+        step x\n\
+        memory x\n\
+        frame Other\n\
+        frame Main x\n\
+        frame Main\n\
+        print c\n\
+        unwatch c\n";
+
+    assert_eq!(
+        responses(script),
+        [
+            "error: unknown command 'foo', try 'help'\n",
+            "error: invalid line 'x'\n",
+            "error: no file 'other.bfm' in the source map\n",
+            "error: invalid pc 'x'\n",
+            "error: no breakpoint 0\n",
+            "error: invalid count 'x'\n",
+            "error: invalid radius 'x'\n",
+            "error: no frame 'Other'\n",
+            "error: invalid base 'x'\n",
+            "Frame Main at cell 0\n>     0    0  a\n      1    0  b\n",
+            "error: no slot 'c' in frame 'Main'\n",
+            "error: no slot 'c' in frame 'Main'\n",
+            "",
+        ]
+    );
+}
+
+#[test]
+fn break_on_a_line_needs_a_source_map() {
+    let program = BF::parse("+".as_bytes()).unwrap();
+    let mut debugger = Debugger::new(&program, Config::default(), None, []);
+    let mut output = Vec::new();
+    debugger.run("break 1\n".as_bytes(), &mut output).unwrap();
+
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("error: no source map loaded, use 'break pc <n>'\n"));
+}
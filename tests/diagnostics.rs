@@ -0,0 +1,192 @@
+use bfmacro::compiler::{Compiler, Diagnostic, ErrorCode, Files};
+use std::io;
+use std::path::Path;
+
+/// Compile `source`, returning its diagnostics and the compiler holding the files they point into.
+fn compile(source: &str) -> (Vec<Diagnostic>, Compiler) {
+    let mut compiler = Compiler::new();
+    let diagnostics = compiler
+        .compile_source(Path::new("test.bfm"), source)
+        .err()
+        .unwrap();
+    (diagnostics, compiler)
+}
+
+fn render(diagnostic: &Diagnostic, files: &Files) -> String {
+    let mut output = Vec::new();
+    diagnostic.render(files, &mut output).unwrap();
+    String::from_utf8(output).unwrap()
+}
+
+/// A diagnostic with a label over several lines, a label further down the same file and a label
+/// in another file.
+fn spread_out() -> (Diagnostic, Files) {
+    let mut files = Files::default();
+    let main = files.add(
+        Path::new("main.bfm"),
+        "using Main {\n    a [\n        b\n    ]\n}\n\n\n\nmacro m(x) { x }\n".to_owned(),
+    );
+    let lib = files.add(Path::new("lib.bfm"), "frame Main { a b }\n".to_owned());
+
+    let diagnostic = Diagnostic::error(
+        ErrorCode::LoopPointerMismatch,
+        "Loop \"moves\"\tthe pointer",
+    )
+    .with_primary(main, (19, 36), "this loop")
+    .with_label(main, (48, 49), "")
+    .with_label(lib, (6, 10), "frame defined here")
+    .with_note("first note")
+    .with_note("second note");
+    (diagnostic, files)
+}
+
+#[test]
+fn labels_are_underlined_below_their_line() {
+    let (diagnostics, compiler) = compile(
+        "\
+frame Main { a }
+macro f(x) { x + }
+macro f(x) { x - }
+using Main { f(a) }
+",
+    );
+
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(
+        render(&diagnostics[0], compiler.files()),
+        "\
+error[E0105]: Multiple definitions of macro 'f'
+ --> test.bfm:3:7
+  |
+2 | macro f(x) { x + }
+  |       - previous definition here
+3 | macro f(x) { x - }
+  |       ^ redefined here
+
+"
+    );
+}
+
+#[test]
+fn notes_follow_the_snippet() {
+    let (diagnostics, compiler) = compile(
+        "\
+frame Main { a }
+macro g(x) { x + g(x) }
+macro f(x) { g(x) }
+using Main { f(a) }
+",
+    );
+
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(
+        render(&diagnostics[0], compiler.files()),
+        "\
+error[E0112]: Macro expansion is nested deeper than 128 levels
+ --> test.bfm:2:18
+  |
+2 | macro g(x) { x + g(x) }
+  |                  ^ while expanding this
+  |
+  = note: Expanded through 'f' -> 'g' (127 times)
+
+"
+    );
+}
+
+#[test]
+fn labels_are_grouped_by_file() {
+    let (diagnostic, files) = spread_out();
+    assert_eq!(
+        render(&diagnostic, &files),
+        "\
+error[E0201]: Loop \"moves\"\tthe pointer
+ --> main.bfm:2:7
+  |
+2 |     a [
+  |       ^
+3 |         b
+  |         ^
+4 |     ]
+  |     ^ this loop
+...
+9 | macro m(x) { x }
+  |       -
+ ::: lib.bfm:1:7
+  |
+1 | frame Main { a b }
+  |       ---- frame defined here
+  |
+  = note: first note
+  = note: second note
+
+"
+    );
+}
+
+#[test]
+fn diagnostics_without_labels_are_a_single_line() {
+    let error = io::Error::new(io::ErrorKind::NotFound, "no such file");
+    let diagnostic = Diagnostic::io(Path::new("gone.bfm"), error);
+    assert_eq!(
+        render(&diagnostic, &Files::default()),
+        "error[E0001]: Could not read 'gone.bfm': no such file\n\n"
+    );
+}
+
+#[test]
+fn json_has_every_label_with_its_location() {
+    let (diagnostic, files) = spread_out();
+    assert_eq!(
+        diagnostic.to_json(&files),
+        concat!(
+            r#"{"severity":"error","code":"E0201","message":"Loop \"moves\"\tthe pointer","#,
+            r#""primary":{"file":"main.bfm","byte_start":19,"byte_end":36,"#,
+            r#""line_start":2,"column_start":7,"line_end":4,"column_end":6,"label":"this loop"},"#,
+            r#""secondary":[{"file":"main.bfm","byte_start":48,"byte_end":49,"#,
+            r#""line_start":9,"column_start":7,"line_end":9,"column_end":8,"label":""},"#,
+            r#"{"file":"lib.bfm","byte_start":6,"byte_end":10,"#,
+            r#""line_start":1,"column_start":7,"line_end":1,"column_end":11,"#,
+            r#""label":"frame defined here"}],"#,
+            r#""notes":["first note","second note"]}"#,
+        )
+    );
+}
+
+#[test]
+fn json_of_a_compile_error() {
+    let (diagnostics, compiler) = compile("frame Main { a }\nusing Main { b + }\n");
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(
+        diagnostics[0].to_json(compiler.files()),
+        concat!(
+            r#"{"severity":"error","code":"E0100","#,
+            r#""message":"No symbol with the name 'b' found in frame 'Main'","#,
+            r#""primary":{"file":"test.bfm","byte_start":30,"byte_end":31,"#,
+            r#""line_start":2,"column_start":14,"line_end":2,"column_end":15,"label":""},"#,
+            r#""secondary":[],"notes":[]}"#,
+        )
+    );
+}
+
+#[test]
+fn json_without_labels_has_a_null_primary() {
+    let error = io::Error::new(io::ErrorKind::NotFound, "no such file");
+    let diagnostic = Diagnostic::io(Path::new("gone.bfm"), error);
+    assert_eq!(
+        diagnostic.to_json(&Files::default()),
+        concat!(
+            r#"{"severity":"error","code":"E0001","#,
+            r#""message":"Could not read 'gone.bfm': no such file","#,
+            r#""primary":null,"secondary":[],"notes":[]}"#,
+        )
+    );
+}
+
+#[test]
+fn json_escapes_control_characters() {
+    let diagnostic = Diagnostic::error(ErrorCode::Syntax, "a\\b\nc\u{1}");
+    assert!(diagnostic
+        .to_json(&Files::default())
+        .contains(r#""message":"a\\b\nc\u0001""#));
+}
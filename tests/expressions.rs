@@ -0,0 +1,266 @@
+use bfmacro::compiler::{Compiler, ErrorCode, Options};
+use std::path::Path;
+
+/// Compile `source` without simplifying, returning the code, or the code and source text under
+/// the primary label of each error.
+fn compile(source: &str) -> Result<String, Vec<(ErrorCode, &str)>> {
+    let mut compiler = Compiler::new().with_options(Options {
+        simplify: false,
+        ..Options::default()
+    });
+    match compiler.compile_source(Path::new("test.bfm"), source) {
+        Ok(program) => {
+            let mut output = Vec::new();
+            program.write(&mut output).unwrap();
+            Ok(String::from_utf8(output).unwrap().lines().collect())
+        }
+        Err(diagnostics) => Err(diagnostics
+            .iter()
+            .map(|diagnostic| {
+                let span = diagnostic.primary.as_ref().unwrap().span;
+                (diagnostic.code.unwrap(), &source[span.0..span.1])
+            })
+            .collect()),
+    }
+}
+
+#[test]
+fn counts_repeat_plus_and_minus() {
+    assert_eq!(
+        compile("frame Main { a b } using Main { a +3 b -2 }"),
+        Ok("+++>--".to_owned())
+    );
+    assert_eq!(
+        compile("frame Main { a } using Main { a +'A' }"),
+        Ok("+".repeat(65))
+    );
+    assert_eq!(
+        compile("frame Main { a } using Main { a +'\\n' -'\\t' }"),
+        Ok("+".repeat(10) + "-".repeat(9).as_str())
+    );
+}
+
+#[test]
+fn negative_counts_go_the_other_way() {
+    assert_eq!(
+        compile("frame Main { a } using Main { a -(2 - 5) +(1 - 3) }"),
+        Ok("+++--".to_owned())
+    );
+}
+
+#[test]
+fn counts_are_limited() {
+    let code = compile("frame Main { a } using Main { a +65535 -65535 }").unwrap();
+    assert_eq!(code, "+".repeat(65535) + "-".repeat(65535).as_str());
+
+    assert_eq!(
+        compile("frame Main { a } using Main { a +65536 }"),
+        Err(vec![(ErrorCode::CountOutOfRange, "65536")])
+    );
+    assert_eq!(
+        compile("frame Main { a } using Main { a -(0 - 65536) }"),
+        Err(vec![(ErrorCode::CountOutOfRange, "0 - 65536")])
+    );
+}
+
+#[test]
+fn integer_literals_too_large_are_reported_once() {
+    assert_eq!(
+        compile("frame Main { a } using Main { a +99999999999999999999 }"),
+        Err(vec![(ErrorCode::Syntax, "99999999999999999999")])
+    );
+    assert_eq!(
+        compile("frame Main { a } using Main { a +9223372036854775808 }"),
+        Err(vec![(ErrorCode::Syntax, "9223372036854775808")])
+    );
+}
+
+#[test]
+fn constants_are_evaluated_in_any_order() {
+    let source = "
+        const SUM = HALF * 2 + REST;
+        const HALF = (7 - 1) / 2 % 4;
+        const REST = '0' - '/';
+        frame Main { a }
+        using Main { a +(SUM) }
+    ";
+    assert_eq!(compile(source), Ok("+".repeat(7)));
+
+    let source = "
+        const NEGATIVE = -(2 * 3);
+        frame Main { a }
+        using Main { a +(NEGATIVE) }
+    ";
+    assert_eq!(compile(source), Ok("-".repeat(6)));
+}
+
+#[test]
+fn constants_depending_on_themselves_are_reported() {
+    let source = "
+        const A = B + 1;
+        const B = C * 2;
+        const C = A;
+        frame Main { a }
+        using Main { a +(A) }
+    ";
+    let diagnostics = Compiler::new()
+        .compile_source(Path::new("test.bfm"), source)
+        .err()
+        .unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].code, Some(ErrorCode::RecursiveConstant));
+    assert_eq!(
+        diagnostics[0].message,
+        "Constant 'A' depends on itself: A -> B -> C -> A"
+    );
+
+    assert_eq!(
+        compile("const A = A; frame Main { a } using Main { a +3 }"),
+        Err(vec![(ErrorCode::RecursiveConstant, "A")])
+    );
+}
+
+#[test]
+fn errors_in_constant_expressions_are_reported() {
+    assert_eq!(
+        compile("const A = B; frame Main { a } using Main { a +3 }"),
+        Err(vec![(ErrorCode::UnknownConstant, "B")])
+    );
+    assert_eq!(
+        compile("const A = 1 / (2 - 2); frame Main { a } using Main { a +3 }"),
+        Err(vec![(ErrorCode::Arithmetic, "2 - 2")])
+    );
+    assert_eq!(
+        compile("const A = 9223372036854775807 + 1; frame Main { a } using Main { a +3 }"),
+        Err(vec![(ErrorCode::Arithmetic, "9223372036854775807 + 1")])
+    );
+    assert_eq!(
+        compile("frame Main { a } using Main { a +(UNKNOWN) }"),
+        Err(vec![(ErrorCode::UnknownConstant, "UNKNOWN")])
+    );
+}
+
+#[test]
+fn constants_with_errors_are_only_reported_where_defined() {
+    let source = "
+        const A = 1 % 0;
+        const B = A + 1;
+        frame Main { a }
+        using Main { a +(A) +(B) }
+    ";
+    assert_eq!(compile(source), Err(vec![(ErrorCode::Arithmetic, "0")]));
+}
+
+#[test]
+fn repeat_counts_must_be_known_at_compile_time() {
+    assert_eq!(
+        compile("const N = 3; frame Main { a } using Main { repeat N { a + } }"),
+        Ok("+++".to_owned())
+    );
+    assert_eq!(
+        compile("frame Main { a } using Main { repeat N { a + } }"),
+        Err(vec![(ErrorCode::NotConstant, "repeat N")])
+    );
+    assert_eq!(
+        compile("frame Main { a } using Main { repeat (0 - 1) { a + } }"),
+        Err(vec![(ErrorCode::CountOutOfRange, "0 - 1")])
+    );
+}
+
+#[test]
+fn integer_parameters_take_numbers_and_expressions() {
+    let source = "
+        const THREE = 3;
+        macro add(x, n: int) { x +(n) }
+        frame Main { a b c }
+        using Main {
+            add(a, 2)
+            add(b, THREE)
+            add(c, ('A' - 60))
+        }
+    ";
+    assert_eq!(compile(source), Ok("++>+++>+++++".to_owned()));
+}
+
+#[test]
+fn integer_parameters_are_passed_on_and_counted_down() {
+    let source = "
+        macro add(x, n: int) { x +(n) }
+        macro repeat_add(x, times: int, n: int) {
+            if times > 0 {
+                add(x, n)
+                repeat_add(x, (times - 1), n)
+            }
+        }
+        frame Main { a }
+        using Main { repeat_add(a, 3, 2) }
+    ";
+    assert_eq!(compile(source), Ok("+".repeat(6)));
+
+    let source = "
+        macro fill(x, n: int) { repeat n { x - } }
+        frame Main { a }
+        using Main { fill(a, '\\t') }
+    ";
+    assert_eq!(compile(source), Ok("-".repeat(9)));
+}
+
+#[test]
+fn cells_can_not_be_passed_as_integers() {
+    let source = |argument: &str| {
+        format!(
+            "
+            macro add(x, n: int) {{ x +(n) }}
+            frame Inner {{ b }}
+            frame Main {{ a inner: Inner }}
+            using Main {{ add(a, {argument}) }}
+            "
+        )
+    };
+
+    for argument in ["a", "inner.b", "{ a + }", "\"text\""] {
+        let source = source(argument);
+        assert_eq!(
+            compile(&source),
+            Err(vec![(ErrorCode::ArgumentType, argument)]),
+            "{argument}"
+        );
+    }
+}
+
+#[test]
+fn integers_can_not_be_passed_as_cells() {
+    let source = "
+        macro clear(x) { x [-] }
+        frame Main { a }
+        using Main { clear(3) }
+    ";
+    assert_eq!(compile(source), Err(vec![(ErrorCode::ArgumentType, "3")]));
+
+    let source = "
+        macro run(body: block) { body }
+        frame Main { a }
+        using Main { run(3) }
+    ";
+    assert_eq!(compile(source), Err(vec![(ErrorCode::ArgumentType, "3")]));
+}
+
+#[test]
+fn integer_parameters_and_cells_are_not_interchangeable_in_the_body() {
+    let source = "
+        macro add(x, n: int) { n + }
+        frame Main { a }
+        using Main { add(a, 3) }
+    ";
+    assert_eq!(compile(source), Err(vec![(ErrorCode::ExpectedSlot, "n")]));
+
+    let source = "
+        macro add(x, n: int) { x +(x) }
+        frame Main { a }
+        using Main { add(a, 3) }
+    ";
+    assert_eq!(
+        compile(source),
+        Err(vec![(ErrorCode::ExpectedInteger, "x")])
+    );
+}
@@ -0,0 +1,49 @@
+/* Generated by bfmacro. */
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+#define TAPE_LENGTH 1048576
+
+typedef uint8_t cell;
+
+static cell tape[TAPE_LENGTH];
+
+static size_t check(size_t pointer)
+{
+    if (pointer >= TAPE_LENGTH) {
+        fputs("error: pointer moved outside the tape\n", stderr);
+        exit(1);
+    }
+    return pointer;
+}
+
+static cell input(cell current)
+{
+    int c;
+    (void)current;
+    fflush(stdout);
+    c = getchar();
+    return c == EOF ? 0 : (cell)c;
+}
+
+int main(void)
+{
+    size_t p = 0;
+
+    tape[p] += 3;
+    if (tape[p]) tape[check(p + 1)] += (cell)(tape[p] * UINT64_C(2));
+    tape[p] = 0;
+    p = check(p + 1);
+    if (tape[p]) tape[check(p + 1)] += (cell)(tape[p] * UINT64_C(1));
+    tape[p] = 0;
+    tape[p] = input(tape[p]);
+    putchar(tape[p]);
+    tape[p] = 0;
+    p = check(p + 1);
+    while (tape[p]) p = check(p + 1);
+    p = check(p - 1);
+    putchar(tape[p]);
+
+    return 0;
+}
@@ -0,0 +1,61 @@
+// Generated by bfmacro.
+use std::io::{self, Read, Write};
+
+const TAPE_LENGTH: usize = 1048576;
+
+type Cell = u8;
+
+fn check(pointer: Option<usize>) -> io::Result<usize> {
+    pointer
+        .filter(|pointer| *pointer < TAPE_LENGTH)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "pointer moved outside the tape"))
+}
+
+#[allow(unused_variables)]
+fn read_cell(input: &mut impl Read, output: &mut impl Write, current: Cell) -> io::Result<Cell> {
+    output.flush()?;
+    let mut byte = [0];
+    loop {
+        match input.read(&mut byte) {
+            Ok(0) => return Ok(0),
+            Ok(_) => return Ok(Cell::from(byte[0])),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Run the program, reading `,` from `input` and writing `.` to `output`.
+#[allow(unused_mut, unused_variables)]
+pub fn run(mut input: impl Read, mut output: impl Write) -> io::Result<()> {
+    let mut tape: Vec<Cell> = vec![0; TAPE_LENGTH];
+    let mut p: usize = 0;
+
+    tape[p] = tape[p].wrapping_add(3);
+    if tape[p] != 0 {
+        let target = check(p.checked_add(1))?;
+        tape[target] = tape[target].wrapping_add(tape[p].wrapping_mul(2));
+    }
+    tape[p] = 0;
+    p = check(p.checked_add(1))?;
+    if tape[p] != 0 {
+        let target = check(p.checked_add(1))?;
+        tape[target] = tape[target].wrapping_add(tape[p].wrapping_mul(1));
+    }
+    tape[p] = 0;
+    tape[p] = read_cell(&mut input, &mut output, tape[p])?;
+    output.write_all(&[tape[p] as u8])?;
+    tape[p] = 0;
+    p = check(p.checked_add(1))?;
+    while tape[p] != 0 {
+        p = check(p.checked_add(1))?;
+    }
+    p = check(p.checked_sub(1))?;
+    output.write_all(&[tape[p] as u8])?;
+
+    output.flush()
+}
+
+fn main() -> io::Result<()> {
+    run(io::stdin().lock(), io::stdout().lock())
+}
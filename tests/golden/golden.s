@@ -0,0 +1,156 @@
+# Generated by bfmacro.
+# Build with: as program.s -o program.o && ld program.o -o program
+# %rbx is the pointer, %r12 the tape and %r13 the number of buffered output bytes.
+
+    .set TAPE_LENGTH, 1048576
+    .set OUTPUT_BUFFER_SIZE, 4096
+
+    .bss
+    .balign 8
+tape:
+    .zero TAPE_LENGTH * 1
+output_buffer:
+    .zero OUTPUT_BUFFER_SIZE
+input_byte:
+    .zero 1
+
+    .section .rodata
+pointer_message:
+    .ascii "error: pointer moved outside the tape\n"
+    .set POINTER_MESSAGE_LENGTH, . - pointer_message
+io_message:
+    .ascii "error: failed to read or write\n"
+    .set IO_MESSAGE_LENGTH, . - io_message
+
+    .text
+    .globl _start
+_start:
+    xorl %ebx, %ebx
+    leaq tape(%rip), %r12
+    xorl %r13d, %r13d
+
+    addb $3, (%r12,%rbx,1)
+    movzbl (%r12,%rbx,1), %eax
+    testl %eax, %eax
+    jz .Lmultiply_end_1
+    leaq 1(%rbx), %rcx
+    cmpq $TAPE_LENGTH, %rcx
+    jae pointer_error
+    imulq $2, %rax
+    addb %al, (%r12,%rcx,1)
+.Lmultiply_end_1:
+    movb $0, (%r12,%rbx,1)
+    addq $1, %rbx
+    cmpq $TAPE_LENGTH, %rbx
+    jae pointer_error
+    movzbl (%r12,%rbx,1), %eax
+    testl %eax, %eax
+    jz .Lmultiply_end_4
+    leaq 1(%rbx), %rcx
+    cmpq $TAPE_LENGTH, %rcx
+    jae pointer_error
+    imulq $1, %rax
+    addb %al, (%r12,%rcx,1)
+.Lmultiply_end_4:
+    movb $0, (%r12,%rbx,1)
+    call input
+    testl %eax, %eax
+    jns .Linput_store_6
+    xorl %eax, %eax
+.Linput_store_6:
+    movb %al, (%r12,%rbx,1)
+    movb (%r12,%rbx,1), %dil
+    call output
+    movb $0, (%r12,%rbx,1)
+    addq $1, %rbx
+    cmpq $TAPE_LENGTH, %rbx
+    jae pointer_error
+.Lscan_10:
+    cmpb $0, (%r12,%rbx,1)
+    je .Lscan_end_10
+    addq $1, %rbx
+    cmpq $TAPE_LENGTH, %rbx
+    jae pointer_error
+    jmp .Lscan_10
+.Lscan_end_10:
+    addq $-1, %rbx
+    cmpq $TAPE_LENGTH, %rbx
+    jae pointer_error
+    movb (%r12,%rbx,1), %dil
+    call output
+
+    call flush
+    xorl %edi, %edi
+    jmp exit
+
+# Reads a byte into %eax, which is -1 at the end of input.
+input:
+    call flush
+1:
+    xorl %eax, %eax
+    xorl %edi, %edi
+    leaq input_byte(%rip), %rsi
+    movl $1, %edx
+    syscall
+    cmpq $-4, %rax
+    je 1b
+    testq %rax, %rax
+    js io_error
+    jz 2f
+    movzbl input_byte(%rip), %eax
+    ret
+2:
+    movl $-1, %eax
+    ret
+
+# Buffers the byte in %dil.
+output:
+    leaq output_buffer(%rip), %rax
+    movb %dil, (%rax,%r13)
+    incq %r13
+    cmpq $OUTPUT_BUFFER_SIZE, %r13
+    je flush
+    ret
+
+flush:
+    leaq output_buffer(%rip), %rsi
+1:
+    testq %r13, %r13
+    jz 2f
+    movl $1, %eax
+    movl $1, %edi
+    movq %r13, %rdx
+    syscall
+    cmpq $-4, %rax
+    je 1b
+    testq %rax, %rax
+    js io_error
+    addq %rax, %rsi
+    subq %rax, %r13
+    jmp 1b
+2:
+    ret
+
+pointer_error:
+    call flush
+    leaq pointer_message(%rip), %rsi
+    movl $POINTER_MESSAGE_LENGTH, %edx
+    jmp fail
+
+io_error:
+    xorl %r13d, %r13d
+    leaq io_message(%rip), %rsi
+    movl $IO_MESSAGE_LENGTH, %edx
+
+# Writes %rdx bytes at %rsi to stderr, and exits with 1.
+fail:
+    movl $1, %eax
+    movl $2, %edi
+    syscall
+    movl $1, %edi
+
+exit:
+    movl $60, %eax
+    syscall
+
+    .section .note.GNU-stack, "", @progbits
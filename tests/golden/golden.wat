@@ -0,0 +1,126 @@
+;; Generated by bfmacro.
+(module
+  (import "env" "getchar" (func $getchar (result i32)))
+  (import "env" "putchar" (func $putchar (param i32)))
+  (memory (export "memory") 16)
+
+  (func $check (param $address i32) (result i32)
+    local.get $address
+    i32.const 1048576
+    i32.ge_u
+    if
+      unreachable
+    end
+    local.get $address
+  )
+
+  (func $input (param $current i32) (result i32)
+    (local $byte i32)
+    call $getchar
+    local.tee $byte
+    i32.const 0
+    i32.lt_s
+    if (result i32)
+      i32.const 0
+    else
+      local.get $byte
+    end
+  )
+
+  (func $run (export "run")
+    (local $p i32)
+    (local $target i32)
+    (local $value i32)
+    local.get $p
+    local.get $p
+    i32.load8_u
+    i32.const 3
+    i32.add
+    i32.store8
+    local.get $p
+    i32.load8_u
+    local.tee $value
+    if
+      local.get $p
+      i32.const 1
+      i32.add
+      call $check
+      local.set $target
+      local.get $target
+      local.get $target
+      i32.load8_u
+      local.get $value
+      i32.const 2
+      i32.mul
+      i32.add
+      i32.store8
+    end
+    local.get $p
+    i32.const 0
+    i32.store8
+    local.get $p
+    i32.const 1
+    i32.add
+    call $check
+    local.set $p
+    local.get $p
+    i32.load8_u
+    local.tee $value
+    if
+      local.get $p
+      i32.const 1
+      i32.add
+      call $check
+      local.set $target
+      local.get $target
+      local.get $target
+      i32.load8_u
+      local.get $value
+      i32.const 1
+      i32.mul
+      i32.add
+      i32.store8
+    end
+    local.get $p
+    i32.const 0
+    i32.store8
+    local.get $p
+    local.get $p
+    i32.load8_u
+    call $input
+    i32.store8
+    local.get $p
+    i32.load8_u
+    call $putchar
+    local.get $p
+    i32.const 0
+    i32.store8
+    local.get $p
+    i32.const 1
+    i32.add
+    call $check
+    local.set $p
+    block
+      loop
+        local.get $p
+        i32.load8_u
+        i32.eqz
+        br_if 1
+        local.get $p
+        i32.const 1
+        i32.add
+        call $check
+        local.set $p
+        br 0
+      end
+    end
+    local.get $p
+    i32.const -1
+    i32.add
+    call $check
+    local.set $p
+    local.get $p
+    i32.load8_u
+    call $putchar
+  )
+)
@@ -0,0 +1,107 @@
+use bfmacro::compiler::{Compiler, Diagnostic, ErrorCode};
+use std::path::Path;
+
+fn parse_errors(source: &str) -> Vec<Diagnostic> {
+    let mut compiler = Compiler::new();
+    compiler
+        .compile_source(Path::new("test.bfm"), source)
+        .err()
+        .unwrap()
+}
+
+/// The code and the source text under the primary label of each diagnostic.
+fn locations<'a>(source: &'a str, diagnostics: &[Diagnostic]) -> Vec<(ErrorCode, &'a str)> {
+    diagnostics
+        .iter()
+        .map(|diagnostic| {
+            let span = diagnostic.primary.as_ref().unwrap().span;
+            (diagnostic.code.unwrap(), &source[span.0..span.1])
+        })
+        .collect()
+}
+
+#[test]
+fn errors_in_separate_blocks_are_both_reported() {
+    let source = "\
+frame Main { a }
+macro f(x) { x + ; }
+macro g(x) { x - = }
+using Main { f(a) g(a) }
+";
+
+    let diagnostics = parse_errors(source);
+    assert_eq!(
+        locations(source, &diagnostics),
+        [(ErrorCode::Syntax, ";"), (ErrorCode::Syntax, "=")]
+    );
+    let semicolon = source.find(';').unwrap();
+    let equals = source.find('=').unwrap();
+    assert_eq!(
+        diagnostics[0].primary.as_ref().unwrap().span,
+        (semicolon, semicolon + 1)
+    );
+    assert_eq!(
+        diagnostics[1].primary.as_ref().unwrap().span,
+        (equals, equals + 1)
+    );
+    assert_eq!(diagnostics[0].message, "Unexpected token ';'");
+}
+
+#[test]
+fn parsing_continues_after_a_bad_definition() {
+    let source = "\
+frame Main { a }
+macro f(x) { x + ) x }
+using Main { f(a) }
+macro g(x { x }
+";
+
+    let diagnostics = parse_errors(source);
+    assert_eq!(
+        locations(source, &diagnostics),
+        [(ErrorCode::Syntax, ")"), (ErrorCode::Syntax, "{")]
+    );
+    assert_eq!(diagnostics[1].notes, ["expected one of ')', ',', or ':'"]);
+}
+
+#[test]
+fn recovering_adds_no_errors_of_its_own() {
+    // Everything after the unclosed frame is skipped rather than reported again.
+    let source = "\
+frame Main { a
+using Main { a + }
+macro f(x) { x }
+";
+
+    let diagnostics = parse_errors(source);
+    assert_eq!(
+        locations(source, &diagnostics),
+        [(ErrorCode::Syntax, "using")]
+    );
+    assert_eq!(
+        diagnostics[0].notes,
+        ["expected one of ':', '}', or identifier"]
+    );
+}
+
+#[test]
+fn syntax_errors_stop_before_checking_names() {
+    let source = "\
+frame Main { a }
+using Main { unknown(b) ; }
+";
+
+    let diagnostics = parse_errors(source);
+    assert_eq!(locations(source, &diagnostics), [(ErrorCode::Syntax, ";")]);
+}
+
+#[test]
+fn end_of_file_is_reported_after_the_last_token() {
+    let source = "frame Main { a }\nusing Main { a +\n";
+
+    let diagnostics = parse_errors(source);
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].message, "Unexpected end of file");
+    let end = source.trim_end().len();
+    assert_eq!(diagnostics[0].primary.as_ref().unwrap().span, (end, end));
+}
@@ -0,0 +1,107 @@
+use bfmacro::bf::BF;
+use bfmacro::compiler::{Compiler, Options};
+use bfmacro::source_map::SourceMap;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+const SOURCE: &str = "\
+frame Main { a b }
+macro add_two(x) {
+    x ++
+}
+using Main {
+    a +
+    add_two(b)
+}
+";
+
+/// Compile `SOURCE`, then write its code and source map out and read them back.
+fn round_trip() -> (BF, SourceMap) {
+    let mut compiler = Compiler::new().with_options(Options {
+        simplify: false,
+        ..Options::default()
+    });
+    let program = compiler
+        .compile_source(Path::new("main.bfm"), SOURCE)
+        .ok()
+        .unwrap();
+
+    let mut code = Vec::new();
+    program.write(&mut code).unwrap();
+    let mut source_map = Vec::new();
+    compiler
+        .source_map()
+        .write(&program, &mut source_map)
+        .unwrap();
+
+    let mut program = BF::parse(code.as_slice()).unwrap();
+    let source_map = SourceMap::read(&mut program, source_map.as_slice()).unwrap();
+    (program, source_map)
+}
+
+/// File, line and macro name of each origin the instruction at `pc` was expanded through.
+fn stack(program: &BF, source_map: &SourceMap, pc: usize) -> Vec<(PathBuf, usize, Option<String>)> {
+    let origin = program.origin(pc).unwrap();
+    source_map
+        .stack(origin)
+        .into_iter()
+        .map(|origin| {
+            let file = source_map.files[origin.file].clone();
+            (file, origin.line, origin.name.clone())
+        })
+        .collect()
+}
+
+#[test]
+fn origins_survive_writing_and_reading() {
+    let (program, source_map) = round_trip();
+    assert_eq!(source_map.files, [PathBuf::from("main.bfm")]);
+    assert_eq!(program.len(), 4);
+
+    let main = || PathBuf::from("main.bfm");
+    assert_eq!(stack(&program, &source_map, 0), [(main(), 6, None)]);
+    for pc in 1..4 {
+        assert_eq!(
+            stack(&program, &source_map, pc),
+            [(main(), 3, None), (main(), 7, Some("add_two".to_owned())),]
+        );
+    }
+
+    let origin = source_map.origin(program.origin(3).unwrap());
+    assert_eq!(&SOURCE[origin.span.0..origin.span.1], "+");
+    assert_eq!(source_map.describe(origin), "main.bfm:3:8");
+}
+
+fn read(source_map: &str) -> std::io::Result<SourceMap> {
+    let mut program = BF::parse("+>+".as_bytes()).unwrap();
+    SourceMap::read(&mut program, source_map.as_bytes())
+}
+
+#[test]
+fn indices_out_of_range_are_rejected() {
+    let header = "bfmacro-source-map 1\nfile 0 main.bfm\norigin 0 0 0 1 1 1 - -\n";
+    assert!(read(&format!("{header}map 0 3 0\n")).is_ok());
+
+    for line in [
+        "file 2 other.bfm",
+        "origin 2 0 0 1 1 1 - -",
+        "origin 1 1 0 1 1 1 - -",
+        "origin 1 0 0 1 1 1 1 -",
+        "map 0 4 0",
+        "map 2 1 0",
+        "map 0 1 1",
+    ] {
+        let err = read(&format!("{header}{line}\n")).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData, "{line}");
+        assert!(err.to_string().contains("Index out of range"), "{line}");
+    }
+}
+
+#[test]
+fn other_files_are_rejected() {
+    let err = read("not a source map\n").err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+    let err = read("bfmacro-source-map 1\nmap x 1 0\n").err().unwrap();
+    assert!(err.to_string().contains("Invalid source map line"));
+}